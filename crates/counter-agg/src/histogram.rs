@@ -0,0 +1,269 @@
+use std::cmp::Ordering;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{CounterError, CounterSummary};
+
+// A Prometheus-style histogram is a set of counters, one per bucket, where the
+// counter for a bucket tracks the cumulative number of observations less than
+// or equal to its upper bound (the `le` label). The last bucket must have an
+// upper bound of +Inf so that its counter is the total number of observations.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct HistogramBucket {
+    pub le: f64,
+    pub counter: CounterSummary,
+}
+
+// buckets are kept sorted by `le` with at most one bucket per `le`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct CounterHistogram {
+    pub buckets: Vec<HistogramBucket>,
+}
+
+fn cmp_le(a: f64, b: f64) -> Ordering {
+    a.partial_cmp(&b).expect("histogram bucket bounds cannot be NaN")
+}
+
+impl CounterHistogram {
+    pub fn new() -> CounterHistogram {
+        CounterHistogram { buckets: vec![] }
+    }
+
+    // Builds a histogram out of bucket summaries in any order, summaries for
+    // the same `le` are combined in time order so they must cover disjoint
+    // time ranges.
+    pub fn from_buckets(
+        buckets: impl IntoIterator<Item = HistogramBucket>,
+    ) -> Result<CounterHistogram, CounterError> {
+        let mut buckets: Vec<_> = buckets.into_iter().collect();
+        buckets.sort_by(|a, b| {
+            cmp_le(a.le, b.le).then_with(|| a.counter.first.ts.cmp(&b.counter.first.ts))
+        });
+        let mut combined: Vec<HistogramBucket> = Vec::with_capacity(buckets.len());
+        for bucket in buckets {
+            match combined.last_mut() {
+                Some(last) if last.le == bucket.le => last.counter.combine(&bucket.counter)?,
+                _ => combined.push(bucket),
+            }
+        }
+        Ok(CounterHistogram { buckets: combined })
+    }
+
+    pub fn combine(&mut self, incoming: &CounterHistogram) -> Result<(), CounterError> {
+        let buckets = self.buckets.drain(..).chain(incoming.buckets.iter().cloned());
+        *self = CounterHistogram::from_buckets(buckets.collect::<Vec<_>>())?;
+        Ok(())
+    }
+
+    // The increase in each bucket's cumulative count over the summarized
+    // period as (le, count) pairs. As in Prometheus, the counts are forced to
+    // be monotonic in `le`, as scrapes of the different buckets are not
+    // atomic and may disagree slightly.
+    pub fn cumulative_counts(&self) -> Vec<(f64, f64)> {
+        let mut max = 0.0f64;
+        self.buckets.iter()
+            .map(|b| {
+                max = max.max(b.counter.delta());
+                (b.le, max)
+            })
+            .collect()
+    }
+
+    pub fn has_inf_bucket(&self) -> bool {
+        matches!(self.buckets.last(), Some(b) if b.le == f64::INFINITY)
+    }
+
+    // Estimates the quantile in the same way as Prometheus' `histogram_quantile`:
+    // find the bucket containing the rank and interpolate linearly within it.
+    // The lower bound of the first bucket is taken to be 0 if its upper bound
+    // is positive, and ranks which fall in the +Inf bucket return the largest
+    // finite upper bound. Returns None if the histogram is unusable, ie if it
+    // does not have a +Inf bucket, has fewer than two buckets, or did not
+    // observe anything over the period.
+    pub fn quantile(&self, quantile: f64) -> Option<f64> {
+        if quantile.is_nan() {
+            return Some(f64::NAN);
+        }
+        if quantile < 0.0 {
+            return Some(f64::NEG_INFINITY);
+        }
+        if quantile > 1.0 {
+            return Some(f64::INFINITY);
+        }
+        if self.buckets.len() < 2 || !self.has_inf_bucket() {
+            return None;
+        }
+        let counts = self.cumulative_counts();
+        let total = counts.last().unwrap().1;
+        if total == 0.0 {
+            return None;
+        }
+
+        let rank = quantile * total;
+        let b = counts.iter().position(|&(_, count)| count >= rank).unwrap();
+        if b == counts.len() - 1 {
+            return Some(counts[b - 1].0);
+        }
+        if b == 0 && counts[0].0 <= 0.0 {
+            return Some(counts[0].0);
+        }
+
+        let (bucket_end, end_count) = counts[b];
+        let (bucket_start, start_count) = match b {
+            0 => (0.0, 0.0),
+            _ => counts[b - 1],
+        };
+        let count = end_count - start_count;
+        let rank = rank - start_count;
+        Some(bucket_start + (bucket_end - bucket_start) * (rank / count))
+    }
+
+    // The number of observations in each bucket (no longer cumulative) as
+    // (value, count) pairs, where the value is the midpoint of the bucket
+    // using the same bucket bounds as `quantile`. This is what we feed into
+    // sketches that want individual observations rather than buckets.
+    pub fn observations(&self) -> Vec<(f64, f64)> {
+        let counts = self.cumulative_counts();
+        let mut prev: Option<(f64, f64)> = None;
+        let mut observations = Vec::with_capacity(counts.len());
+        for &(le, count) in &counts {
+            let (start, start_count) = prev.unwrap_or((le.min(0.0), 0.0));
+            let value = if le.is_infinite() {
+                start
+            } else {
+                start + (le - start) / 2.0
+            };
+            if count > start_count {
+                observations.push((value, count - start_count));
+            }
+            prev = Some((le, count));
+        }
+        observations
+    }
+}
+
+impl Default for CounterHistogram {
+    fn default() -> Self {
+        CounterHistogram::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time_series::TSPoint;
+
+    fn bucket(le: f64, points: &[(i64, f64)]) -> HistogramBucket {
+        let mut counter = CounterSummary::new(&TSPoint{ts: points[0].0, val: points[0].1}, None);
+        for &(ts, val) in &points[1..] {
+            counter.add_point(&TSPoint{ts, val}).unwrap();
+        }
+        HistogramBucket{le, counter}
+    }
+
+    fn histogram() -> CounterHistogram {
+        CounterHistogram::from_buckets(vec![
+            bucket(f64::INFINITY, &[(0, 10.0), (100, 110.0)]),
+            bucket(0.5, &[(0, 5.0), (100, 55.0)]),
+            bucket(0.25, &[(0, 0.0), (100, 20.0)]),
+            bucket(1.0, &[(0, 10.0), (100, 90.0)]),
+        ]).unwrap()
+    }
+
+    #[test]
+    fn test_from_buckets_sorts_and_combines() {
+        let hist = CounterHistogram::from_buckets(vec![
+            bucket(1.0, &[(200, 30.0), (300, 40.0)]),
+            bucket(f64::INFINITY, &[(0, 0.0), (100, 10.0)]),
+            bucket(1.0, &[(0, 0.0), (100, 20.0)]),
+        ]).unwrap();
+        assert_eq!(hist.buckets.len(), 2);
+        assert_eq!(hist.buckets[0].le, 1.0);
+        assert_eq!(hist.buckets[0].counter.delta(), 40.0);
+        assert_eq!(hist.buckets[1].le, f64::INFINITY);
+
+        // overlapping summaries for the same bucket cannot be combined
+        let err = CounterHistogram::from_buckets(vec![
+            bucket(1.0, &[(0, 0.0), (100, 20.0)]),
+            bucket(1.0, &[(100, 20.0), (200, 30.0)]),
+        ]);
+        assert_eq!(err, Err(CounterError::OrderError));
+    }
+
+    #[test]
+    fn test_quantile() {
+        let hist = histogram();
+        assert_eq!(hist.cumulative_counts(), vec![(0.25, 20.0), (0.5, 50.0), (1.0, 80.0), (f64::INFINITY, 100.0)]);
+        // rank 10 is in the first bucket which starts at 0
+        assert!((hist.quantile(0.1).unwrap() - 0.125).abs() < 1e-12);
+        // rank 35 is halfway through (0.25, 0.5]
+        assert!((hist.quantile(0.35).unwrap() - 0.375).abs() < 1e-12);
+        assert!((hist.quantile(0.8).unwrap() - 1.0).abs() < 1e-12);
+        // ranks in the +Inf bucket return the highest finite bound
+        assert_eq!(hist.quantile(0.9), Some(1.0));
+        assert_eq!(hist.quantile(-1.0), Some(f64::NEG_INFINITY));
+        assert_eq!(hist.quantile(2.0), Some(f64::INFINITY));
+    }
+
+    #[test]
+    fn test_quantile_with_reset() {
+        let hist = CounterHistogram::from_buckets(vec![
+            bucket(1.0, &[(0, 10.0), (100, 20.0), (200, 5.0)]),
+            bucket(f64::INFINITY, &[(0, 10.0), (100, 30.0), (200, 10.0)]),
+        ]).unwrap();
+        assert_eq!(hist.cumulative_counts(), vec![(1.0, 15.0), (f64::INFINITY, 30.0)]);
+        assert!((hist.quantile(0.25).unwrap() - 0.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_quantile_unusable() {
+        let no_inf = CounterHistogram::from_buckets(vec![
+            bucket(0.5, &[(0, 0.0), (100, 5.0)]),
+            bucket(1.0, &[(0, 0.0), (100, 10.0)]),
+        ]).unwrap();
+        assert_eq!(no_inf.quantile(0.5), None);
+
+        let only_inf = CounterHistogram::from_buckets(vec![
+            bucket(f64::INFINITY, &[(0, 0.0), (100, 10.0)]),
+        ]).unwrap();
+        assert_eq!(only_inf.quantile(0.5), None);
+
+        let empty = CounterHistogram::from_buckets(vec![
+            bucket(1.0, &[(0, 5.0), (100, 5.0)]),
+            bucket(f64::INFINITY, &[(0, 5.0), (100, 5.0)]),
+        ]).unwrap();
+        assert_eq!(empty.quantile(0.5), None);
+    }
+
+    #[test]
+    fn test_non_monotonic_buckets() {
+        // the 1.0 bucket was scraped slightly before the 0.5 bucket
+        let hist = CounterHistogram::from_buckets(vec![
+            bucket(0.5, &[(0, 0.0), (100, 12.0)]),
+            bucket(1.0, &[(0, 0.0), (100, 10.0)]),
+            bucket(f64::INFINITY, &[(0, 0.0), (100, 20.0)]),
+        ]).unwrap();
+        assert_eq!(hist.cumulative_counts(), vec![(0.5, 12.0), (1.0, 12.0), (f64::INFINITY, 20.0)]);
+    }
+
+    #[test]
+    fn test_observations() {
+        let hist = histogram();
+        assert_eq!(hist.observations(), vec![(0.125, 20.0), (0.375, 30.0), (0.75, 30.0), (1.0, 20.0)]);
+    }
+
+    #[test]
+    fn test_combine() {
+        let mut a = CounterHistogram::from_buckets(vec![
+            bucket(1.0, &[(0, 0.0), (100, 10.0)]),
+            bucket(f64::INFINITY, &[(0, 0.0), (100, 10.0)]),
+        ]).unwrap();
+        let b = CounterHistogram::from_buckets(vec![
+            bucket(1.0, &[(200, 10.0), (300, 10.0)]),
+            bucket(f64::INFINITY, &[(200, 10.0), (300, 30.0)]),
+        ]).unwrap();
+        a.combine(&b).unwrap();
+        assert_eq!(a.cumulative_counts(), vec![(1.0, 10.0), (f64::INFINITY, 30.0)]);
+        assert!((a.quantile(0.5).unwrap() - 1.0).abs() < 1e-12);
+    }
+}
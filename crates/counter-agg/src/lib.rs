@@ -2,17 +2,42 @@
 use time_series::TSPoint;
 use stats_agg::{XYPair, stats2d::StatsSummary2D};
 use serde::{Deserialize, Serialize};
+use flat_serialize_macro::FlatSerializable;
 
 
 pub mod range;
+pub mod histogram;
 mod tests;
 
 #[derive(Debug, PartialEq)]
 pub enum CounterError{
     OrderError,
     BoundsInvalid,
+    ToleranceMismatch,
 }
 
+// Some exporters briefly report a slightly lower value due to sampling races,
+// a decrease of no more than `absolute`, or no more than `relative` times the
+// previous value, is treated as noise rather than a counter reset.
+// The default of no tolerance treats every decrease as a reset.
+#[derive(Debug, PartialEq, Copy, Clone, Default, Serialize, Deserialize, FlatSerializable)]
+#[repr(C)]
+pub struct ResetTolerance {
+    pub absolute: f64,
+    pub relative: f64,
+}
+
+impl ResetTolerance {
+    pub fn is_reset(&self, prev: f64, next: f64) -> bool {
+        let decrease = prev - next;
+        decrease > 0.0 && decrease > self.absolute && decrease > self.relative * prev.abs()
+    }
+}
+
+// the number of reset timestamps a CounterSummary keeps, only the most recent
+// resets are kept so the summary stays small for very noisy counters
+pub const MAX_RESET_TIMES: usize = 64;
+
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct CounterSummary {
     pub first: TSPoint,
@@ -24,6 +49,9 @@ pub struct CounterSummary {
     pub num_changes: u64,
     pub stats: StatsSummary2D,
     pub bounds: Option<range::I64Range>,
+    pub reset_tolerance: ResetTolerance,
+    // times of the first point after each reset, in time order
+    pub reset_times: Vec<i64>,
 }
 
 // Note that this can lose fidelity with the timestamp, but it would only lose it in the microseconds, 
@@ -47,6 +75,10 @@ fn to_seconds(t: f64)-> f64{
 /// counter at that timestamp.
 impl CounterSummary {
     pub fn new(pt: &TSPoint, bounds:Option<range::I64Range>) -> CounterSummary {
+        CounterSummary::with_reset_tolerance(pt, bounds, ResetTolerance::default())
+    }
+
+    pub fn with_reset_tolerance(pt: &TSPoint, bounds:Option<range::I64Range>, reset_tolerance: ResetTolerance) -> CounterSummary {
         let mut n = CounterSummary{
             first: *pt,
             second: *pt,
@@ -57,6 +89,8 @@ impl CounterSummary {
             num_changes: 0,
             stats: StatsSummary2D::new(),
             bounds,
+            reset_tolerance,
+            reset_times: vec![],
         };
         n.stats.accum(ts_to_xy(*pt)).unwrap();
         n
@@ -74,9 +108,11 @@ impl CounterSummary {
             // see discussion at https://github.com/timescale/timescale-analytics/discussions/65
             return Ok(());
         }
-        if incoming.val < self.last.val {
+        if self.reset_tolerance.is_reset(self.last.val, incoming.val) {
             self.reset_sum += self.last.val;
             self.num_resets+= 1;
+            self.reset_times.push(incoming.ts);
+            self.truncate_reset_times();
         }
         // right now we treat a counter reset that goes to exactly zero as a change (not sure that's correct, but it seems defensible)
         if incoming.val != self.last.val{
@@ -93,6 +129,13 @@ impl CounterSummary {
         Ok(())
     }
 
+    fn truncate_reset_times(&mut self) {
+        if self.reset_times.len() > MAX_RESET_TIMES {
+            let excess = self.reset_times.len() - MAX_RESET_TIMES;
+            self.reset_times.drain(..excess);
+        }
+    }
+
     fn single_value(&self) -> bool {
         self.last == self.first
     }
@@ -103,12 +146,17 @@ impl CounterSummary {
         if self.last.ts >= incoming.first.ts {
             return Err(CounterError::OrderError);
         }
+        // resets must be detected the same way on both sides of the boundary
+        if self.reset_tolerance != incoming.reset_tolerance {
+            return Err(CounterError::ToleranceMismatch);
+        }
 
         if self.last.val != incoming.first.val{
             self.num_changes += 1;
-            if  incoming.first.val < self.last.val {
+            if self.reset_tolerance.is_reset(self.last.val, incoming.first.val) {
                 self.reset_sum += self.last.val;
                 self.num_resets += 1;
+                self.reset_times.push(incoming.first.ts);
             }
         }
         
@@ -127,6 +175,8 @@ impl CounterSummary {
         self.reset_sum += incoming.reset_sum;
         self.num_resets += incoming.num_resets;
         self.num_changes += incoming.num_changes;
+        self.reset_times.extend_from_slice(&incoming.reset_times);
+        self.truncate_reset_times();
         
         self.stats = self.stats.combine(stats).unwrap();
         self.bounds_extend(incoming.bounds);
@@ -150,7 +200,7 @@ impl CounterSummary {
     
     pub fn idelta_left(&self) -> f64 {
         //check for counter reset
-        if self.second.val >= self.first.val {
+        if !self.reset_tolerance.is_reset(self.first.val, self.second.val) {
             self.second.val - self.first.val
         } else {
             self.second.val // counter reset assumes it reset at the previous point, so we just return the second point
@@ -159,7 +209,7 @@ impl CounterSummary {
 
     pub fn idelta_right(&self) -> f64 {
         //check for counter reset
-        if self.last.val >= self.penultimate.val {
+        if !self.reset_tolerance.is_reset(self.penultimate.val, self.last.val) {
             self.last.val - self.penultimate.val
         } else {
             self.last.val
@@ -263,4 +313,77 @@ impl CounterSummary {
         let duration = bounds.duration().unwrap(); // only returns None if we have an infinite bound, which is checked in the delta stuff 
         Ok(Some(delta / to_seconds(duration as f64))) // don't have to deal with 0 case because that is checked in delta as well (singleton)
     }
+
+    // the checks shared by all extrapolation methods, returns the bounds to
+    // extrapolate to, or None if there aren't enough points to extrapolate from
+    fn extrapolation_bounds(&self) -> Result<Option<range::I64Range>, CounterError> {
+        if self.bounds.is_none() || !self.bounds_valid() || self.bounds.unwrap().has_infinite() {
+            return Err(CounterError::BoundsInvalid);
+        }
+        if self.single_value() || self.bounds.unwrap().is_singleton() {
+            return Ok(None);
+        }
+        Ok(self.bounds)
+    }
+
+    // Like prometheus_delta, extrapolates the average rate of change out to the
+    // bounds without extrapolating to negative counter values, but always
+    // extrapolates all the way to the bounds instead of applying the 110% heuristic.
+    pub fn clamped_delta(&self) -> Result<Option<f64>, CounterError> {
+        let bounds = match self.extrapolation_bounds()? {
+            None => return Ok(None),
+            Some(bounds) => bounds,
+        };
+        let delta = self.delta();
+        let sampled_interval = self.time_delta();
+        let mut duration_to_start = to_seconds((self.first.ts - bounds.left.unwrap()) as f64);
+        let duration_to_end = to_seconds((bounds.right.unwrap() - self.last.ts) as f64);
+        if delta > 0.0 && self.first.val >= 0.0 {
+            let duration_to_zero = sampled_interval * (self.first.val / delta);
+            duration_to_start = duration_to_start.min(duration_to_zero);
+        }
+        Ok(Some(delta * (sampled_interval + duration_to_start + duration_to_end) / sampled_interval))
+    }
+
+    pub fn clamped_rate(&self) -> Result<Option<f64>, CounterError> {
+        let delta = match self.clamped_delta()? {
+            None => return Ok(None),
+            Some(delta) => delta,
+        };
+        let duration = self.bounds.unwrap().duration().unwrap();
+        Ok(Some(delta / to_seconds(duration as f64)))
+    }
+
+    // Extrapolates the least squares fit of the (reset adjusted) points out to
+    // the bounds, the fit is not extrapolated to negative counter values at the
+    // start of the bounds.
+    pub fn regression_delta(&self) -> Result<Option<f64>, CounterError> {
+        let bounds = match self.extrapolation_bounds()? {
+            None => return Ok(None),
+            Some(bounds) => bounds,
+        };
+        let slope = match self.stats.slope() {
+            None => return Ok(None),
+            Some(slope) => slope,
+        };
+        // evaluate the fit relative to the mean rather than using the intercept,
+        // as the intercept is at the epoch and loses precision
+        let avg = self.stats.avg().unwrap();
+        let fit = |ts: i64| avg.y + slope * (to_seconds(ts as f64) - avg.x);
+        let mut start = fit(bounds.left.unwrap());
+        let end = fit(bounds.right.unwrap());
+        if start < 0.0 && self.first.val >= 0.0 {
+            start = 0.0;
+        }
+        Ok(Some(end - start))
+    }
+
+    pub fn regression_rate(&self) -> Result<Option<f64>, CounterError> {
+        let delta = match self.regression_delta()? {
+            None => return Ok(None),
+            Some(delta) => delta,
+        };
+        let duration = self.bounds.unwrap().duration().unwrap();
+        Ok(Some(delta / to_seconds(duration as f64)))
+    }
 }
@@ -192,6 +192,100 @@ pub mod tests {
         // test error in wrong direction
         assert_eq!(part2.combine(&part1).unwrap_err(), CounterError::OrderError);
     }
+
+    #[test]
+    fn test_reset_tolerance() {
+        let tolerance = ResetTolerance{absolute: 0.0, relative: 0.001};
+        let mut summary = CounterSummary::with_reset_tolerance(&TSPoint{ts: 0, val:1000.0}, None, tolerance);
+        // a small decrease is noise
+        summary.add_point(&TSPoint{ts: 5, val:999.9}).unwrap();
+        summary.add_point(&TSPoint{ts: 10, val:1001.0}).unwrap();
+        assert_eq!(summary.num_resets, 0);
+        assert_eq!(summary.num_changes, 2);
+        assert_relative_eq!(summary.delta(), 1.0);
+        assert_relative_eq!(summary.idelta_left(), -0.1, epsilon = 1e-9);
+        // a large one is still a reset
+        summary.add_point(&TSPoint{ts: 15, val:10.0}).unwrap();
+        assert_eq!(summary.num_resets, 1);
+        assert_relative_eq!(summary.delta(), 11.0);
+        assert_relative_eq!(summary.idelta_right(), 10.0);
+
+        let tolerance = ResetTolerance{absolute: 0.5, relative: 0.0};
+        let mut summary = CounterSummary::with_reset_tolerance(&TSPoint{ts: 0, val:1000.0}, None, tolerance);
+        summary.add_point(&TSPoint{ts: 5, val:999.5}).unwrap();
+        assert_eq!(summary.num_resets, 0);
+        summary.add_point(&TSPoint{ts: 10, val:998.9}).unwrap();
+        assert_eq!(summary.num_resets, 1);
+
+        // without a tolerance every decrease is a reset
+        let mut summary = CounterSummary::new(&TSPoint{ts: 0, val:1000.0}, None);
+        summary.add_point(&TSPoint{ts: 5, val:999.9}).unwrap();
+        assert_eq!(summary.num_resets, 1);
+    }
+
+    #[test]
+    fn test_combine_reset_tolerance() {
+        let tolerance = ResetTolerance{absolute: 1.0, relative: 0.0};
+        let mut summary = CounterSummary::with_reset_tolerance(&TSPoint{ts: 0, val:100.0}, None, tolerance);
+        summary.add_point(&TSPoint{ts: 5, val:99.5}).unwrap();
+        summary.add_point(&TSPoint{ts: 10, val:110.0}).unwrap();
+
+        let part1 = CounterSummary::with_reset_tolerance(&TSPoint{ts: 0, val:100.0}, None, tolerance);
+        let mut part2 = CounterSummary::with_reset_tolerance(&TSPoint{ts: 5, val:99.5}, None, tolerance);
+        part2.add_point(&TSPoint{ts: 10, val:110.0}).unwrap();
+
+        // the decrease at the boundary is noise as well
+        let mut combined = part1.clone();
+        combined.combine(&part2).unwrap();
+        assert_close_enough(&summary, &combined);
+        assert_eq!(combined.num_resets, 0);
+        assert_relative_eq!(combined.delta(), 10.0);
+
+        // summaries with different tolerances cannot be combined
+        let other = CounterSummary::new(&TSPoint{ts: 5, val:99.5}, None);
+        let mut combined = part1.clone();
+        assert_eq!(combined.combine(&other).unwrap_err(), CounterError::ToleranceMismatch);
+    }
+
+    #[test]
+    fn test_reset_times() {
+        let mut summary = CounterSummary::new(&TSPoint{ts: 0, val:0.0}, None);
+        summary.add_point(&TSPoint{ts: 5, val:10.0}).unwrap();
+        summary.add_point(&TSPoint{ts: 10, val:5.0}).unwrap();
+        summary.add_point(&TSPoint{ts: 15, val:10.0}).unwrap();
+        summary.add_point(&TSPoint{ts: 20, val:5.0}).unwrap();
+        summary.add_point(&TSPoint{ts: 25, val:1.0}).unwrap();
+        assert_eq!(summary.reset_times, vec![10, 20, 25]);
+
+        let mut part1 = CounterSummary::new(&TSPoint{ts: 0, val:0.0}, None);
+        part1.add_point(&TSPoint{ts: 5, val:10.0}).unwrap();
+        part1.add_point(&TSPoint{ts: 10, val:5.0}).unwrap();
+        part1.add_point(&TSPoint{ts: 15, val:10.0}).unwrap();
+        // reset on the boundary
+        let mut part2 = CounterSummary::new(&TSPoint{ts: 20, val:5.0}, None);
+        part2.add_point(&TSPoint{ts: 25, val:1.0}).unwrap();
+        part1.combine(&part2).unwrap();
+        assert_eq!(part1.reset_times, summary.reset_times);
+    }
+
+    #[test]
+    fn test_reset_times_bounded() {
+        let mut summary = CounterSummary::new(&TSPoint{ts: 0, val:0.0}, None);
+        for i in 1..=(2 * MAX_RESET_TIMES as i64) {
+            summary.add_point(&TSPoint{ts: 2 * i - 1, val:10.0}).unwrap();
+            summary.add_point(&TSPoint{ts: 2 * i, val:0.0}).unwrap();
+        }
+        assert_eq!(summary.num_resets, 2 * MAX_RESET_TIMES as u64);
+        assert_eq!(summary.reset_times.len(), MAX_RESET_TIMES);
+        // only the most recent resets are kept
+        assert_eq!(summary.reset_times[0], 2 * MAX_RESET_TIMES as i64 + 2);
+        assert_eq!(*summary.reset_times.last().unwrap(), 4 * MAX_RESET_TIMES as i64);
+
+        let mut combined = CounterSummary::new(&TSPoint{ts: -2, val:10.0}, None);
+        combined.add_point(&TSPoint{ts: -1, val:0.0}).unwrap();
+        combined.combine(&summary).unwrap();
+        assert_eq!(combined.reset_times, summary.reset_times);
+    }
     
     #[test]
     fn test_extraction_single_point() {
@@ -292,6 +386,44 @@ pub mod tests {
         assert_eq!(summary.bounds.unwrap(), I64Range{left:Some(0), right:Some(30)});
     }
 
+    #[test]
+    fn test_clamped_and_regression_extrapolation(){
+        let micros = |secs: i64| secs * 1_000_000;
+        let summary = CounterSummary::new(&TSPoint{ts: 5, val:15.0}, None);
+        assert_eq!(summary.clamped_delta().unwrap_err(), CounterError::BoundsInvalid);
+        assert_eq!(summary.regression_delta().unwrap_err(), CounterError::BoundsInvalid);
+
+        let bounds = Some(I64Range{left:Some(0), right:Some(micros(60))});
+        let mut summary = CounterSummary::new(&TSPoint{ts: micros(10), val:10.0}, bounds);
+        assert_eq!(summary.clamped_delta().unwrap(), None);
+        assert_eq!(summary.regression_delta().unwrap(), None);
+
+        summary.add_point(&TSPoint{ts: micros(20), val:20.0}).unwrap();
+        summary.add_point(&TSPoint{ts: micros(30), val:30.0}).unwrap();
+        // the last point is too far from the end bound for prometheus to extrapolate all the way
+        assert_relative_eq!(summary.prometheus_delta().unwrap().unwrap(), 35.0);
+        // but clamped does, while stopping at the counter's zero point at the start
+        assert_relative_eq!(summary.clamped_delta().unwrap().unwrap(), 60.0);
+        assert_relative_eq!(summary.clamped_rate().unwrap().unwrap(), 1.0);
+        assert_relative_eq!(summary.regression_delta().unwrap().unwrap(), 60.0, epsilon = 1e-9);
+        assert_relative_eq!(summary.regression_rate().unwrap().unwrap(), 1.0, epsilon = 1e-9);
+
+        // the regression uses the fit line, not the first and last points,
+        // here it's y = x - 5/3 which is clamped to zero at the start
+        let mut summary = CounterSummary::new(&TSPoint{ts: micros(10), val:10.0}, bounds);
+        summary.add_point(&TSPoint{ts: micros(20), val:15.0}).unwrap();
+        summary.add_point(&TSPoint{ts: micros(30), val:30.0}).unwrap();
+        assert_relative_eq!(summary.regression_delta().unwrap().unwrap(), 60.0 - 5.0 / 3.0, epsilon = 1e-9);
+        assert_relative_eq!(summary.clamped_delta().unwrap().unwrap(), 60.0);
+
+        // the fit is not extrapolated below zero at the start
+        let mut summary = CounterSummary::new(&TSPoint{ts: micros(30), val:0.0}, bounds);
+        summary.add_point(&TSPoint{ts: micros(40), val:10.0}).unwrap();
+        summary.add_point(&TSPoint{ts: micros(50), val:20.0}).unwrap();
+        assert_relative_eq!(summary.regression_delta().unwrap().unwrap(), 30.0, epsilon = 1e-9);
+        assert_relative_eq!(summary.clamped_delta().unwrap().unwrap(), 30.0);
+    }
+
     #[test]
     fn test_prometheus_extrapolation_simple(){
         //error on lack of bounds provided
@@ -0,0 +1,187 @@
+// A fixed-point decimal number for keeping the sums of integer and numeric
+// inputs exactly. 38 significant digits covers any realistic sum of bigints
+// or money amounts, arithmetic that would go beyond that returns None rather
+// than losing precision.
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+// the number of decimal places of an average, if the inputs have fewer
+pub const MIN_AVERAGE_SCALE: u32 = 16;
+
+/// `value` * 10^-`scale`
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub struct Decimal {
+    pub value: i128,
+    pub scale: u32,
+}
+
+fn power_of_ten(exponent: u32) -> Option<i128> {
+    10i128.checked_pow(exponent)
+}
+
+impl Decimal {
+    pub fn zero() -> Self {
+        Decimal { value: 0, scale: 0 }
+    }
+
+    // the same number with `scale` decimal places, which must be at least the
+    // current number
+    fn rescale(&self, scale: u32) -> Option<Self> {
+        debug_assert!(scale >= self.scale);
+        let value = self.value.checked_mul(power_of_ten(scale - self.scale)?)?;
+        Some(Decimal { value, scale })
+    }
+
+    /// the result has as many decimal places as the more precise input
+    /// ```
+    /// use stats_agg::decimal::Decimal;
+    /// let a: Decimal = "1.25".parse().unwrap();
+    /// let b: Decimal = "-3.1".parse().unwrap();
+    /// assert_eq!(a.checked_add(&b).unwrap().to_string(), "-1.85");
+    /// ```
+    pub fn checked_add(&self, other: &Decimal) -> Option<Self> {
+        let scale = self.scale.max(other.scale);
+        let (a, b) = (self.rescale(scale)?, other.rescale(scale)?);
+        Some(Decimal { value: a.value.checked_add(b.value)?, scale })
+    }
+
+    pub fn checked_sub(&self, other: &Decimal) -> Option<Self> {
+        let negated = Decimal { value: other.value.checked_neg()?, scale: other.scale };
+        self.checked_add(&negated)
+    }
+
+    /// divides by `divisor` rounding half away from zero, to `scale` decimal
+    /// places or fewer if there would otherwise be too many digits
+    /// ```
+    /// use stats_agg::decimal::Decimal;
+    /// let a: Decimal = "-2.5".parse().unwrap();
+    /// assert_eq!(a.checked_div(3, 4).unwrap().to_string(), "-0.8333");
+    /// ```
+    pub fn checked_div(&self, divisor: u64, scale: u32) -> Option<Self> {
+        if divisor == 0 {
+            return None;
+        }
+        let divisor = divisor as i128;
+        let mut scale = scale.max(self.scale);
+        let dividend = loop {
+            match self.rescale(scale) {
+                Some(d) => break d.value,
+                None if scale > self.scale => scale -= 1,
+                None => return None,
+            }
+        };
+        let (quotient, remainder) = (dividend / divisor, dividend % divisor);
+        // |remainder| * 2 >= divisor without overflowing
+        let value = if remainder.abs() >= divisor - remainder.abs() {
+            quotient + dividend.signum()
+        } else {
+            quotient
+        };
+        Some(Decimal { value, scale })
+    }
+
+    // the nearest double, parsing our string form gets it correctly rounded
+    pub fn to_f64(&self) -> f64 {
+        self.to_string().parse().unwrap()
+    }
+}
+
+impl From<i64> for Decimal {
+    fn from(value: i64) -> Self {
+        Decimal { value: value as i128, scale: 0 }
+    }
+}
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let digits = self.value.unsigned_abs().to_string();
+        let scale = self.scale as usize;
+        let sign = if self.value < 0 { "-" } else { "" };
+        if scale == 0 {
+            return write!(f, "{}{}", sign, digits);
+        }
+        let digits = format!("{:0>width$}", digits, width = scale + 1);
+        let (integer, fraction) = digits.split_at(digits.len() - scale);
+        write!(f, "{}{}.{}", sign, integer, fraction)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseDecimalError;
+
+// parses the plain decimal notation Postgres outputs numerics in, keeping
+// any trailing zeros as part of the scale
+impl FromStr for Decimal {
+    type Err = ParseDecimalError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (negative, unsigned) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        let (integer, fraction) = match unsigned.split_once('.') {
+            Some((integer, fraction)) => (integer, fraction),
+            None => (unsigned, ""),
+        };
+        let all_digits = |part: &str| part.bytes().all(|b| b.is_ascii_digit());
+        if integer.is_empty() && fraction.is_empty() || !all_digits(integer) || !all_digits(fraction) {
+            return Err(ParseDecimalError);
+        }
+        let mut value: i128 = 0;
+        for digit in integer.bytes().chain(fraction.bytes()) {
+            value = value.checked_mul(10)
+                .and_then(|v| v.checked_add((digit - b'0') as i128))
+                .ok_or(ParseDecimalError)?;
+        }
+        Ok(Decimal {
+            value: if negative { -value } else { value },
+            scale: fraction.len() as u32,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decimal(s: &str) -> Decimal {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for s in ["0", "-7", "123.4500", "-0.001", "0.10", "99999999999999999999999999999999999999"] {
+            assert_eq!(decimal(s).to_string(), s);
+        }
+        assert_eq!(decimal("+.5").to_string(), "0.5");
+        assert_eq!(decimal("3.").to_string(), "3");
+        assert_eq!(decimal("123.4500").scale, 4);
+        for s in ["", ".", "NaN", "Infinity", "1e5", "1.2.3", "--1", "100000000000000000000000000000000000000000"] {
+            assert_eq!(s.parse::<Decimal>(), Err(ParseDecimalError));
+        }
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        assert_eq!(decimal("0.1").checked_add(&decimal("0.2")), Some(decimal("0.3")));
+        assert_eq!(decimal("1").checked_sub(&decimal("0.25")), Some(decimal("0.75")));
+        assert_eq!(Decimal::from(i64::MAX).checked_add(&Decimal::from(i64::MAX)).unwrap().to_string(), "18446744073709551614");
+        let huge = decimal("170141183460469231731687303715884105727");
+        assert_eq!(huge.checked_add(&decimal("1")), None);
+        assert_eq!(huge.checked_add(&decimal("0.1")), None);
+
+        assert_eq!(decimal("10").checked_div(4, 0), Some(decimal("3")));
+        assert_eq!(decimal("-10").checked_div(4, 0), Some(decimal("-3")));
+        assert_eq!(decimal("1").checked_div(3, 16).unwrap().to_string(), "0.3333333333333333");
+        assert_eq!(decimal("2").checked_div(3, 16).unwrap().to_string(), "0.6666666666666667");
+        assert_eq!(decimal("1.23456").checked_div(1, 2), Some(decimal("1.23456")));
+        // fewer places if there isn't room for them
+        assert_eq!(huge.checked_div(3, 16).unwrap().to_string(), "56713727820156410577229101238628035242");
+        assert_eq!(decimal("1").checked_div(0, 16), None);
+
+        assert_eq!(decimal("0.1").to_f64(), 0.1);
+        assert_eq!(decimal("-12345678901234567890").to_f64(), -12345678901234567890.0);
+    }
+}
@@ -0,0 +1,197 @@
+// Exponentially weighted moving average and variance of a series of
+// timestamped values, where each value's weight halves every `half_life`
+// microseconds before the latest value seen.
+//
+// Rather than updating the average recursively in time order we keep the
+// total weight, weighted mean and weighted sum of squared deviations as of a
+// reference time, the latest timestamp seen. Changing the reference time
+// scales every weight by the same factor, which leaves the mean unchanged, so
+// summaries can be brought to a common reference time and combined in the
+// same way as unweighted ones, regardless of the order of the inputs.
+use serde::{Deserialize, Serialize};
+use crate::StatsError;
+
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub struct EwmaSummary {
+    pub half_life: i64,
+    pub n: u64,
+    pub reference_time: i64,
+    // the sum of the weights as of reference_time, at least 1 if n > 0 since
+    // the value at reference_time has weight 1
+    pub weight: f64,
+    pub mean: f64,
+    // sum(weight * (x - mean)^2)
+    pub m2: f64,
+}
+
+impl EwmaSummary {
+    pub fn new(half_life: i64) -> Self {
+        assert!(half_life > 0, "half_life must be positive");
+        EwmaSummary {
+            half_life,
+            n: 0,
+            reference_time: 0,
+            weight: 0.0,
+            mean: 0.0,
+            m2: 0.0,
+        }
+    }
+
+    /// ```
+    /// use stats_agg::ewma::EwmaSummary;
+    /// let mut p = EwmaSummary::new(10);
+    /// p.accum(0, 1.0).unwrap();
+    /// p.accum(10, 4.0).unwrap();
+    /// // the first value has half the weight of the second
+    /// assert_eq!(p.mean(), Some(3.0));
+    /// ```
+    pub fn accum(&mut self, ts: i64, value: f64) -> Result<(), StatsError> {
+        let point = EwmaSummary {
+            half_life: self.half_life,
+            n: 1,
+            reference_time: ts,
+            weight: 1.0,
+            mean: value,
+            m2: 0.0,
+        };
+        *self = self.combine(&point)?;
+        Ok(())
+    }
+
+    // the weights are scaled so they're as of `time`, which must not be
+    // earlier than the current reference time
+    fn decay_to(&self, time: i64) -> Self {
+        debug_assert!(time >= self.reference_time);
+        let decay = 0.5f64.powf((time - self.reference_time) as f64 / self.half_life as f64);
+        EwmaSummary {
+            reference_time: time,
+            weight: self.weight * decay,
+            m2: self.m2 * decay,
+            ..*self
+        }
+    }
+
+    /// ```
+    /// use stats_agg::ewma::EwmaSummary;
+    /// use stats_agg::StatsError;
+    /// let mut p = EwmaSummary::new(10);
+    /// p.accum(0, 1.0).unwrap();
+    /// let mut q = EwmaSummary::new(10);
+    /// q.accum(10, 4.0).unwrap();
+    /// assert_eq!(p.combine(&q), q.combine(&p));
+    /// assert_eq!(p.combine(&EwmaSummary::new(20)), Err(StatsError::HalfLifeMismatch));
+    /// ```
+    pub fn combine(&self, other: &EwmaSummary) -> Result<Self, StatsError> {
+        if self.half_life != other.half_life {
+            return Err(StatsError::HalfLifeMismatch);
+        }
+        if other.n == 0 {
+            return Ok(*self);
+        }
+        if self.n == 0 {
+            return Ok(*other);
+        }
+        let reference_time = self.reference_time.max(other.reference_time);
+        let a = self.decay_to(reference_time);
+        let b = other.decay_to(reference_time);
+        // the parallel algorithm from Chan et al. with weights
+        let weight = a.weight + b.weight;
+        let delta = b.mean - a.mean;
+        Ok(EwmaSummary {
+            half_life: self.half_life,
+            n: a.n + b.n,
+            reference_time,
+            weight,
+            mean: a.mean + delta * b.weight / weight,
+            m2: a.m2 + b.m2 + delta * delta * a.weight * b.weight / weight,
+        })
+    }
+
+    pub fn count(&self) -> i64 {
+        self.n as i64
+    }
+
+    pub fn mean(&self) -> Option<f64> {
+        if self.n == 0 {
+            return None;
+        }
+        Some(self.mean)
+    }
+
+    // the weighted population variance, which is 0 for a single value
+    pub fn variance(&self) -> Option<f64> {
+        if self.n == 0 {
+            return None;
+        }
+        Some(self.m2 / self.weight)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    const POINTS: [(i64, f64); 6] = [(0, 2.0), (7, 8.0), (15, 0.5), (16, 3.0), (30, 4.5), (31, 17.0)];
+
+    fn summary(points: &[(i64, f64)]) -> EwmaSummary {
+        let mut s = EwmaSummary::new(10);
+        for &(ts, value) in points {
+            s.accum(ts, value).unwrap();
+        }
+        s
+    }
+
+    // the definition, with every weight computed directly
+    fn expected(points: &[(i64, f64)], half_life: f64) -> (f64, f64) {
+        let latest = points.iter().map(|p| p.0).max().unwrap();
+        let weights: Vec<f64> = points.iter().map(|p| 0.5f64.powf((latest - p.0) as f64 / half_life)).collect();
+        let total: f64 = weights.iter().sum();
+        let mean = points.iter().zip(&weights).map(|(p, w)| p.1 * w).sum::<f64>() / total;
+        let variance = points.iter().zip(&weights).map(|(p, w)| w * (p.1 - mean).powi(2)).sum::<f64>() / total;
+        (mean, variance)
+    }
+
+    #[test]
+    fn test_accum() {
+        let s = summary(&POINTS);
+        let (mean, variance) = expected(&POINTS, 10.0);
+        assert_eq!(s.count(), 6);
+        assert_eq!(s.reference_time, 31);
+        assert_relative_eq!(s.mean().unwrap(), mean, max_relative = 1e-12);
+        assert_relative_eq!(s.variance().unwrap(), variance, max_relative = 1e-12);
+
+        let mut reversed = POINTS;
+        reversed.reverse();
+        let r = summary(&reversed);
+        assert_relative_eq!(r.mean().unwrap(), mean, max_relative = 1e-12);
+        assert_relative_eq!(r.variance().unwrap(), variance, max_relative = 1e-12);
+
+        assert_eq!(summary(&[(5, 3.0)]).variance(), Some(0.0));
+        assert_eq!(EwmaSummary::new(10).mean(), None);
+        assert_eq!(EwmaSummary::new(10).variance(), None);
+    }
+
+    #[test]
+    fn test_combine() {
+        let s = summary(&POINTS);
+        for split in 0..=POINTS.len() {
+            let (a, b) = POINTS.split_at(split);
+            for c in [summary(a).combine(&summary(b)).unwrap(), summary(b).combine(&summary(a)).unwrap()] {
+                assert_eq!(c.n, s.n);
+                assert_eq!(c.reference_time, s.reference_time);
+                assert_relative_eq!(c.weight, s.weight, max_relative = 1e-12);
+                assert_relative_eq!(c.mean, s.mean, max_relative = 1e-12);
+                assert_relative_eq!(c.m2, s.m2, max_relative = 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn test_old_values_vanish() {
+        // values thousands of half-lives old have no weight left at all
+        let s = summary(&[(0, 1000.0), (100_000, 1.0), (100_010, 3.0)]);
+        assert_relative_eq!(s.mean().unwrap(), 3.5 / 1.5, max_relative = 1e-12);
+        assert_eq!(s.count(), 3);
+    }
+}
@@ -0,0 +1,141 @@
+// A StatsSummary1D for integer or numeric inputs which also keeps their sum
+// exactly, so the sum matches what Postgres' own sum() returns for those
+// types and the average is exact to at least 16 decimal places. The variance
+// and higher moments are still calculated in floating point from the values
+// converted to doubles.
+use serde::{Deserialize, Serialize};
+use crate::decimal::{Decimal, MIN_AVERAGE_SCALE};
+use crate::stats1d::StatsSummary1D;
+use crate::StatsError;
+
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
+pub struct ExactStatsSummary1D {
+    pub stats: StatsSummary1D,
+    pub sum: Decimal,
+}
+
+impl ExactStatsSummary1D {
+    pub fn new() -> Self {
+        ExactStatsSummary1D {
+            stats: StatsSummary1D::new(),
+            sum: Decimal::zero(),
+        }
+    }
+
+    /// ```
+    /// use stats_agg::exact1d::ExactStatsSummary1D;
+    /// let mut s = ExactStatsSummary1D::new();
+    /// for v in ["0.1", "0.2"] {
+    ///     s.accum(v.parse().unwrap()).unwrap();
+    /// }
+    /// assert_eq!(s.sum().unwrap().to_string(), "0.3");
+    /// assert_eq!(s.avg().unwrap().to_string(), "0.1500000000000000");
+    /// ```
+    pub fn accum(&mut self, p: Decimal) -> Result<(), StatsError> {
+        let sum = self.sum.checked_add(&p).ok_or(StatsError::SumOverflow)?;
+        self.stats.accum(p.to_f64())?;
+        self.sum = sum;
+        Ok(())
+    }
+
+    // inverse of accum() for windowed aggregates, the sum is always removed
+    // exactly, but we still re-calculate when the floating point part would
+    // lose too much precision
+    pub fn remove(&self, p: Decimal) -> Option<Self> {
+        Some(ExactStatsSummary1D {
+            stats: self.stats.remove(p.to_f64())?,
+            sum: self.sum.checked_sub(&p)?,
+        })
+    }
+
+    pub fn combine(&self, other: &ExactStatsSummary1D) -> Result<Self, StatsError> {
+        Ok(ExactStatsSummary1D {
+            sum: self.sum.checked_add(&other.sum).ok_or(StatsError::SumOverflow)?,
+            stats: self.stats.combine(other.stats)?,
+        })
+    }
+
+    pub fn remove_combined(&self, remove: &ExactStatsSummary1D) -> Option<Self> {
+        Some(ExactStatsSummary1D {
+            stats: self.stats.remove_combined(remove.stats)?,
+            sum: self.sum.checked_sub(&remove.sum)?,
+        })
+    }
+
+    pub fn count(&self) -> i64 {
+        self.stats.count()
+    }
+
+    pub fn sum(&self) -> Option<Decimal> {
+        if self.stats.n == 0 {
+            return None;
+        }
+        Some(self.sum)
+    }
+
+    // rounded half away from zero to at least 16 decimal places
+    pub fn avg(&self) -> Option<Decimal> {
+        if self.stats.n == 0 {
+            return None;
+        }
+        self.sum.checked_div(self.stats.n, self.sum.scale.max(MIN_AVERAGE_SCALE))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary(values: &[&str]) -> ExactStatsSummary1D {
+        let mut s = ExactStatsSummary1D::new();
+        for v in values {
+            s.accum(v.parse().unwrap()).unwrap();
+        }
+        s
+    }
+
+    #[test]
+    fn test_exact_sum() {
+        // doubles can't represent these exactly, nor the sum of the large ones
+        let s = summary(&["9007199254740993", "9007199254740993", "0.1", "-0.7"]);
+        assert_eq!(s.count(), 4);
+        assert_eq!(s.sum().unwrap().to_string(), "18014398509481985.4");
+        assert_eq!(s.avg().unwrap().to_string(), "4503599627370496.3500000000000000");
+        assert_eq!(s.stats.sum().unwrap(), 18014398509481985.4);
+
+        let ints = summary(&["1", "2", "2"]);
+        assert_eq!(ints.sum().unwrap().to_string(), "5");
+        assert_eq!(ints.avg().unwrap().to_string(), "1.6666666666666667");
+        assert!((ints.stats.var_pop().unwrap() - 2.0 / 9.0).abs() < 1e-15);
+
+        assert_eq!(ExactStatsSummary1D::new().sum(), None);
+        assert_eq!(ExactStatsSummary1D::new().avg(), None);
+
+        let mut full = summary(&["170141183460469231731687303715884105727"]);
+        assert_eq!(full.accum("1".parse().unwrap()), Err(StatsError::SumOverflow));
+        // a failed accum leaves the summary unchanged
+        assert_eq!(full.count(), 1);
+    }
+
+    #[test]
+    fn test_remove_and_combine() {
+        let values = ["10.5", "-3", "7.25", "12", "0.001"];
+        let s = summary(&values);
+        for split in 0..=values.len() {
+            let (a, b) = values.split_at(split);
+            let (a, b) = (summary(a), summary(b));
+            let combined = a.combine(&b).unwrap();
+            assert_eq!(combined.sum, s.sum);
+            assert_eq!(combined.count(), s.count());
+            assert!((combined.stats.var_samp().unwrap() - s.stats.var_samp().unwrap()).abs() < 1e-12);
+
+            let part = s.remove_combined(&b).unwrap();
+            assert_eq!(part.sum().map(|d| d.to_f64()), a.sum().map(|d| d.to_f64()));
+            assert_eq!(part.count(), a.count());
+        }
+
+        let removed = s.remove("-3".parse().unwrap()).unwrap();
+        assert_eq!(removed.sum().unwrap().to_string(), "29.751");
+        assert_eq!(removed.count(), 4);
+    }
+}
@@ -0,0 +1,229 @@
+// Statistical tests which only need the count, mean and variance of each
+// sample, so they can be run on summaries rather than the raw data.
+use crate::stats1d::StatsSummary1D;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct TTest {
+    pub t: f64,
+    pub df: f64,
+    // two-sided
+    pub p_value: f64,
+}
+
+/// Welch's unequal variances t-test of whether the two samples have the same
+/// mean. Returns None unless both samples have at least two values and at
+/// least one of them has a non-zero variance.
+/// ```
+/// use stats_agg::stats1d::StatsSummary1D;
+/// use stats_agg::hypothesis::welch_t_test;
+/// let a = StatsSummary1D::new_from_vec(vec![1.0, 2.0, 3.0, 4.0]).unwrap();
+/// let b = StatsSummary1D::new_from_vec(vec![2.0, 4.0, 6.0, 8.0]).unwrap();
+/// let test = welch_t_test(&a, &b).unwrap();
+/// assert!(test.t < 0.0);
+/// assert!(test.p_value > 0.05);
+/// ```
+pub fn welch_t_test(a: &StatsSummary1D, b: &StatsSummary1D) -> Option<TTest> {
+    if a.n < 2 || b.n < 2 {
+        return None;
+    }
+    let (na, nb) = (a.n as f64, b.n as f64);
+    // the variance of each sample's mean
+    let va = a.var_samp()? / na;
+    let vb = b.var_samp()? / nb;
+    let v = va + vb;
+    if v == 0.0 {
+        return None;
+    }
+    let t = (a.avg()? - b.avg()?) / v.sqrt();
+    // the Welch–Satterthwaite approximation
+    let df = v * v / (va * va / (na - 1.0) + vb * vb / (nb - 1.0));
+    Some(TTest {
+        t,
+        df,
+        p_value: student_t_two_sided_p(t, df),
+    })
+}
+
+// The probability of a value at least as far from 0 as `t` under Student's
+// t-distribution with `df` degrees of freedom.
+pub fn student_t_two_sided_p(t: f64, df: f64) -> f64 {
+    if t.is_nan() || df.is_nan() {
+        return f64::NAN;
+    }
+    regularized_incomplete_beta(df / 2.0, 0.5, df / (df + t * t))
+}
+
+// The value `t` for which P(T <= t) = `p` under Student's t-distribution
+// with `df` degrees of freedom, found by bisection on the two-sided p-value,
+// which is monotonic in |t|.
+pub fn student_t_quantile(p: f64, df: f64) -> f64 {
+    if p.is_nan() || df.is_nan() || p <= 0.0 || p >= 1.0 {
+        return if p == 0.0 {
+            f64::NEG_INFINITY
+        } else if p == 1.0 {
+            f64::INFINITY
+        } else {
+            f64::NAN
+        };
+    }
+    if p < 0.5 {
+        return -student_t_quantile(1.0 - p, df);
+    }
+    let target = 2.0 * (1.0 - p);
+    let (mut low, mut high) = (0.0, 1.0);
+    while student_t_two_sided_p(high, df) > target {
+        low = high;
+        high *= 2.0;
+    }
+    // stops once the interval can no longer be halved
+    loop {
+        let mid = low + (high - low) / 2.0;
+        if mid <= low || mid >= high {
+            return mid;
+        }
+        if student_t_two_sided_p(mid, df) > target {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+}
+
+// ln(Γ(x)) using the Lanczos approximation with g = 7, which is accurate to
+// around 15 significant figures.
+fn ln_gamma(x: f64) -> f64 {
+    const G: f64 = 7.0;
+    const COEFFICIENTS: [f64; 9] = [
+        0.999_999_999_999_809_9,
+        676.520_368_121_885_1,
+        -1_259.139_216_722_402_8,
+        771.323_428_777_653_1,
+        -176.615_029_162_140_6,
+        12.507_343_278_686_905,
+        -0.138_571_095_265_720_12,
+        9.984_369_578_019_572e-6,
+        1.505_632_735_149_311_6e-7,
+    ];
+    if x < 0.5 {
+        // reflection formula, Γ(x)Γ(1 - x) = π / sin(πx)
+        let pi = std::f64::consts::PI;
+        return (pi / (pi * x).sin()).abs().ln() - ln_gamma(1.0 - x);
+    }
+    let x = x - 1.0;
+    let mut sum = COEFFICIENTS[0];
+    for (i, c) in COEFFICIENTS.iter().enumerate().skip(1) {
+        sum += c / (x + i as f64);
+    }
+    let t = x + G + 0.5;
+    0.5 * (2.0 * std::f64::consts::PI).ln() + (x + 0.5) * t.ln() - t + sum.ln()
+}
+
+// I_x(a, b), the CDF of the beta distribution, evaluated with the continued
+// fraction from Numerical Recipes §6.4, using the symmetry
+// I_x(a, b) = 1 - I_(1-x)(b, a) to stay where it converges quickly.
+fn regularized_incomplete_beta(a: f64, b: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    if x >= 1.0 {
+        return 1.0;
+    }
+    let ln_front = ln_gamma(a + b) - ln_gamma(a) - ln_gamma(b) + a * x.ln() + b * (1.0 - x).ln();
+    if x < (a + 1.0) / (a + b + 2.0) {
+        ln_front.exp() * beta_continued_fraction(a, b, x) / a
+    } else {
+        1.0 - ln_front.exp() * beta_continued_fraction(b, a, 1.0 - x) / b
+    }
+}
+
+// evaluated using the modified Lentz's method
+fn beta_continued_fraction(a: f64, b: f64, x: f64) -> f64 {
+    const MAX_ITERATIONS: u32 = 300;
+    const EPSILON: f64 = 1e-15;
+    const TINY: f64 = 1e-300;
+    let not_tiny = |v: f64| if v.abs() < TINY { TINY } else { v };
+
+    let mut c = 1.0;
+    let mut d = 1.0 / not_tiny(1.0 - (a + b) * x / (a + 1.0));
+    let mut h = d;
+    for m in 1..=MAX_ITERATIONS {
+        let m = m as f64;
+        // the even and odd steps of the recurrence
+        let even = m * (b - m) * x / ((a + 2.0 * m - 1.0) * (a + 2.0 * m));
+        d = 1.0 / not_tiny(1.0 + even * d);
+        c = not_tiny(1.0 + even / c);
+        h *= d * c;
+        let odd = -(a + m) * (a + b + m) * x / ((a + 2.0 * m) * (a + 2.0 * m + 1.0));
+        d = 1.0 / not_tiny(1.0 + odd * d);
+        c = not_tiny(1.0 + odd / c);
+        let delta = d * c;
+        h *= delta;
+        if (delta - 1.0).abs() < EPSILON {
+            break;
+        }
+    }
+    h
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    #[test]
+    fn test_ln_gamma() {
+        assert_relative_eq!(ln_gamma(1.0), 0.0, epsilon = 1e-14);
+        assert_relative_eq!(ln_gamma(5.0), 24.0f64.ln(), max_relative = 1e-14);
+        assert_relative_eq!(ln_gamma(0.5), std::f64::consts::PI.sqrt().ln(), max_relative = 1e-14);
+        assert_relative_eq!(ln_gamma(0.1), 2.252_712_651_734_206, max_relative = 1e-14);
+    }
+
+    #[test]
+    fn test_student_t() {
+        // reference values computed with mpmath
+        assert_relative_eq!(student_t_two_sided_p(2.0, 10.0), 0.073_388_034_770_740_38, max_relative = 1e-12);
+        assert_relative_eq!(student_t_two_sided_p(-2.0, 10.0), 0.073_388_034_770_740_38, max_relative = 1e-12);
+        assert_relative_eq!(student_t_two_sided_p(0.5, 3.7), 0.645_335_633_319_932, max_relative = 1e-12);
+        assert_relative_eq!(student_t_two_sided_p(10.0, 1.5), 0.023_659_355_113_621_56, max_relative = 1e-12);
+        assert_relative_eq!(student_t_two_sided_p(0.01, 200.0), 0.992_031_255_152_902_3, max_relative = 1e-12);
+        assert_eq!(student_t_two_sided_p(0.0, 10.0), 1.0);
+        assert_eq!(student_t_two_sided_p(f64::INFINITY, 10.0), 0.0);
+        assert!(student_t_two_sided_p(f64::NAN, 10.0).is_nan());
+    }
+
+    #[test]
+    fn test_student_t_quantile() {
+        // reference values computed with mpmath
+        assert_relative_eq!(student_t_quantile(0.975, 10.0), 2.228_138_851_986_275, max_relative = 1e-12);
+        assert_relative_eq!(student_t_quantile(0.025, 10.0), -2.228_138_851_986_275, max_relative = 1e-12);
+        assert_relative_eq!(student_t_quantile(0.995, 3.0), 5.840_909_309_733_357, max_relative = 1e-12);
+        assert_relative_eq!(student_t_quantile(0.9995, 1.0), 636.619_248_768_719_6, max_relative = 1e-10);
+        assert_eq!(student_t_quantile(0.5, 10.0), 0.0);
+        assert_eq!(student_t_quantile(1.0, 10.0), f64::INFINITY);
+        assert!(student_t_quantile(1.5, 10.0).is_nan());
+    }
+
+    #[test]
+    fn test_welch_t_test() {
+        let a = StatsSummary1D::new_from_vec(vec![
+            27.5, 21.0, 19.0, 23.6, 17.0, 17.9, 16.9, 20.1, 21.9, 22.6, 23.1, 19.6, 19.0, 21.7, 21.4,
+        ]).unwrap();
+        let b = StatsSummary1D::new_from_vec(vec![
+            27.1, 22.0, 20.8, 23.4, 23.4, 23.5, 25.8, 22.0, 24.8, 20.2, 21.9, 22.1, 22.9, 20.5, 24.4,
+        ]).unwrap();
+        let test = welch_t_test(&a, &b).unwrap();
+        assert_relative_eq!(test.t, -2.455_356_398_286_004, max_relative = 1e-12);
+        assert_relative_eq!(test.df, 24.988_529_290_231_414, max_relative = 1e-12);
+        assert_relative_eq!(test.p_value, 0.021_378_001_462_867_06, max_relative = 1e-10);
+
+        // swapping the samples only changes the sign of t
+        let swapped = welch_t_test(&b, &a).unwrap();
+        assert_relative_eq!(swapped.t, -test.t);
+        assert_relative_eq!(swapped.p_value, test.p_value);
+
+        let single = StatsSummary1D::new_from_vec(vec![1.0]).unwrap();
+        assert_eq!(welch_t_test(&a, &single), None);
+        let constant = StatsSummary1D::new_from_vec(vec![1.0, 1.0, 1.0]).unwrap();
+        assert_eq!(welch_t_test(&constant, &constant), None);
+    }
+}
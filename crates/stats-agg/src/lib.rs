@@ -6,9 +6,21 @@
 // https://github.com/postgres/postgres/blob/472e518a44eacd9caac7d618f1b6451672ca4481/src/backend/utils/adt/float.c#L3260
 //
 
+#[cfg(test)]
+extern crate quickcheck;
+#[cfg(test)]
+#[macro_use(quickcheck)]
+extern crate quickcheck_macros;
+
 #[derive(Debug, PartialEq)]
 pub enum StatsError {
     DoubleOverflow,
+    // summaries or points with different numbers of variables
+    DimensionMismatch,
+    // ewma summaries with different half-lives
+    HalfLifeMismatch,
+    // an exact sum with more digits than a Decimal can hold
+    SumOverflow,
 }
 
 #[derive(Debug, PartialEq)]
@@ -31,5 +43,119 @@ pub struct XYPair {
 // test early in the function for whether we need to recalculate and pass NULL quickly so that we don't affect those
 // cases too heavily. 
 const INV_FLOATING_ERROR_THRESHOLD : f64 = 0.99;
+// Neumaier's improvement of Kahan summation: the rounding error of each
+// addition to `sum` is accumulated separately in `compensation`, and
+// `sum + compensation` stays within a rounding error or two of the exact total
+// rather than drifting further from it with each addition. This matters when
+// the values are large compared to their spread, and so to the precision
+// needed in the mean. Infinite sums are left uncompensated, as the error
+// would be NaN.
+pub(crate) fn compensated_add(sum: f64, compensation: f64, x: f64) -> (f64, f64) {
+    let t = sum + x;
+    if !t.is_finite() {
+        return (t, compensation);
+    }
+    let error = if sum.abs() >= x.abs() {
+        (sum - t) + x
+    } else {
+        (x - t) + sum
+    };
+    (t, compensation + error)
+}
+
 pub mod stats2d;
 pub mod stats1d;
+pub mod regression;
+pub mod hypothesis;
+pub mod ewma;
+pub mod decimal;
+pub mod exact1d;
+
+// The count, mean and sums of the 2nd, 3rd and 4th powers of the deviations
+// from the mean of a set of values, ie the unnormalized central moments. The
+// 3rd and 4th moments are combined using the pairwise formulas from Pébay,
+// "Formulas for Robust, One-Pass Parallel Computation of Covariances and
+// Arbitrary-Order Statistical Moments" (2008), which generalize the
+// Youngs-Cramer update we use for the 2nd moment:
+//      delta = mean_b - mean_a
+//      M3 = M3_a + M3_b + delta^3 * n_a * n_b * (n_a - n_b) / n^2
+//           + 3 * delta * (n_a * M2_b - n_b * M2_a) / n
+//      M4 = M4_a + M4_b + delta^4 * n_a * n_b * (n_a^2 - n_a * n_b + n_b^2) / n^3
+//           + 6 * delta^2 * (n_a^2 * M2_b + n_b^2 * M2_a) / n^2
+//           + 4 * delta * (n_a * M3_b - n_b * M3_a) / n
+// Adding a single value is combining with a set with n = 1 and all moments 0.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Moments {
+    pub n: f64,
+    pub mean: f64,
+    pub m2: f64,
+    pub m3: f64,
+    pub m4: f64,
+}
+
+impl Moments {
+    pub fn point(x: f64) -> Self {
+        Moments { n: 1.0, mean: x, m2: 0.0, m3: 0.0, m4: 0.0 }
+    }
+
+    // the 3rd and 4th moments of the union of two disjoint sets
+    pub fn combine_higher(&self, other: &Moments) -> (f64, f64) {
+        let (na, nb) = (self.n, other.n);
+        let n = na + nb;
+        let delta = other.mean - self.mean;
+        let delta2 = delta * delta;
+        let m3 = self.m3 + other.m3
+            + delta2 * delta * na * nb * (na - nb) / (n * n)
+            + 3.0 * delta * (na * other.m2 - nb * self.m2) / n;
+        let m4 = self.m4 + other.m4
+            + delta2 * delta2 * na * nb * (na * na - na * nb + nb * nb) / (n * n * n)
+            + 6.0 * delta2 * (na * na * other.m2 + nb * nb * self.m2) / (n * n)
+            + 4.0 * delta * (na * other.m3 - nb * self.m3) / n;
+        (m3, m4)
+    }
+
+    // the inverse of combine_higher(), the 3rd and 4th moments of the part
+    // that, combined with `removed`, makes up `self`. `part` must already have
+    // its n, mean and 2nd moment, its higher moments are ignored.
+    pub fn remove_higher(&self, removed: &Moments, part: Moments) -> (f64, f64) {
+        let part = Moments { m3: 0.0, m4: 0.0, ..part };
+        let m3 = self.m3 - part.combine_higher(removed).0;
+        let part = Moments { m3, ..part };
+        let m4 = self.m4 - part.combine_higher(removed).1;
+        (m3, m4)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod exact {
+    // Exact arithmetic for checking the compensated sums. The test inputs are
+    // all multiples of 2^-20 well within the range of an i128 when scaled by
+    // 2^20, so their sums can be calculated exactly as integers.
+    const SCALE: f64 = (1u64 << 20) as f64;
+
+    pub fn input(base: i32, offset: i32) -> f64 {
+        // large values with a small spread, or with cancellation when the
+        // bases differ in sign
+        base as f64 * (1u64 << 21) as f64 + offset as f64 / SCALE
+    }
+
+    pub fn sum(values: &[f64]) -> i128 {
+        values.iter().map(|&v| {
+            let scaled = v * SCALE;
+            assert_eq!(scaled.fract(), 0.0);
+            scaled as i128
+        }).sum()
+    }
+
+    // asserts `total` is about as close to the exact sum as a double can be
+    #[track_caller]
+    pub fn assert_close(total: f64, values: &[f64]) {
+        let exact = sum(values);
+        let error = (total * SCALE - exact as f64).abs() / SCALE;
+        let sum_abs: f64 = values.iter().map(|v| v.abs()).sum();
+        // the bound on the error of Neumaier's algorithm
+        let bound = 2.0 * f64::EPSILON * (exact as f64 / SCALE).abs()
+            + 4.0 * values.len() as f64 * f64::EPSILON * f64::EPSILON * sum_abs;
+        assert!(error <= bound, "{} != {}, error {} > {}", total, exact as f64 / SCALE, error, bound);
+    }
+}
@@ -0,0 +1,358 @@
+// Multiple linear regression of y on any number of x variables.
+//
+// Rather than the raw sums XᵀX and Xᵀy, which lose precision badly when the
+// variables are large compared to their spread, we keep the means of each
+// variable and the co-moments about those means, sum((a - mean_a) * (b - mean_b))
+// for every pair of variables. This is the multivariate form of the
+// Youngs-Cramer update StatsSummary2D uses, and combines the same way:
+//      delta = means_b - means_a
+//      means = means_a + delta * n_b / n
+//      C = C_a + C_b + delta * deltaᵀ * n_a * n_b / n
+// The co-moments of the x's are the centered XᵀX, and their co-moments with y
+// the centered Xᵀy, so the slopes are the solution of Cxx * b = Cxy, with the
+// intercept then chosen so the fit passes through the means.
+use serde::{Deserialize, Serialize};
+use crate::StatsError;
+
+// When solving for the coefficients, an x whose co-moment with itself is
+// reduced to below this fraction of its original value by eliminating the
+// other x's is taken to be a linear combination of them, in which case the
+// coefficients aren't unique and we return None.
+const COLLINEARITY_THRESHOLD: f64 = 1e-12;
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct RegressionSummary {
+    pub n: u64,
+    // the mean of each x followed by the mean of y
+    pub means: Vec<f64>,
+    // the co-moments of each pair of variables, in the same order as the means,
+    // stored as a packed lower triangular matrix, see `comoment_index`
+    pub comoments: Vec<f64>,
+}
+
+fn comoment_index(i: usize, j: usize) -> usize {
+    let (i, j) = if i >= j { (i, j) } else { (j, i) };
+    i * (i + 1) / 2 + j
+}
+
+impl RegressionSummary {
+    pub fn new(num_x: usize) -> Self {
+        let dims = num_x + 1;
+        RegressionSummary {
+            n: 0,
+            means: vec![0.0; dims],
+            comoments: vec![0.0; dims * (dims + 1) / 2],
+        }
+    }
+
+    fn point(y: f64, x: &[f64]) -> Self {
+        let mut means = x.to_vec();
+        means.push(y);
+        let dims = means.len();
+        let mut comoments = vec![0.0; dims * (dims + 1) / 2];
+        // as in StatsSummary2D, a co-moment involving an infinite value is NaN
+        for i in 0..dims {
+            for j in 0..=i {
+                if !means[i].is_finite() || !means[j].is_finite() {
+                    comoments[comoment_index(i, j)] = f64::NAN;
+                }
+            }
+        }
+        RegressionSummary { n: 1, means, comoments }
+    }
+
+    pub fn num_x(&self) -> usize {
+        self.means.len() - 1
+    }
+
+    fn y(&self) -> usize {
+        self.num_x()
+    }
+
+    fn comoment(&self, i: usize, j: usize) -> f64 {
+        self.comoments[comoment_index(i, j)]
+    }
+
+    fn is_finite(&self) -> bool {
+        self.means.iter().chain(&self.comoments).all(|v| v.is_finite())
+    }
+
+    /// accumulate a point into a RegressionSummary, the number of x values
+    /// must match the summary
+    /// ```
+    /// use stats_agg::StatsError;
+    /// use stats_agg::regression::RegressionSummary;
+    /// let mut p = RegressionSummary::new(2);
+    /// p.accum(1.0, &[1.0, 2.0]).unwrap();
+    /// assert_eq!(p.accum(1.0, &[1.0]), Err(StatsError::DimensionMismatch));
+    /// assert_eq!(p.accum(f64::MAX, &[1.0, f64::MAX]), Err(StatsError::DoubleOverflow));
+    /// ```
+    pub fn accum(&mut self, y: f64, x: &[f64]) -> Result<(), StatsError> {
+        if x.len() != self.num_x() {
+            return Err(StatsError::DimensionMismatch);
+        }
+        *self = self.combine(&RegressionSummary::point(y, x))?;
+        Ok(())
+    }
+
+    /// combine two RegressionSummaries over the same number of x variables
+    /// ```
+    /// use stats_agg::regression::RegressionSummary;
+    /// let points = [(3.0, [1.0, 0.0]), (5.0, [2.0, 1.0]), (4.0, [0.0, 3.0]), (9.0, [4.0, 2.0])];
+    /// let mut all = RegressionSummary::new(2);
+    /// let mut a = RegressionSummary::new(2);
+    /// let mut b = RegressionSummary::new(2);
+    /// for (i, (y, x)) in points.iter().enumerate() {
+    ///     all.accum(*y, x).unwrap();
+    ///     if i < 2 { a.accum(*y, x).unwrap() } else { b.accum(*y, x).unwrap() }
+    /// }
+    /// let combined = a.combine(&b).unwrap();
+    /// assert_eq!(combined.n, all.n);
+    /// for (c, e) in combined.comoments.iter().zip(&all.comoments) {
+    ///     assert!((c - e).abs() < 1e-12);
+    /// }
+    /// ```
+    pub fn combine(&self, other: &RegressionSummary) -> Result<Self, StatsError> {
+        if self.means.len() != other.means.len() {
+            return Err(StatsError::DimensionMismatch);
+        }
+        if other.n == 0 {
+            return Ok(self.clone());
+        }
+        if self.n == 0 {
+            return Ok(other.clone());
+        }
+        let (na, nb) = (self.n as f64, other.n as f64);
+        let n = na + nb;
+        let delta: Vec<f64> = other.means.iter().zip(&self.means).map(|(b, a)| b - a).collect();
+        let means = self.means.iter().zip(&delta).map(|(a, d)| a + d * nb / n).collect();
+        let mut comoments = Vec::with_capacity(self.comoments.len());
+        for i in 0..delta.len() {
+            for j in 0..=i {
+                let c = self.comoment(i, j) + other.comoment(i, j) + delta[i] * delta[j] * na * nb / n;
+                comoments.push(c);
+            }
+        }
+        let mut combined = RegressionSummary { n: self.n + other.n, means, comoments };
+        if !combined.is_finite() {
+            // we only report overflow if the inputs were finite, otherwise
+            // any co-moment which ended up infinite involved an infinite
+            // input and should be NaN
+            if self.is_finite() && other.is_finite() {
+                return Err(StatsError::DoubleOverflow);
+            }
+            for c in combined.comoments.iter_mut() {
+                if c.is_infinite() {
+                    *c = f64::NAN;
+                }
+            }
+        }
+        Ok(combined)
+    }
+
+    // Solves Cxx * b = Cxy using the Cholesky decomposition of Cxx, which is
+    // symmetric and positive semi-definite. Returns None if it is singular,
+    // ie if there are no more points than x variables, or some x
+    // is a linear combination of the others (including being constant).
+    fn solve(&self) -> Option<Vec<f64>> {
+        if self.n == 0 {
+            return None;
+        }
+        let k = self.num_x();
+        // l holds the lower triangular factor L, packed like the co-moments
+        let mut l = vec![0.0f64; k * (k + 1) / 2];
+        for j in 0..k {
+            let diag = self.comoment(j, j);
+            let d = diag - (0..j).map(|m| l[comoment_index(j, m)].powi(2)).sum::<f64>();
+            // with infinite inputs this is NaN, which we let through as the result
+            if d <= diag * COLLINEARITY_THRESHOLD {
+                return None;
+            }
+            let d = d.sqrt();
+            l[comoment_index(j, j)] = d;
+            for i in j + 1..k {
+                let s = self.comoment(i, j) - (0..j).map(|m| l[comoment_index(i, m)] * l[comoment_index(j, m)]).sum::<f64>();
+                l[comoment_index(i, j)] = s / d;
+            }
+        }
+        // forward substitution for L * z = Cxy, then back substitution for Lᵀ * b = z
+        let mut z = vec![0.0; k];
+        for i in 0..k {
+            let s = self.comoment(i, self.y()) - (0..i).map(|m| l[comoment_index(i, m)] * z[m]).sum::<f64>();
+            z[i] = s / l[comoment_index(i, i)];
+        }
+        let mut b = vec![0.0; k];
+        for i in (0..k).rev() {
+            let s = z[i] - (i + 1..k).map(|m| l[comoment_index(m, i)] * b[m]).sum::<f64>();
+            b[i] = s / l[comoment_index(i, i)];
+        }
+        Some(b)
+    }
+
+    /// returns the coefficient of each x in the least squares fit
+    /// ```
+    /// use stats_agg::regression::RegressionSummary;
+    /// // y = 1 + 2 * x1 - 3 * x2
+    /// let mut p = RegressionSummary::new(2);
+    /// for x in [[0.0, 0.0], [1.0, 0.0], [0.0, 1.0], [2.0, 3.0]] {
+    ///     p.accum(1.0 + 2.0 * x[0] - 3.0 * x[1], &x).unwrap();
+    /// }
+    /// let coefficients = p.coefficients().unwrap();
+    /// assert!((coefficients[0] - 2.0).abs() < 1e-12);
+    /// assert!((coefficients[1] + 3.0).abs() < 1e-12);
+    /// assert!((p.intercept().unwrap() - 1.0).abs() < 1e-12);
+    /// ```
+    pub fn coefficients(&self) -> Option<Vec<f64>> {
+        self.solve()
+    }
+
+    /// returns the intercept of the least squares fit
+    pub fn intercept(&self) -> Option<f64> {
+        let b = self.solve()?;
+        let mean_x = &self.means[..self.num_x()];
+        Some(self.means[self.y()] - b.iter().zip(mean_x).map(|(b, x)| b * x).sum::<f64>())
+    }
+
+    /// returns the coefficient of determination (R²) of the least squares fit,
+    /// the fraction of the variance of y explained by the x's
+    pub fn determination_coeff(&self) -> Option<f64> {
+        let b = self.solve()?;
+        let syy = self.comoment(self.y(), self.y());
+        // as with StatsSummary2D a constant y is perfectly explained
+        if syy == 0.0 {
+            return Some(1.0);
+        }
+        let explained: f64 = b.iter().enumerate().map(|(i, b)| b * self.comoment(i, self.y())).sum();
+        Some(explained / syy)
+    }
+
+    /// returns the value of y the least squares fit predicts for the x's
+    pub fn predict(&self, x: &[f64]) -> Result<Option<f64>, StatsError> {
+        if x.len() != self.num_x() {
+            return Err(StatsError::DimensionMismatch);
+        }
+        let b = match self.solve() {
+            None => return Ok(None),
+            Some(b) => b,
+        };
+        let mean_x = &self.means[..self.num_x()];
+        let offset: f64 = b.iter().zip(x.iter().zip(mean_x)).map(|(b, (x, m))| b * (x - m)).sum();
+        Ok(Some(self.means[self.y()] + offset))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::XYPair;
+    use crate::stats2d::StatsSummary2D;
+    use approx::assert_relative_eq;
+
+    fn points() -> Vec<(f64, [f64; 3])> {
+        vec![
+            (4.0, [1.0, 7.5, -2.0]),
+            (-1.5, [2.0, 3.0, 0.0]),
+            (10.0, [8.0, -1.0, 4.5]),
+            (2.0, [0.5, 0.5, 1.0]),
+            (7.0, [3.0, 6.0, 6.0]),
+            (0.0, [-4.0, 2.0, 3.0]),
+            (3.5, [6.0, 9.0, -1.0]),
+            (5.0, [2.5, 4.0, 2.0]),
+        ]
+    }
+
+    fn summary(points: &[(f64, [f64; 3])]) -> RegressionSummary {
+        let mut s = RegressionSummary::new(3);
+        for (y, x) in points {
+            s.accum(*y, x).unwrap();
+        }
+        s
+    }
+
+    #[test]
+    fn test_exact_fit() {
+        let f = |x: &[f64; 3]| 3.0 - 0.5 * x[0] + 2.0 * x[1] + 0.25 * x[2];
+        let mut s = RegressionSummary::new(3);
+        for (_, x) in points() {
+            s.accum(f(&x), &x).unwrap();
+        }
+        let b = s.coefficients().unwrap();
+        assert_relative_eq!(b[0], -0.5, max_relative = 1e-12);
+        assert_relative_eq!(b[1], 2.0, max_relative = 1e-12);
+        assert_relative_eq!(b[2], 0.25, max_relative = 1e-12);
+        assert_relative_eq!(s.intercept().unwrap(), 3.0, max_relative = 1e-12);
+        assert_relative_eq!(s.determination_coeff().unwrap(), 1.0, max_relative = 1e-12);
+        let x = [10.0, -3.0, 2.0];
+        assert_relative_eq!(s.predict(&x).unwrap().unwrap(), f(&x), max_relative = 1e-12);
+        assert_eq!(s.predict(&[1.0]), Err(StatsError::DimensionMismatch));
+    }
+
+    #[test]
+    fn test_single_x_matches_stats2d() {
+        let mut s = RegressionSummary::new(1);
+        let mut p = StatsSummary2D::new();
+        for (y, x) in points() {
+            s.accum(y, &x[..1]).unwrap();
+            p.accum(XYPair{x: x[0], y}).unwrap();
+        }
+        assert_relative_eq!(s.coefficients().unwrap()[0], p.slope().unwrap(), max_relative = 1e-12);
+        assert_relative_eq!(s.intercept().unwrap(), p.intercept().unwrap(), max_relative = 1e-12);
+        assert_relative_eq!(s.determination_coeff().unwrap(), p.determination_coeff().unwrap(), max_relative = 1e-12);
+        assert_relative_eq!(s.comoment(0, 1), p.sxy, max_relative = 1e-12);
+    }
+
+    #[test]
+    fn test_combine() {
+        let points = points();
+        let all = summary(&points);
+        let (a, b) = points.split_at(3);
+        let combined = summary(a).combine(&summary(b)).unwrap();
+        assert_eq!(combined.n, all.n);
+        for (c, e) in combined.means.iter().zip(&all.means) {
+            assert_relative_eq!(c, e, max_relative = 1e-12);
+        }
+        for (c, e) in combined.comoments.iter().zip(&all.comoments) {
+            assert_relative_eq!(c, e, max_relative = 1e-12);
+        }
+        assert_eq!(summary(a).combine(&RegressionSummary::new(3)).unwrap(), summary(a));
+        assert_eq!(RegressionSummary::new(3).combine(&summary(a)).unwrap(), summary(a));
+        assert_eq!(summary(a).combine(&RegressionSummary::new(2)), Err(StatsError::DimensionMismatch));
+    }
+
+    #[test]
+    fn test_singular() {
+        assert_eq!(RegressionSummary::new(3).coefficients(), None);
+        // fewer points than x variables
+        assert_eq!(summary(&points()[..3]).coefficients(), None);
+        // the third x is the sum of the first two
+        let mut s = RegressionSummary::new(3);
+        for (y, x) in points() {
+            s.accum(y, &[x[0], x[1], x[0] + x[1]]).unwrap();
+        }
+        assert_eq!(s.coefficients(), None);
+        assert_eq!(s.intercept(), None);
+        assert_eq!(s.determination_coeff(), None);
+        assert_eq!(s.predict(&[1.0, 2.0, 3.0]), Ok(None));
+    }
+
+    #[test]
+    fn test_no_x() {
+        // with no x variables the fit is just the mean of y
+        let mut s = RegressionSummary::new(0);
+        for (y, _) in points() {
+            s.accum(y, &[]).unwrap();
+        }
+        assert_eq!(s.coefficients(), Some(vec![]));
+        assert_relative_eq!(s.intercept().unwrap(), 3.75);
+        assert_eq!(s.determination_coeff(), Some(0.0));
+    }
+
+    #[test]
+    fn test_infinite_input() {
+        let mut s = summary(&points());
+        s.accum(1.0, &[f64::INFINITY, 1.0, 1.0]).unwrap();
+        assert!(s.comoment(0, 0).is_nan());
+        assert_eq!(s.means[0], f64::INFINITY);
+        assert!(s.coefficients().unwrap().iter().all(|b| b.is_nan()));
+    }
+}
@@ -1,6 +1,6 @@
 
 use serde::{Deserialize, Serialize};
-use crate::{StatsError, INV_FLOATING_ERROR_THRESHOLD};
+use crate::{compensated_add, Moments, StatsError, INV_FLOATING_ERROR_THRESHOLD};
 
 #[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
 #[repr(C)]
@@ -8,6 +8,9 @@ pub struct StatsSummary1D {
     pub n: u64,
     pub sx: f64,
     pub sxx: f64,
+    pub sx3: f64, // sum((x-sx/n)^3), NaN if unknown, see has_higher_moments()
+    pub sx4: f64, // sum((x-sx/n)^4)
+    pub sxc: f64, // the rounding error in sx, see compensated_add
 }
 
 impl StatsSummary1D{
@@ -15,11 +18,29 @@ impl StatsSummary1D{
         self.n as f64
     }
 
+    // sum(x), corrected for the rounding error
+    fn total(&self) -> f64 {
+        self.sx + self.sxc
+    }
+
+    fn moments(&self) -> Moments {
+        Moments {
+            n: self.n64(),
+            mean: self.total() / self.n64(),
+            m2: self.sxx,
+            m3: self.sx3,
+            m4: self.sx4,
+        }
+    }
+
     pub fn new() -> Self {
         StatsSummary1D {
             n: 0,
             sx: 0.0,
             sxx: 0.0,
+            sx3: 0.0,
+            sx4: 0.0,
+            sxc: 0.0,
         }
     }
 
@@ -27,18 +48,20 @@ impl StatsSummary1D{
     // for this part, we've essentially copied the Postgres implementation found: // https://github.com/postgres/postgres/blob/8bdd6f563aa2456de602e78991e6a9f61b8ec86d/src/backend/utils/adt/float.c#L2813
     // Note that the Youngs-Cramer method relies on the sum((x - Sx/n)^2) for which they derive a recurrence relation which is reflected in the algorithm here:
     // the recurrence relation is: sum((x - Sx/n)^2) = Sxx = Sxx_n-1 + 1/(n(n-1)) * (nx - Sx)^2 
+    // The higher moments are accumulated with Pébay's formulas, see `Moments`.
     pub fn accum(&mut self, p: f64) -> Result<(), StatsError> {
-        let old = StatsSummary1D {
-            n: self.n,
-            sx: self.sx,
-            sxx: self.sxx,
-        };
+        let old = *self;
         self.n += 1;
-        self.sx += p;
+        let (sx, sxc) = compensated_add(self.sx, self.sxc, p);
+        self.sx = sx;
+        self.sxc = sxc;
         if old.n > 0 {
-            let tmpx = p * self.n64() - self.sx;
+            let tmpx = p * self.n64() - self.total();
             let scale = 1.0 / (self.n64() * old.n64());
             self.sxx += tmpx * tmpx * scale;
+            let (sx3, sx4) = old.moments().combine_higher(&Moments::point(p));
+            self.sx3 = sx3;
+            self.sx4 = sx4;
             if self.has_infinite() {
                 if self.check_overflow(&old, p) {
                     return Err(StatsError::DoubleOverflow);
@@ -59,6 +82,13 @@ impl StatsSummary1D{
             }
 
         }
+        // The higher moments don't take part in overflow detection, they can
+        // overflow for inputs where the variance does not and we would rather
+        // return an infinite kurtosis than fail. They are NaN along with sxx.
+        if self.sxx.is_nan() {
+            self.sx3 = f64::NAN;
+            self.sx4 = f64::NAN;
+        }
         Result::Ok(())
     }
 
@@ -87,6 +117,7 @@ impl StatsSummary1D{
     // n = n_old + 1 -> n_old = n - 1
     // Sx = Sx_old + x -> Sx_old = Sx - x
     // sum((x - Sx/n)^2) = Sxx = Sxx_old + 1/(n * n_old) * (nx - Sx)^2  -> Sxx_old = Sxx - 1/(n * n_old) * (nx - Sx)^2
+    // The higher moments are found by inverting Pébay's formulas, see `Moments::remove_higher`.
 
     pub fn remove(&self, p: f64) -> Option<Self> {
         // if we are trying to remove a nan/infinite input, it's time to recalculate.
@@ -109,17 +140,68 @@ impl StatsSummary1D{
             return Some(StatsSummary1D::new());
         }
         
+        let (sx, sxc) = compensated_add(self.sx, self.sxc, -p);
         let mut new = StatsSummary1D {
             n: self.n - 1,
-            sx: self.sx - p,
-            sxx: 0.0, // initialize this for now.
+            sx,
+            sxx: 0.0, // initialize these for now.
+            sx3: 0.0,
+            sx4: 0.0,
+            sxc,
         }; 
-        let tmpx = p * self.n64() - self.sx;
+        let tmpx = p * self.n64() - self.total();
         let scale = 1.0 / (self.n64() * new.n64());
         new.sxx = self.sxx - tmpx * tmpx * scale;
+        let (sx3, sx4) = self.moments().remove_higher(&Moments::point(p), new.moments());
+        new.sx3 = sx3;
+        new.sx4 = sx4;
         Some(new) 
     }
 
+    // A value with an integer weight stands for that many observations of the
+    // value, so it is equivalent to a summary with n = weight and no spread.
+    // Combining with that summary is the weighted form of the Youngs-Cramer
+    // update: Sxx = Sxx_old + w * n_old / (n_old + w) * (x - Sx_old/n_old)^2
+    // and because it is a combine, remove_combined inverts it exactly.
+    fn weighted_point(p: f64, weight: u64) -> Result<Self, StatsError> {
+        let w = weight as f64;
+        let sx = p * w;
+        if sx.is_infinite() && p.is_finite() {
+            return Err(StatsError::DoubleOverflow);
+        }
+        let spread = if p.is_finite() { 0.0 } else { f64::NAN };
+        Ok(StatsSummary1D {
+            n: weight,
+            sx,
+            sxx: spread,
+            sx3: spread,
+            sx4: spread,
+            sxc: 0.0,
+        })
+    }
+
+    // accumulate a value that stands for `weight` observations, equivalent to
+    // calling accum() `weight` times
+    pub fn accum_weighted(&mut self, p: f64, weight: u64) -> Result<(), StatsError> {
+        if weight == 0 {
+            return Ok(());
+        }
+        *self = self.combine(StatsSummary1D::weighted_point(p, weight)?)?;
+        Ok(())
+    }
+
+    // inverse of accum_weighted() for windowed aggregates, as with remove()
+    // returns None if we should re-calculate from scratch
+    pub fn remove_weighted(&self, p: f64, weight: u64) -> Option<Self> {
+        if !p.is_finite() {
+            return None;
+        }
+        if weight == 0 {
+            return Some(*self);
+        }
+        self.remove_combined(StatsSummary1D::weighted_point(p, weight).ok()?)
+    }
+
     // convenience function for creating an aggregate from a vector, currently used mostly for testing.
     pub fn new_from_vec(v: Vec<f64>) -> Result<Self, StatsError> {
         let mut r = StatsSummary1D::new();
@@ -142,12 +224,17 @@ impl StatsSummary1D{
         } else if other.n == 0 {
             return Ok(*self);
         }
-        let tmp = self.sx / self.n64() - other.sx / other.n64();
+        let tmp = self.total() / self.n64() - other.total() / other.n64();
         let n = self.n + other.n;
+        let (sx3, sx4) = self.moments().combine_higher(&other.moments());
+        let (sx, sxc) = compensated_add(self.sx, self.sxc + other.sxc, other.sx);
         let r = StatsSummary1D {
             n: n,
-            sx: self.sx + other.sx,
+            sx,
             sxx: self.sxx + other.sxx + self.n64() * other.n64() * tmp * tmp / n as f64,
+            sx3,
+            sx4,
+            sxc,
         };
         if r.has_infinite() && !self.has_infinite() && !other.has_infinite() {
             return Err(StatsError::DoubleOverflow);
@@ -176,13 +263,20 @@ impl StatsSummary1D{
         if remove.sx / combined.sx > INV_FLOATING_ERROR_THRESHOLD{
             return None;
         }
+        let (sx, sxc) = compensated_add(combined.sx, combined.sxc - remove.sxc, -remove.sx);
         let mut part = StatsSummary1D{
             n: combined.n - remove.n,
-            sx: combined.sx - remove.sx,
-            sxx: 0.0, //just initialize this, for now.
+            sx,
+            sxx: 0.0, //just initialize these, for now.
+            sx3: 0.0,
+            sx4: 0.0,
+            sxc,
         };
-        let tmp = part.sx / part.n64() - remove.sx / remove.n64(); //gets squared so order doesn't matter
+        let tmp = part.total() / part.n64() - remove.total() / remove.n64(); //gets squared so order doesn't matter
         part.sxx = combined.sxx - remove.sxx - part.n64() * remove.n64() * tmp * tmp / combined.n64(); 
+        let (sx3, sx4) = combined.moments().remove_higher(&remove.moments(), part.moments());
+        part.sx3 = sx3;
+        part.sx4 = sx4;
         Some(part)
     }
    
@@ -190,7 +284,7 @@ impl StatsSummary1D{
         if self.n == 0 {
             return None;
         }
-        Some(self.sx / self.n64())
+        Some(self.total() / self.n64())
     }
    
     pub fn count(&self) -> i64 {
@@ -201,7 +295,7 @@ impl StatsSummary1D{
         if self.n == 0 {
             return None;
         }
-        Some(self.sx)
+        Some(self.total())
     }
 
     pub fn var_pop(&self) -> Option<f64> {
@@ -225,6 +319,62 @@ impl StatsSummary1D{
     pub fn stddev_samp(&self) -> Option<f64> {
         Some(self.var_samp()?.sqrt())
     }
+
+    // Summaries stored before the higher moments were tracked don't have
+    // them, they're NaN then, and stay NaN when combined with others. Infinite
+    // inputs also make them NaN, but make sxx NaN along with them.
+    pub fn has_higher_moments(&self) -> bool {
+        !self.sx3.is_nan() || self.sxx.is_nan()
+    }
+
+    // skewness and kurtosis are the 3rd and 4th central moments normalized by
+    // the population or sample variance, the kurtosis is not the excess kurtosis.
+    // They are None if the higher moments are unknown.
+    pub fn skewness_pop(&self) -> Option<f64> {
+        if !self.has_higher_moments() {
+            return None;
+        }
+        Some(self.sx3 / self.n64() / self.var_pop()?.powf(1.5))
+    }
+
+    pub fn skewness_samp(&self) -> Option<f64> {
+        if !self.has_higher_moments() {
+            return None;
+        }
+        Some(self.sx3 / self.n64() / self.var_samp()?.powf(1.5))
+    }
+
+    pub fn kurtosis_pop(&self) -> Option<f64> {
+        if !self.has_higher_moments() {
+            return None;
+        }
+        Some(self.sx4 / self.n64() / self.var_pop()?.powi(2))
+    }
+
+    pub fn kurtosis_samp(&self) -> Option<f64> {
+        if !self.has_higher_moments() {
+            return None;
+        }
+        Some(self.sx4 / self.n64() / self.var_samp()?.powi(2))
+    }
+
+    // the number of standard deviations `value` is from the mean, None if
+    // the standard deviation is 0
+    pub fn z_score_pop(&self, value: f64) -> Option<f64> {
+        let stddev = self.stddev_pop()?;
+        if stddev == 0.0 {
+            return None;
+        }
+        Some((value - self.avg()?) / stddev)
+    }
+
+    pub fn z_score_samp(&self, value: f64) -> Option<f64> {
+        let stddev = self.stddev_samp()?;
+        if stddev == 0.0 {
+            return None;
+        }
+        Some((value - self.avg()?) / stddev)
+    }
 }
 
 #[cfg(test)]
@@ -237,6 +387,23 @@ mod tests {
         assert_eq!(s1.n, s2.n);
         assert_relative_eq!(s1.sx, s2.sx);
         assert_relative_eq!(s1.sxx, s2.sxx);
+        assert_relative_eq!(s1.sx3, s2.sx3, max_relative = 1e-12, epsilon = 1e-10);
+        assert_relative_eq!(s1.sx4, s2.sx4, max_relative = 1e-12);
+    }
+
+    // removal subtracts rather than adds, so loses a little more precision
+    #[track_caller]
+    fn assert_removed_close_enough(s1:&StatsSummary1D, s2:&StatsSummary1D){
+        assert_eq!(s1.n, s2.n);
+        assert_relative_eq!(s1.sx, s2.sx, max_relative = 1e-12);
+        assert_relative_eq!(s1.sxx, s2.sxx, max_relative = 1e-12);
+        assert_relative_eq!(s1.sx3, s2.sx3, max_relative = 1e-12);
+        assert_relative_eq!(s1.sx4, s2.sx4, max_relative = 1e-12);
+    }
+
+    fn central_moment(v: &[f64], k: i32) -> f64 {
+        let mean = v.iter().sum::<f64>() / v.len() as f64;
+        v.iter().map(|x| (x - mean).powi(k)).sum()
     }
 
     #[test]
@@ -246,4 +413,148 @@ mod tests {
         let r = StatsSummary1D::new_from_vec(vec![3.0, 4.0]).unwrap(); 
         assert_close_enough(&q.combine(r).unwrap(), &p);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_higher_moments() {
+        let v = vec![2.0, 8.0, 0.5, 3.0, 4.5, 17.0, 6.0, 1.0];
+        let p = StatsSummary1D::new_from_vec(v.clone()).unwrap();
+        assert_relative_eq!(p.sx3, central_moment(&v, 3), max_relative = 1e-12);
+        assert_relative_eq!(p.sx4, central_moment(&v, 4), max_relative = 1e-12);
+
+        let n = v.len() as f64;
+        let var_pop = central_moment(&v, 2) / n;
+        let var_samp = central_moment(&v, 2) / (n - 1.0);
+        assert_relative_eq!(p.skewness_pop().unwrap(), central_moment(&v, 3) / n / var_pop.powf(1.5), max_relative = 1e-12);
+        assert_relative_eq!(p.skewness_samp().unwrap(), central_moment(&v, 3) / n / var_samp.powf(1.5), max_relative = 1e-12);
+        assert_relative_eq!(p.kurtosis_pop().unwrap(), central_moment(&v, 4) / n / var_pop.powi(2), max_relative = 1e-12);
+        assert_relative_eq!(p.kurtosis_samp().unwrap(), central_moment(&v, 4) / n / var_samp.powi(2), max_relative = 1e-12);
+
+        // combining works for unequal sizes
+        let q = StatsSummary1D::new_from_vec(v[..3].to_vec()).unwrap();
+        let r = StatsSummary1D::new_from_vec(v[3..].to_vec()).unwrap();
+        assert_close_enough(&q.combine(r).unwrap(), &p);
+        assert_close_enough(&r.combine(q).unwrap(), &p);
+
+        // a symmetric distribution has no skew
+        let s = StatsSummary1D::new_from_vec(vec![1.0, 2.0, 3.0, 4.0, 5.0]).unwrap();
+        assert_relative_eq!(s.skewness_pop().unwrap(), 0.0, epsilon = 1e-12);
+        assert_relative_eq!(s.kurtosis_pop().unwrap(), 1.7, max_relative = 1e-12);
+
+        assert_eq!(StatsSummary1D::new().skewness_pop(), None);
+        assert_eq!(StatsSummary1D::new().kurtosis_samp(), None);
+        assert!(StatsSummary1D::new_from_vec(vec![1.0, f64::INFINITY]).unwrap().kurtosis_pop().unwrap().is_nan());
+
+        // unknown moments stay unknown when combined or accumulated into
+        let mut unknown = StatsSummary1D { sx3: f64::NAN, sx4: f64::NAN, ..q };
+        assert_eq!(unknown.skewness_pop(), None);
+        assert_eq!(unknown.kurtosis_samp(), None);
+        assert_eq!(unknown.combine(r).unwrap().skewness_samp(), None);
+        unknown.accum(4.0).unwrap();
+        assert_eq!(unknown.kurtosis_pop(), None);
+        assert_relative_eq!(unknown.var_pop().unwrap(), StatsSummary1D::new_from_vec(vec![2.0, 8.0, 0.5, 4.0]).unwrap().var_pop().unwrap());
+    }
+
+    #[test]
+    fn test_remove() {
+        let v = vec![2.0, 8.0, 0.5, 3.0, 4.5, 17.0, 6.0, 1.0];
+        let p = StatsSummary1D::new_from_vec(v.clone()).unwrap();
+        let q = StatsSummary1D::new_from_vec(v[..7].to_vec()).unwrap();
+        assert_removed_close_enough(&p.remove(1.0).unwrap(), &q);
+
+        let r = StatsSummary1D::new_from_vec(v[5..].to_vec()).unwrap();
+        let q = StatsSummary1D::new_from_vec(v[..5].to_vec()).unwrap();
+        assert_removed_close_enough(&p.remove_combined(r).unwrap(), &q);
+
+        assert_eq!(q.remove_combined(q), Some(StatsSummary1D::new()));
+    }
+
+    #[test]
+    fn test_weighted() {
+        // a weight is the same as repeating the value
+        let mut p = StatsSummary1D::new();
+        p.accum_weighted(2.0, 3).unwrap();
+        p.accum_weighted(5.0, 1).unwrap();
+        p.accum_weighted(100.0, 0).unwrap();
+        p.accum_weighted(-1.0, 2).unwrap();
+        let q = StatsSummary1D::new_from_vec(vec![2.0, 2.0, 2.0, 5.0, -1.0, -1.0]).unwrap();
+        assert_close_enough(&p, &q);
+        assert_eq!(p.avg(), Some(1.5));
+
+        // and can be removed exactly
+        let r = p.remove_weighted(2.0, 3).unwrap();
+        let q = StatsSummary1D::new_from_vec(vec![5.0, -1.0, -1.0]).unwrap();
+        assert_removed_close_enough(&r, &q);
+        assert_eq!(p.remove_weighted(2.0, 0), Some(p));
+        assert_eq!(r.remove_weighted(-1.0, 2).unwrap().remove_weighted(5.0, 1), Some(StatsSummary1D::new()));
+
+        let mut p = StatsSummary1D::new();
+        assert_eq!(p.accum_weighted(f64::MAX, 2), Err(StatsError::DoubleOverflow));
+        p.accum_weighted(f64::INFINITY, 2).unwrap();
+        assert_eq!(p.sum(), Some(f64::INFINITY));
+        assert!(p.var_pop().unwrap().is_nan());
+        assert_eq!(p.remove_weighted(f64::INFINITY, 2), None);
+    }
+
+    #[test]
+    fn test_z_score() {
+        // mean 5, population stddev 2
+        let p = StatsSummary1D::new_from_vec(vec![2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0]).unwrap();
+        assert_eq!(p.z_score_pop(9.0), Some(2.0));
+        assert_eq!(p.z_score_pop(4.0), Some(-0.5));
+        assert_relative_eq!(p.z_score_samp(9.0).unwrap(), 4.0 / (32.0f64 / 7.0).sqrt());
+
+        let constant = StatsSummary1D::new_from_vec(vec![3.0, 3.0]).unwrap();
+        assert_eq!(constant.z_score_pop(3.0), None);
+        assert_eq!(StatsSummary1D::new().z_score_pop(1.0), None);
+    }
+
+    #[test]
+    fn test_compensated_sum() {
+        // summing these naively puts the average out by around 0.008
+        let v = 1e9 + 0.01;
+        let mut p = StatsSummary1D::new();
+        for _ in 0..1_000_000 {
+            p.accum(v).unwrap();
+        }
+        assert_relative_eq!(p.avg().unwrap(), v, max_relative = f64::EPSILON);
+        assert_relative_eq!(p.sum().unwrap(), v * 1e6, max_relative = f64::EPSILON);
+        assert!(p.var_pop().unwrap() < 1e-12);
+
+        let mut q = p;
+        for _ in 0..10 {
+            q = q.combine(q).unwrap();
+        }
+        assert_relative_eq!(q.avg().unwrap(), v, max_relative = f64::EPSILON);
+
+        // infinite sums aren't compensated
+        let p = StatsSummary1D::new_from_vec(vec![1.0, f64::INFINITY, 1e-20]).unwrap();
+        assert_eq!(p.sum(), Some(f64::INFINITY));
+        assert_eq!(p.sxc, 0.0);
+    }
+
+    #[quickcheck]
+    fn compensated_sum_is_close_to_exact(values: Vec<(i32, i32)>, split: usize) {
+        use crate::exact;
+        let values: Vec<f64> = values.into_iter().map(|(b, o)| exact::input(b, o)).collect();
+        if values.is_empty() {
+            return;
+        }
+        let p = StatsSummary1D::new_from_vec(values.clone()).unwrap();
+        exact::assert_close(p.sum().unwrap(), &values);
+        exact::assert_close(p.avg().unwrap() * values.len() as f64, &values);
+
+        let (a, b) = values.split_at(split % values.len());
+        let a = StatsSummary1D::new_from_vec(a.to_vec()).unwrap();
+        let b = StatsSummary1D::new_from_vec(b.to_vec()).unwrap();
+        exact::assert_close(a.combine(b).unwrap().sum().unwrap(), &values);
+
+        // removal is an addition of the negation, so it is compensated too
+        let mut rest = values.clone();
+        let last = rest.pop().unwrap();
+        if let Some(r) = p.remove(last) {
+            if !rest.is_empty() {
+                exact::assert_close(r.sum().unwrap(), &rest);
+            }
+        }
+    }
+}
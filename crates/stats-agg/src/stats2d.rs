@@ -1,7 +1,7 @@
 // 2D stats are based on the Youngs-Cramer implementation in PG here:
 // https://github.com/postgres/postgres/blob/472e518a44eacd9caac7d618f1b6451672ca4481/src/backend/utils/adt/float.c#L3260
 use serde::{Deserialize, Serialize};
-use crate::{StatsError, XYPair, INV_FLOATING_ERROR_THRESHOLD};
+use crate::{compensated_add, Moments, StatsError, XYPair, INV_FLOATING_ERROR_THRESHOLD};
 use flat_serialize_macro::FlatSerializable;
 
 #[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize, FlatSerializable)]
@@ -13,6 +13,12 @@ pub struct StatsSummary2D {
     pub sy: f64,  // sum(y)
     pub syy: f64, // sum((y-sy/n)^2) (sum of squares)
     pub sxy: f64, // sum((x-sx/n)*(y-sy/n)) (sum of products)
+    pub sx3: f64, // sum((x-sx/n)^3), NaN if unknown, see has_higher_moments()
+    pub sx4: f64, // sum((x-sx/n)^4)
+    pub sy3: f64, // sum((y-sy/n)^3)
+    pub sy4: f64, // sum((y-sy/n)^4)
+    pub sxc: f64, // the rounding error in sx, see compensated_add
+    pub syc: f64, // the rounding error in sy
 }
 
 
@@ -25,12 +31,47 @@ impl StatsSummary2D {
             sy: 0.0,
             syy: 0.0,
             sxy: 0.0,
+            sx3: 0.0,
+            sx4: 0.0,
+            sy3: 0.0,
+            sy4: 0.0,
+            sxc: 0.0,
+            syc: 0.0,
         }
     }
 
     fn n64(&self) -> f64 {
         self.n as f64
     }
+
+    // sum(x) and sum(y), corrected for the rounding error
+    fn total_x(&self) -> f64 {
+        self.sx + self.sxc
+    }
+
+    fn total_y(&self) -> f64 {
+        self.sy + self.syc
+    }
+
+    fn x_moments(&self) -> Moments {
+        Moments {
+            n: self.n64(),
+            mean: self.total_x() / self.n64(),
+            m2: self.sxx,
+            m3: self.sx3,
+            m4: self.sx4,
+        }
+    }
+
+    fn y_moments(&self) -> Moments {
+        Moments {
+            n: self.n64(),
+            mean: self.total_y() / self.n64(),
+            m2: self.syy,
+            m3: self.sy3,
+            m4: self.sy4,
+        }
+    }
     /// accumulate an XYPair into a StatsSummary2D
     /// ```
     /// use stats_agg::*;
@@ -47,24 +88,27 @@ impl StatsSummary2D {
     ///
     ///```
     pub fn accum(&mut self, p: XYPair) -> Result<(), StatsError> {
-        let old = StatsSummary2D {
-            n: self.n,
-            sx: self.sx,
-            sxx: self.sxx,
-            sy: self.sy,
-            syy: self.syy,
-            sxy: self.sxy,
-        };
+        let old = *self;
         self.n += 1;
-        self.sx += p.x;
-        self.sy += p.y;
+        let (sx, sxc) = compensated_add(self.sx, self.sxc, p.x);
+        let (sy, syc) = compensated_add(self.sy, self.syc, p.y);
+        self.sx = sx;
+        self.sxc = sxc;
+        self.sy = sy;
+        self.syc = syc;
         if old.n > 0 {
-            let tmpx = p.x * self.n64() - self.sx;
-            let tmpy = p.y * self.n64() - self.sy;
+            let tmpx = p.x * self.n64() - self.total_x();
+            let tmpy = p.y * self.n64() - self.total_y();
             let scale = 1.0 / (self.n64() * old.n64());
             self.sxx += tmpx * tmpx * scale;
             self.syy += tmpy * tmpy * scale;
             self.sxy += tmpx * tmpy * scale;
+            let (sx3, sx4) = old.x_moments().combine_higher(&Moments::point(p.x));
+            let (sy3, sy4) = old.y_moments().combine_higher(&Moments::point(p.y));
+            self.sx3 = sx3;
+            self.sx4 = sx4;
+            self.sy3 = sy3;
+            self.sy4 = sy4;
             if self.has_infinite() {
                 if self.check_overflow(&old, p) {
                     return Err(StatsError::DoubleOverflow);
@@ -95,6 +139,16 @@ impl StatsSummary2D {
                 self.sxy = f64::NAN;
             }
         }
+        // as in StatsSummary1D the higher moments don't take part in overflow
+        // detection, and are NaN along with the sums of squares
+        if self.sxx.is_nan() {
+            self.sx3 = f64::NAN;
+            self.sx4 = f64::NAN;
+        }
+        if self.syy.is_nan() {
+            self.sy3 = f64::NAN;
+            self.sy4 = f64::NAN;
+        }
         Result::Ok(())
     }
     fn has_infinite(&self) -> bool {
@@ -134,6 +188,7 @@ impl StatsSummary2D {
     // sum((x - Sx/n)^2) = Sxx = Sxx_old + 1/(n * n_old) * (nx - Sx)^2  -> Sxx_old = Sxx - 1/(n * n_old) * (nx - Sx)^2
     // Sy / Syy analogous
     // sum((x - Sx/n)(y - Sy/n)) = Sxy = Sxy_old + 1/(n * n_old) * (nx - Sx) * (ny - Sy)  -> Sxy_old = Sxy - 1/(n * n_old) * (nx - Sx) * (ny - Sy)
+    // The higher moments are found by inverting Pébay's formulas, see `Moments::remove_higher`.
     pub fn remove(&self, p: XYPair) -> Option<Self> {
         // if we are trying to remove a nan/infinite input, it's time to recalculate.
         if !p.x.is_finite() || !p.y.is_finite(){
@@ -156,23 +211,96 @@ impl StatsSummary2D {
             return Some(StatsSummary2D::new());
         }
 
+        let (sx, sxc) = compensated_add(self.sx, self.sxc, -p.x);
+        let (sy, syc) = compensated_add(self.sy, self.syc, -p.y);
         let mut new = StatsSummary2D {
             n: self.n - 1,
-            sx: self.sx - p.x,
-            sy: self.sy - p.y,
+            sx,
+            sy,
             sxx: 0.0, // initialize these for now.
             syy: 0.0,
             sxy: 0.0,
+            sx3: 0.0,
+            sx4: 0.0,
+            sy3: 0.0,
+            sy4: 0.0,
+            sxc,
+            syc,
         };
-        let tmpx = p.x * self.n64() - self.sx;
-        let tmpy = p.y * self.n64() - self.sy;
+        let tmpx = p.x * self.n64() - self.total_x();
+        let tmpy = p.y * self.n64() - self.total_y();
         let scale = 1.0 / (self.n64() * new.n64());
         new.sxx = self.sxx - tmpx * tmpx * scale;
         new.syy = self.syy - tmpy * tmpy * scale;
         new.sxy = self.sxy - tmpx * tmpy * scale;
+        let (sx3, sx4) = self.x_moments().remove_higher(&Moments::point(p.x), new.x_moments());
+        let (sy3, sy4) = self.y_moments().remove_higher(&Moments::point(p.y), new.y_moments());
+        new.sx3 = sx3;
+        new.sx4 = sx4;
+        new.sy3 = sy3;
+        new.sy4 = sy4;
         Some(new)
     }
 
+    // As in StatsSummary1D, a pair with an integer weight stands for that many
+    // observations of the pair, so weighted accumulation is a combine with a
+    // summary with n = weight and no spread, and remove_combined inverts it.
+    fn weighted_point(p: &XYPair, weight: u64) -> Result<Self, StatsError> {
+        let w = weight as f64;
+        let (sx, sy) = (p.x * w, p.y * w);
+        if (sx.is_infinite() && p.x.is_finite()) || (sy.is_infinite() && p.y.is_finite()) {
+            return Err(StatsError::DoubleOverflow);
+        }
+        let x_spread = if p.x.is_finite() { 0.0 } else { f64::NAN };
+        let y_spread = if p.y.is_finite() { 0.0 } else { f64::NAN };
+        Ok(StatsSummary2D {
+            n: weight,
+            sx,
+            sxx: x_spread,
+            sy,
+            syy: y_spread,
+            sxy: x_spread + y_spread,
+            sx3: x_spread,
+            sx4: x_spread,
+            sy3: y_spread,
+            sy4: y_spread,
+            sxc: 0.0,
+            syc: 0.0,
+        })
+    }
+
+    /// accumulate an XYPair that stands for `weight` observations into a StatsSummary2D,
+    /// equivalent to calling accum() `weight` times
+    /// ```
+    /// use stats_agg::stats2d::StatsSummary2D;
+    /// use stats_agg::XYPair;
+    /// let mut p = StatsSummary2D::new();
+    /// p.accum_weighted(XYPair{x:1.0, y:1.0,}, 2).unwrap();
+    /// p.accum_weighted(XYPair{x:3.0, y:5.0,}, 1).unwrap();
+    /// let q = StatsSummary2D::new_from_vec(vec![XYPair{x:1.0, y:1.0,}, XYPair{x:1.0, y:1.0,}, XYPair{x:3.0, y:5.0,}]).unwrap();
+    /// assert_eq!(p.slope(), q.slope());
+    /// assert_eq!(p.avg(), q.avg());
+    ///```
+    pub fn accum_weighted(&mut self, p: XYPair, weight: u64) -> Result<(), StatsError> {
+        if weight == 0 {
+            return Ok(());
+        }
+        *self = self.combine(StatsSummary2D::weighted_point(&p, weight)?)?;
+        Ok(())
+    }
+
+    // inverse of accum_weighted() for windowed aggregates, as with remove()
+    // returns None if we should re-calculate from scratch
+    pub fn remove_weighted(&self, p: XYPair, weight: u64) -> Option<Self> {
+        if !p.x.is_finite() || !p.y.is_finite() {
+            return None;
+        }
+        if weight == 0 {
+            return Some(*self);
+        }
+        self.remove_combined(StatsSummary2D::weighted_point(&p, weight).ok()?)
+    }
+
     ///create a StatsSummary2D from a vector of XYPairs
     /// ```
     /// use stats_agg::stats2d::StatsSummary2D;
@@ -207,6 +335,7 @@ impl StatsSummary2D {
     //      sxx = sxx1 + sxx2 + n1 * n2 * (sx1/n1 - sx2/n2)^2 / n
     //      sy / syy analogous
     //      sxy = sxy1 + sxy2 + n1 * n2 * (sx1/n1 - sx2/n2) * (sy1/n1 - sy2/n2) / n
+    // and the higher moments using Pébay's formulas, see `Moments`
     pub fn combine(&self, other: StatsSummary2D) -> Result<Self, StatsError> {
         // TODO: think about whether we want to just modify &self in place here for perf
         // reasons. This is also a set of weird questions around the Rust compiler, so
@@ -220,16 +349,26 @@ impl StatsSummary2D {
         } else if other.n == 0 {
             return Ok(*self);
         }
-        let tmpx = self.sx / self.n64() - other.sx / other.n64();
-        let tmpy = self.sy / self.n64() - other.sy / other.n64();
+        let tmpx = self.total_x() / self.n64() - other.total_x() / other.n64();
+        let tmpy = self.total_y() / self.n64() - other.total_y() / other.n64();
         let n = self.n + other.n;
+        let (sx3, sx4) = self.x_moments().combine_higher(&other.x_moments());
+        let (sy3, sy4) = self.y_moments().combine_higher(&other.y_moments());
+        let (sx, sxc) = compensated_add(self.sx, self.sxc + other.sxc, other.sx);
+        let (sy, syc) = compensated_add(self.sy, self.syc + other.syc, other.sy);
         let r = StatsSummary2D {
             n: n,
-            sx: self.sx + other.sx,
+            sx,
             sxx: self.sxx + other.sxx + self.n64() * other.n64() * tmpx * tmpx / n as f64,
-            sy: self.sy + other.sy,
+            sy,
             syy: self.syy + other.syy + self.n64() * other.n64() * tmpy * tmpy / n as f64,
             sxy: self.sxy + other.sxy + self.n64() * other.n64() * tmpx * tmpy / n as f64,
+            sx3,
+            sx4,
+            sy3,
+            sy4,
+            sxc,
+            syc,
         };
         if r.has_infinite() && !self.has_infinite() && !other.has_infinite() {
             return Err(StatsError::DoubleOverflow);
@@ -258,19 +397,33 @@ impl StatsSummary2D {
         if remove.sx / combined.sx > INV_FLOATING_ERROR_THRESHOLD || remove.sy / combined.sy > INV_FLOATING_ERROR_THRESHOLD {
             return None;
         }
+        let (sx, sxc) = compensated_add(combined.sx, combined.sxc - remove.sxc, -remove.sx);
+        let (sy, syc) = compensated_add(combined.sy, combined.syc - remove.syc, -remove.sy);
         let mut part = StatsSummary2D{
             n: combined.n - remove.n,
-            sx: combined.sx - remove.sx,
-            sy: combined.sy - remove.sy,
+            sx,
+            sy,
             sxx: 0.0, //just initialize these, for now.
             syy: 0.0,
             sxy: 0.0,
+            sx3: 0.0,
+            sx4: 0.0,
+            sy3: 0.0,
+            sy4: 0.0,
+            sxc,
+            syc,
         };
-        let tmpx = part.sx / part.n64() - remove.sx / remove.n64(); //gets squared so order doesn't matter
-        let tmpy = part.sy / part.n64() - remove.sy / remove.n64();
+        let tmpx = part.total_x() / part.n64() - remove.total_x() / remove.n64(); //gets squared so order doesn't matter
+        let tmpy = part.total_y() / part.n64() - remove.total_y() / remove.n64();
         part.sxx = combined.sxx - remove.sxx - part.n64() * remove.n64() * tmpx * tmpx / combined.n64();
         part.syy = combined.syy - remove.syy - part.n64() * remove.n64() * tmpy * tmpy / combined.n64();
         part.sxy = combined.sxy - remove.sxy - part.n64() * remove.n64() * tmpx * tmpy / combined.n64();
+        let (sx3, sx4) = combined.x_moments().remove_higher(&remove.x_moments(), part.x_moments());
+        let (sy3, sy4) = combined.y_moments().remove_higher(&remove.y_moments(), part.y_moments());
+        part.sx3 = sx3;
+        part.sx4 = sx4;
+        part.sy3 = sy3;
+        part.sy4 = sy4;
         Some(part)
     }
 
@@ -290,8 +443,12 @@ impl StatsSummary2D {
         // Y + C - Sy/N - C
         // Y - Sy/N
     pub fn offset(&mut self, offset: XYPair) -> Result<(), StatsError> {
-        self.sx = self.sx + self.n64() * offset.x;
-        self.sy = self.sy + self.n64() * offset.y;
+        let (sx, sxc) = compensated_add(self.sx, self.sxc, self.n64() * offset.x);
+        let (sy, syc) = compensated_add(self.sy, self.syc, self.n64() * offset.y);
+        self.sx = sx;
+        self.sxc = sxc;
+        self.sy = sy;
+        self.syc = syc;
         if self.has_infinite() && offset.x.is_finite() && offset.y.is_finite(){
             return Err(StatsError::DoubleOverflow);
         }
@@ -358,8 +515,8 @@ impl StatsSummary2D {
             return None;
         }
         Some(XYPair {
-            x: self.sx / self.n64(),
-            y: self.sy / self.n64(),
+            x: self.total_x() / self.n64(),
+            y: self.total_y() / self.n64(),
         })
     }
     ///returns the count of inputs as an i64
@@ -394,8 +551,8 @@ impl StatsSummary2D {
             return None;
         }
         Some(XYPair {
-            x: self.sx,
-            y: self.sy,
+            x: self.total_x(),
+            y: self.total_y(),
         })
     }
 
@@ -437,6 +594,65 @@ impl StatsSummary2D {
         })
     }
 
+    // As for StatsSummary1D, the higher moments are NaN for summaries stored
+    // before they were tracked, while infinite inputs make sxx or syy NaN too.
+    pub fn has_higher_moments(&self) -> bool {
+        !self.sx3.is_nan() || self.sxx.is_nan() || self.syy.is_nan()
+    }
+
+    ///returns the population skewness of both the independent and dependent variables as an XYPair,
+    ///ie the third central moment divided by the population variance to the 3/2 power,
+    ///or None if the higher moments are unknown
+    pub fn skewness_pop(&self) -> Option<XYPair> {
+        if !self.has_higher_moments() {
+            return None;
+        }
+        let var = self.var_pop()?;
+        Some(XYPair {
+            x: self.sx3 / self.n64() / var.x.powf(1.5),
+            y: self.sy3 / self.n64() / var.y.powf(1.5),
+        })
+    }
+
+    ///returns the sample skewness of both the independent and dependent variables as an XYPair,
+    ///ie the third central moment divided by the sample variance to the 3/2 power
+    pub fn skewness_samp(&self) -> Option<XYPair> {
+        if !self.has_higher_moments() {
+            return None;
+        }
+        let var = self.var_samp()?;
+        Some(XYPair {
+            x: self.sx3 / self.n64() / var.x.powf(1.5),
+            y: self.sy3 / self.n64() / var.y.powf(1.5),
+        })
+    }
+
+    ///returns the population kurtosis (not the excess kurtosis) of both the independent and dependent
+    ///variables as an XYPair, ie the fourth central moment divided by the square of the population variance
+    pub fn kurtosis_pop(&self) -> Option<XYPair> {
+        if !self.has_higher_moments() {
+            return None;
+        }
+        let var = self.var_pop()?;
+        Some(XYPair {
+            x: self.sx4 / self.n64() / var.x.powi(2),
+            y: self.sy4 / self.n64() / var.y.powi(2),
+        })
+    }
+
+    ///returns the sample kurtosis (not the excess kurtosis) of both the independent and dependent
+    ///variables as an XYPair, ie the fourth central moment divided by the square of the sample variance
+    pub fn kurtosis_samp(&self) -> Option<XYPair> {
+        if !self.has_higher_moments() {
+            return None;
+        }
+        let var = self.var_samp()?;
+        Some(XYPair {
+            x: self.sx4 / self.n64() / var.x.powi(2),
+            y: self.sy4 / self.n64() / var.y.powi(2),
+        })
+    }
+
     /// returns the correlation coefficient, which is the covariance / (stddev(x) * stddev(y))
     /// Note that it makes no difference whether we choose the sample or
     /// population covariance and stddev, because we end up with a canceling n or n-1 term. This
@@ -464,7 +680,7 @@ impl StatsSummary2D {
         if self.n == 0 || self.sxx == 0.0 {
             return None;
         }
-        Some((self.sy - self.sx * self.sxy / self.sxx) / self.n64())
+        Some((self.total_y() - self.total_x() * self.sxy / self.sxx) / self.n64())
     }
 
     /// returns the x intercept of the least squares fit line
@@ -474,7 +690,7 @@ impl StatsSummary2D {
     pub fn x_intercept(&self) -> Option<f64> {
         // vertical line does have an x intercept
         if self.n > 1 && self.sxx == 0.0 {
-            return Some(self.sx / self.n64())
+            return Some(self.total_x() / self.n64())
         }
         // horizontal lines have no x intercepts
         if self.syy == 0.0 {
@@ -495,6 +711,56 @@ impl StatsSummary2D {
         Some(self.sxy * self.sxy / (self.sxx * self.syy))
     }
 
+    /// returns the y value of the least squares fit line at `x`
+    pub fn predict(&self, x: f64) -> Option<f64> {
+        Some(self.intercept()? + self.slope()? * x)
+    }
+
+    // the estimate of the variance of the residuals about the fit line, which
+    // needs at least 3 points as the line itself uses up 2 degrees of freedom
+    fn residual_variance(&self) -> Option<f64> {
+        if self.n <= 2 || self.sxx == 0.0 {
+            return None;
+        }
+        // rounding can make this slightly negative for a perfect fit
+        let residual_sum_squares = (self.syy - self.sxy * self.sxy / self.sxx).max(0.0);
+        Some(residual_sum_squares / (self.n64() - 2.0))
+    }
+
+    /// returns the standard error of the slope of the least squares fit line
+    pub fn slope_stderr(&self) -> Option<f64> {
+        Some((self.residual_variance()? / self.sxx).sqrt())
+    }
+
+    /// returns the standard error of the intercept of the least squares fit line
+    pub fn intercept_stderr(&self) -> Option<f64> {
+        let mean_x = self.total_x() / self.n64();
+        Some((self.residual_variance()? * (1.0 / self.n64() + mean_x * mean_x / self.sxx)).sqrt())
+    }
+
+    /// returns the (lower, upper) bounds of the interval in which a new
+    /// observation of y at `x` is expected to fall with probability
+    /// `confidence`, assuming normally distributed residuals
+    ///```
+    /// use stats_agg::stats2d::StatsSummary2D;
+    /// use stats_agg::XYPair;
+    /// let p = StatsSummary2D::new_from_vec(vec![XYPair{y:2.0, x:1.0,}, XYPair{y:4.5, x:2.0,}, XYPair{y:5.5, x:3.0,}]).unwrap();
+    /// let (lower, upper) = p.prediction_interval(4.0, 0.95).unwrap();
+    /// assert!(lower < p.predict(4.0).unwrap() && p.predict(4.0).unwrap() < upper);
+    /// // the interval needs at least 3 points and a confidence between 0 and 1
+    /// assert!(p.prediction_interval(4.0, 1.0).is_none());
+    /// ```
+    pub fn prediction_interval(&self, x: f64, confidence: f64) -> Option<(f64, f64)> {
+        if !(confidence > 0.0 && confidence < 1.0) {
+            return None;
+        }
+        let prediction = self.predict(x)?;
+        let dx = x - self.total_x() / self.n64();
+        let stderr = (self.residual_variance()? * (1.0 + 1.0 / self.n64() + dx * dx / self.sxx)).sqrt();
+        let t = crate::hypothesis::student_t_quantile((1.0 + confidence) / 2.0, self.n64() - 2.0);
+        Some((prediction - t * stderr, prediction + t * stderr))
+    }
+
     ///returns the sample covariance: (sumxy()/n-1)
     ///```
     /// use stats_agg::stats2d::StatsSummary2D;
@@ -536,6 +802,99 @@ impl StatsSummary2D {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::stats1d::StatsSummary1D;
+    use approx::assert_relative_eq;
+
+    fn pairs() -> Vec<XYPair> {
+        vec![
+            XYPair{x: 2.0, y: -1.0},
+            XYPair{x: 8.0, y: 3.5},
+            XYPair{x: 0.5, y: 2.0},
+            XYPair{x: 3.0, y: 10.0},
+            XYPair{x: 4.5, y: 0.0},
+            XYPair{x: 17.0, y: 1.0},
+            XYPair{x: 6.0, y: 4.0},
+        ]
+    }
+
+    // the x and y moments should match those of the 1D summaries of each
+    #[track_caller]
+    fn assert_moments_match(p: &StatsSummary2D, v: &[XYPair]) {
+        let x = StatsSummary1D::new_from_vec(v.iter().map(|p| p.x).collect()).unwrap();
+        let y = StatsSummary1D::new_from_vec(v.iter().map(|p| p.y).collect()).unwrap();
+        assert_relative_eq!(p.sx3, x.sx3, max_relative = 1e-12, epsilon = 1e-9);
+        assert_relative_eq!(p.sx4, x.sx4, max_relative = 1e-12);
+        assert_relative_eq!(p.sy3, y.sx3, max_relative = 1e-12, epsilon = 1e-9);
+        assert_relative_eq!(p.sy4, y.sx4, max_relative = 1e-12);
+        assert_relative_eq!(p.skewness_pop().unwrap().x, x.skewness_pop().unwrap(), max_relative = 1e-12, epsilon = 1e-9);
+        assert_relative_eq!(p.skewness_samp().unwrap().y, y.skewness_samp().unwrap(), max_relative = 1e-12, epsilon = 1e-9);
+        assert_relative_eq!(p.kurtosis_pop().unwrap().y, y.kurtosis_pop().unwrap(), max_relative = 1e-12);
+        assert_relative_eq!(p.kurtosis_samp().unwrap().x, x.kurtosis_samp().unwrap(), max_relative = 1e-12);
+    }
+
+    #[test]
+    fn test_higher_moments(){
+        let v = pairs();
+        let p = StatsSummary2D::new_from_vec(pairs()).unwrap();
+        assert_moments_match(&p, &v);
+
+        let q = StatsSummary2D::new_from_vec(pairs().into_iter().take(2).collect()).unwrap();
+        let r = StatsSummary2D::new_from_vec(pairs().into_iter().skip(2).collect()).unwrap();
+        assert_moments_match(&q.combine(r).unwrap(), &v);
+
+        let removed = p.remove(XYPair{x: 6.0, y: 4.0}).unwrap();
+        assert_moments_match(&removed, &v[..6]);
+        let removed = p.remove_combined(r).unwrap();
+        assert_moments_match(&removed, &v[..2]);
+
+        assert!(StatsSummary2D::new().skewness_pop().is_none());
+        assert!(StatsSummary2D::new().kurtosis_samp().is_none());
+
+        // unknown moments stay unknown when combined
+        let unknown = StatsSummary2D { sx3: f64::NAN, sx4: f64::NAN, sy3: f64::NAN, sy4: f64::NAN, ..q };
+        assert!(unknown.skewness_samp().is_none());
+        assert!(unknown.combine(r).unwrap().kurtosis_pop().is_none());
+        assert_relative_eq!(unknown.combine(r).unwrap().slope().unwrap(), p.slope().unwrap(), max_relative = 1e-12);
+    }
+
+    #[test]
+    fn test_weighted(){
+        // pre-bucketed data, each pair stands for `weight` observations
+        let mut p = StatsSummary2D::new();
+        p.accum_weighted(XYPair{x: 1.0, y: 3.0}, 3).unwrap();
+        p.accum_weighted(XYPair{x: 2.0, y: 4.0}, 1).unwrap();
+        p.accum_weighted(XYPair{x: 4.0, y: 11.0}, 2).unwrap();
+        let repeated = vec![
+            XYPair{x: 1.0, y: 3.0},
+            XYPair{x: 1.0, y: 3.0},
+            XYPair{x: 1.0, y: 3.0},
+            XYPair{x: 2.0, y: 4.0},
+            XYPair{x: 4.0, y: 11.0},
+            XYPair{x: 4.0, y: 11.0},
+        ];
+        let q = StatsSummary2D::new_from_vec(repeated).unwrap();
+        assert_eq!(p.n, q.n);
+        assert_relative_eq!(p.slope().unwrap(), q.slope().unwrap(), max_relative = 1e-12);
+        assert_relative_eq!(p.intercept().unwrap(), q.intercept().unwrap(), max_relative = 1e-12);
+        assert_relative_eq!(p.sxy, q.sxy, max_relative = 1e-12);
+        assert_relative_eq!(p.sy4, q.sy4, max_relative = 1e-12);
+
+        let r = p.remove_weighted(XYPair{x: 4.0, y: 11.0}, 2).unwrap();
+        let q = StatsSummary2D::new_from_vec(vec![
+            XYPair{x: 1.0, y: 3.0},
+            XYPair{x: 1.0, y: 3.0},
+            XYPair{x: 1.0, y: 3.0},
+            XYPair{x: 2.0, y: 4.0},
+        ]).unwrap();
+        assert_eq!(r.n, q.n);
+        assert_relative_eq!(r.slope().unwrap(), q.slope().unwrap(), max_relative = 1e-12);
+        assert_relative_eq!(r.syy, q.syy, max_relative = 1e-12);
+
+        assert_eq!(
+            StatsSummary2D::new().accum_weighted(XYPair{x: 1.0, y: f64::MAX}, 2),
+            Err(StatsError::DoubleOverflow)
+        );
+    }
     #[test]
     fn test_linear(){
         let p = StatsSummary2D::new_from_vec(vec![XYPair{y:2.0, x:1.0,}, XYPair{y:4.0, x:2.0,}, XYPair{y:6.0, x:3.0,}]).unwrap();
@@ -569,4 +928,51 @@ mod tests {
         assert_eq!(p.intercept().unwrap(), 2.0);
         assert_eq!(p.x_intercept(), None);
     }
+
+    #[test]
+    fn test_regression_errors() {
+        // reference values computed with mpmath
+        let p = StatsSummary2D::new_from_vec(pairs()).unwrap();
+        assert_relative_eq!(p.slope_stderr().unwrap(), 0.293_394_613_282_931_7, max_relative = 1e-12);
+        assert_relative_eq!(p.intercept_stderr().unwrap(), 2.279_378_631_532_344_5, max_relative = 1e-12);
+        assert_relative_eq!(p.predict(10.0).unwrap(), 2.417_352_134_743_439, max_relative = 1e-12);
+        let (lower, upper) = p.prediction_interval(10.0, 0.90).unwrap();
+        assert_relative_eq!(lower, -6.461_966_833_078_618, max_relative = 1e-10);
+        assert_relative_eq!(upper, 11.296_671_102_565_496, max_relative = 1e-10);
+
+        // a perfect fit has no error
+        let p = StatsSummary2D::new_from_vec(vec![XYPair{y:2.0, x:2.0,}, XYPair{y:4.0, x:3.0,}, XYPair{y:6.0, x:4.0,}]).unwrap();
+        assert_eq!(p.slope_stderr(), Some(0.0));
+        assert_eq!(p.intercept_stderr(), Some(0.0));
+        assert_eq!(p.prediction_interval(5.0, 0.99), Some((8.0, 8.0)));
+
+        // two points always fit perfectly, so tell us nothing about the error
+        let p = StatsSummary2D::new_from_vec(vec![XYPair{y:2.0, x:2.0,}, XYPair{y:4.0, x:3.0,}]).unwrap();
+        assert_eq!(p.predict(4.0), Some(6.0));
+        assert_eq!(p.slope_stderr(), None);
+        assert_eq!(p.prediction_interval(4.0, 0.5), None);
+        assert_eq!(StatsSummary2D::new().predict(1.0), None);
+    }
+
+    #[quickcheck]
+    fn compensated_sums_are_close_to_exact(values: Vec<(i32, i32, i32)>, split: usize) {
+        use crate::exact;
+        if values.is_empty() {
+            return;
+        }
+        let xs: Vec<f64> = values.iter().map(|&(b, o, _)| exact::input(b, o)).collect();
+        let ys: Vec<f64> = values.iter().map(|&(b, _, o)| exact::input(b, o)).collect();
+        let pairs = || xs.iter().zip(&ys).map(|(&x, &y)| XYPair{x, y});
+        let p = StatsSummary2D::new_from_vec(pairs().collect()).unwrap();
+        exact::assert_close(p.sum().unwrap().x, &xs);
+        exact::assert_close(p.sum().unwrap().y, &ys);
+        exact::assert_close(p.avg().unwrap().y * values.len() as f64, &ys);
+
+        let split = split % values.len();
+        let a = StatsSummary2D::new_from_vec(pairs().take(split).collect()).unwrap();
+        let b = StatsSummary2D::new_from_vec(pairs().skip(split).collect()).unwrap();
+        let combined = a.combine(b).unwrap();
+        exact::assert_close(combined.sum().unwrap().x, &xs);
+        exact::assert_close(combined.sum().unwrap().y, &ys);
+    }
 }
@@ -168,6 +168,45 @@ pub enum TimeSeriesError {
     InsufficientDataToExtrapolate,
 }
 
+// How the values of the points in a bucket are combined when downsampling
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AggregationMethod {
+    Average,
+    Min,
+    Max,
+    Sum,
+    Count,
+    First,
+    Last,
+    // the continuous percentile, interpolating between the closest values like
+    // percentile_cont(), so Percentile(0.5) is the median
+    Percentile(f64),
+}
+
+impl AggregationMethod {
+    // Aggregates the values of a non-empty bucket of points in time order
+    pub fn aggregate(&self, points: &[TSPoint]) -> f64 {
+        assert!(!points.is_empty());
+        let values = points.iter().map(|pt| pt.val);
+        match self {
+            AggregationMethod::Average => values.sum::<f64>() / points.len() as f64,
+            AggregationMethod::Min => values.fold(f64::INFINITY, f64::min),
+            AggregationMethod::Max => values.fold(f64::NEG_INFINITY, f64::max),
+            AggregationMethod::Sum => values.sum(),
+            AggregationMethod::Count => points.len() as f64,
+            AggregationMethod::First => points[0].val,
+            AggregationMethod::Last => points[points.len() - 1].val,
+            AggregationMethod::Percentile(percentile) => {
+                let mut sorted: Vec<f64> = values.collect();
+                sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+                let rank = percentile * (sorted.len() - 1) as f64;
+                let (lower, upper) = (rank.floor() as usize, rank.ceil() as usize);
+                sorted[lower] + (sorted[upper] - sorted[lower]) * (rank - lower as f64)
+            },
+        }
+    }
+}
+
 pub enum GapfillMethod {
     LOCF,
     Linear,
@@ -253,60 +292,55 @@ impl ExplicitTimeSeries {
         }
     }
 
-    // This function will normalize a time range by averaging the values in `downsample_interval`
-    // sized buckets.  Any gaps will be filled via the given method and will use the downsampled
-    // values as the relevant points for LOCF or interpolation.
-    pub fn downsample_and_gapfill_to_normal_form(&self, downsample_interval: i64, gapfill_method: GapfillMethod) -> Result<NormalTimeSeries, TimeSeriesError> {
-        if !self.ordered {
-            return Err(TimeSeriesError::OrderedDataExpected);
-        }
+    // This function will normalize a time range by aggregating the values in `downsample_interval`
+    // sized buckets, starting from the first point, using `aggregation`.  Any gaps will be filled via
+    // the given method and will use the downsampled values as the relevant points for LOCF or
+    // interpolation, except when counting, where empty buckets are simply 0.
+    pub fn downsample_and_gapfill_to_normal_form(&self, downsample_interval: i64, aggregation: AggregationMethod, gapfill_method: GapfillMethod) -> Result<NormalTimeSeries, TimeSeriesError> {
         if self.points.len() < 2 || self.points.last().unwrap().ts - self.points.first().unwrap().ts < downsample_interval {
             return Err(TimeSeriesError::InsufficientDataToExtrapolate);
         }
+        self.resample(downsample_interval, self.points.first().unwrap().ts, aggregation, gapfill_method)
+    }
+
+    // Like downsample_and_gapfill_to_normal_form(), but with the buckets aligned so that one of
+    // them starts at `origin`, as time_bucket() does, so that the buckets don't depend on the
+    // time of the first point.  The result starts at the bucket containing the first point, and
+    // is empty if there are no points.
+    pub fn resample(&self, interval: i64, origin: i64, aggregation: AggregationMethod, gapfill_method: GapfillMethod) -> Result<NormalTimeSeries, TimeSeriesError> {
+        if !self.ordered {
+            return Err(TimeSeriesError::OrderedDataExpected);
+        }
 
+        let start_ts = match self.points.first() {
+            Some(first) => first.ts - (first.ts - origin).rem_euclid(interval),
+            None => origin,
+        };
         let mut result = NormalTimeSeries {
-            start_ts: self.points.first().unwrap().ts,
-            step_interval: downsample_interval,
+            start_ts,
+            step_interval: interval,
             values: Vec::<f64>::new(),
         };
 
-        let mut bound = self.points.first().unwrap().ts + downsample_interval;
-        let mut sum = 0.0;
-        let mut count = 0;
-        let mut gap_count = 0;
-        for pt in self.points.iter() {
-            if pt.ts < bound {
-                sum += pt.val;
-                count += 1;
-            } else {
-                assert!(count != 0);
-                let new_val = sum / count as f64;
-                // If we missed any intervals prior to the current one, fill in the gap here
-                if gap_count != 0 {
-                    gapfill_method.fill_normalized_series_gap(&mut result, gap_count, new_val);
-                    gap_count = 0;
-                }
-                result.values.push(new_val);
-                sum = pt.val;
-                count = 1;
-                bound += downsample_interval;
-                // If the current point doesn't go in the bucket immediately following the one
-                // we just created, update the bound until we find the correct bucket and track
-                // the number of empty buckets we skip over
-                while bound < pt.ts {
-                    bound += downsample_interval;
-                    gap_count += 1;
+        let bucket_of = |pt: &TSPoint| (pt.ts - start_ts) / interval;
+        let mut remaining = &self.points[..];
+        while !remaining.is_empty() {
+            // as the points are sorted each bucket's points are contiguous
+            let bucket = bucket_of(&remaining[0]);
+            let len = remaining.partition_point(|pt| bucket_of(pt) == bucket);
+            let new_val = aggregation.aggregate(&remaining[..len]);
+            // If we missed any buckets prior to the current one, fill in the gap here
+            let gap_count = bucket - result.values.len() as i64;
+            if gap_count > 0 {
+                if let AggregationMethod::Count = aggregation {
+                    result.values.extend((0..gap_count).map(|_| 0.0));
+                } else {
+                    gapfill_method.fill_normalized_series_gap(&mut result, gap_count as i32, new_val);
                 }
             }
+            result.values.push(new_val);
+            remaining = &remaining[len..];
         }
-        // This will handle the last interval, since we always exit the above loop in the middle
-        // of accumulating an interval
-        assert!(count > 0);
-        let new_val = sum / count as f64;
-        if gap_count != 0 {
-            gapfill_method.fill_normalized_series_gap(&mut result, gap_count, new_val);
-        }
-        result.values.push(sum / count as f64);
         Ok(result)
     }
 }
@@ -593,4 +627,85 @@ mod tests {
         assert!(!matches!(ad, TimeSeries::Normal(_)));
         assert!(if let TimeSeries::Explicit(inner) = ad {inner.ordered} else {false});
     }
+
+    fn explicit_series(points: &[(i64, f64)]) -> ExplicitTimeSeries {
+        ExplicitTimeSeries {
+            ordered: true,
+            points: points.iter().map(|&(ts, val)| TSPoint{ts, val}).collect(),
+        }
+    }
+
+    #[test]
+    fn test_downsample_aggregations() {
+        // buckets [0, 10), [10, 20), [20, 30) (empty) and [30, 40)
+        let series = explicit_series(&[(0, 4.0), (3, 1.0), (9, 7.0), (10, 2.0), (30, 6.0), (35, 8.0)]);
+        let downsample = |aggregation| {
+            series.downsample_and_gapfill_to_normal_form(10, aggregation, GapfillMethod::LOCF).ok().unwrap()
+        };
+
+        let average = downsample(AggregationMethod::Average);
+        assert_eq!(average.start_ts, 0);
+        assert_eq!(average.step_interval, 10);
+        assert_eq!(average.values, vec![4.0, 2.0, 2.0, 7.0]);
+        assert_eq!(downsample(AggregationMethod::Min).values, vec![1.0, 2.0, 2.0, 6.0]);
+        assert_eq!(downsample(AggregationMethod::Max).values, vec![7.0, 2.0, 2.0, 8.0]);
+        assert_eq!(downsample(AggregationMethod::Sum).values, vec![12.0, 2.0, 2.0, 14.0]);
+        assert_eq!(downsample(AggregationMethod::First).values, vec![4.0, 2.0, 2.0, 6.0]);
+        assert_eq!(downsample(AggregationMethod::Last).values, vec![7.0, 2.0, 2.0, 8.0]);
+        assert_eq!(downsample(AggregationMethod::Percentile(0.5)).values, vec![4.0, 2.0, 2.0, 7.0]);
+        assert_eq!(downsample(AggregationMethod::Percentile(0.75)).values, vec![5.5, 2.0, 2.0, 7.5]);
+        // empty buckets have a count of 0 rather than being gapfilled
+        assert_eq!(downsample(AggregationMethod::Count).values, vec![3.0, 1.0, 0.0, 2.0]);
+
+        let linear = series.downsample_and_gapfill_to_normal_form(10, AggregationMethod::Max, GapfillMethod::Linear).ok().unwrap();
+        assert_eq!(linear.values, vec![7.0, 2.0, 5.0, 8.0]);
+    }
+
+    #[test]
+    fn test_downsample_point_on_bucket_boundary() {
+        // the point at 30 starts the 4th bucket, after two empty ones
+        let series = explicit_series(&[(0, 1.0), (5, 3.0), (30, 10.0)]);
+        let result = series.downsample_and_gapfill_to_normal_form(10, AggregationMethod::Average, GapfillMethod::Linear).ok().unwrap();
+        assert_eq!(result.values.len(), 4);
+        assert_eq!(result.values[0], 2.0);
+        assert!((result.values[1] - 14.0 / 3.0).abs() < 1e-12);
+        assert!((result.values[2] - 22.0 / 3.0).abs() < 1e-12);
+        assert_eq!(result.values[3], 10.0);
+
+        let unordered = ExplicitTimeSeries{ordered: false, ..series.clone()};
+        assert!(matches!(
+            unordered.downsample_and_gapfill_to_normal_form(10, AggregationMethod::Average, GapfillMethod::LOCF),
+            Err(TimeSeriesError::OrderedDataExpected)
+        ));
+        assert!(matches!(
+            series.downsample_and_gapfill_to_normal_form(100, AggregationMethod::Average, GapfillMethod::LOCF),
+            Err(TimeSeriesError::InsufficientDataToExtrapolate)
+        ));
+    }
+
+    #[test]
+    fn test_resample_aligned_to_origin() {
+        // buckets [0, 10), [10, 20) and [20, 30) whichever point comes first
+        let series = explicit_series(&[(7, 1.0), (12, 2.0), (18, 4.0), (25, 5.0)]);
+        let result = series.resample(10, 0, AggregationMethod::Average, GapfillMethod::LOCF).ok().unwrap();
+        assert_eq!(result.start_ts, 0);
+        assert_eq!(result.values, vec![1.0, 3.0, 5.0]);
+        let result = series.resample(10, 5, AggregationMethod::Average, GapfillMethod::LOCF).ok().unwrap();
+        assert_eq!(result.start_ts, 5);
+        assert_eq!(result.values, vec![1.5, 4.0, 5.0]);
+        // times before the origin are aligned too
+        let series = explicit_series(&[(-15, 1.0), (-2, 3.0)]);
+        let result = series.resample(10, 0, AggregationMethod::Sum, GapfillMethod::LOCF).ok().unwrap();
+        assert_eq!(result.start_ts, -20);
+        assert_eq!(result.values, vec![1.0, 3.0]);
+
+        // short series give a single bucket, or none
+        let series = explicit_series(&[(3, 1.0), (4, 2.0)]);
+        let result = series.resample(10, 0, AggregationMethod::Max, GapfillMethod::LOCF).ok().unwrap();
+        assert_eq!((result.start_ts, result.values), (0, vec![2.0]));
+        let result = explicit_series(&[(3, 1.0)]).resample(10, 0, AggregationMethod::Count, GapfillMethod::LOCF).ok().unwrap();
+        assert_eq!(result.values, vec![1.0]);
+        let result = explicit_series(&[]).resample(10, 0, AggregationMethod::Average, GapfillMethod::LOCF).ok().unwrap();
+        assert!(result.values.is_empty());
+    }
 }
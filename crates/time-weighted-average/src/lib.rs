@@ -8,6 +8,8 @@ use flat_serialize_macro::FlatSerializable;
 pub enum TimeWeightMethod {
     LOCF = 0,
     Linear,
+    NOCB,
+    Nearest,
 }
 
 #[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
@@ -16,6 +18,15 @@ pub struct TimeWeightSummary {
     pub first: TSPoint,
     pub last: TSPoint,
     pub w_sum: f64,
+    // the centred second moment, ie the integral of the squared distance of
+    // the curve from the time weighted average, used for the time weighted
+    // variance. `None` for summaries stored before it was tracked.
+    pub m2: Option<f64>,
+    // gaps between points longer than this contribute no weight, the total
+    // length of such gaps is tracked in `excluded` so they also don't count
+    // towards the duration of the summary
+    pub max_gap: Option<i64>,
+    pub excluded: i64,
 }
 
 #[derive(PartialEq, Debug)]
@@ -23,18 +34,51 @@ pub enum TimeWeightError {
     OrderError,
     DoubleOverflow, // do we need to do this?
     MethodMismatch,
+    MaxGapMismatch,
     InterpolateMissingPoint,
     ZeroDuration,
+    MissingVariance,
+    PrecisionLoss,
     EmptyIterator,
 }
 
 impl TimeWeightSummary {
     pub fn new(pt: TSPoint, method: TimeWeightMethod) -> Self {
+        TimeWeightSummary::with_max_gap(pt, method, None)
+    }
+
+    pub fn with_max_gap(pt: TSPoint, method: TimeWeightMethod, max_gap: Option<i64>) -> Self {
         TimeWeightSummary {
             method: method,
             first: pt,
             last: pt,
             w_sum: 0.0,
+            m2: Some(0.0),
+            max_gap,
+            excluded: 0,
+        }
+    }
+
+    fn exceeds_max_gap(&self, start: i64, end: i64) -> bool {
+        matches!(self.max_gap, Some(max_gap) if end - start > max_gap)
+    }
+
+    // the moments of the segment between two adjacent points, and the
+    // duration to exclude if they are too far apart
+    fn segment(&self, first: TSPoint, second: TSPoint) -> (Moments, i64) {
+        if self.exceeds_max_gap(first.ts, second.ts) {
+            (Moments::default(), second.ts - first.ts)
+        } else {
+            (self.method.moments(first, second), 0)
+        }
+    }
+
+    // a missing m2 is taken as 0 here, callers keep it missing in the result
+    fn moments(&self) -> Moments {
+        Moments {
+            duration: self.covered_duration() as f64,
+            w_sum: self.w_sum,
+            m2: self.m2.unwrap_or(0.0),
         }
     }
 
@@ -47,11 +91,66 @@ impl TimeWeightSummary {
             // see discussion at https://github.com/timescale/timescale-analytics/discussions/65
             return Ok(());
         }
-        self.w_sum += self.method.weighted_sum(self.last, pt);
+        let (segment, excluded) = self.segment(self.last, pt);
+        self.m2 = self.m2.map(|_| self.moments().combine(&segment).m2);
+        self.w_sum += segment.w_sum;
+        self.excluded += excluded;
         self.last = pt;
         Ok(())
     }
 
+    // Removes the first point from the summary, `next` must be the point that
+    // followed it. Together with accum() this lets a summary be maintained
+    // over a moving window, without rescanning the rest of the window. Fails
+    // with PrecisionLoss, leaving the summary unchanged, if too little would
+    // be left to compute it accurately, in which case it should be rebuilt
+    // from the remaining points instead.
+    pub fn remove_first(&mut self, next: TSPoint) -> Result<(), TimeWeightError> {
+        if next.ts <= self.first.ts || next.ts > self.last.ts {
+            return Err(TimeWeightError::OrderError);
+        }
+        let (segment, excluded) = self.segment(self.first, next);
+        let rest = self
+            .moments()
+            .remove(&segment)
+            .ok_or(TimeWeightError::PrecisionLoss)?;
+        self.w_sum = rest.w_sum;
+        self.m2 = self.m2.map(|_| rest.m2);
+        self.excluded -= excluded;
+        self.first = next;
+        Ok(())
+    }
+
+    // The inverse of combine(), removes `removed`, which must be the first of
+    // the summaries combined into this one. `next` must be the first point of
+    // the summary that followed it. Like remove_first() this can fail with
+    // PrecisionLoss.
+    pub fn remove_first_summary(
+        &mut self,
+        removed: &TimeWeightSummary,
+        next: TSPoint,
+    ) -> Result<(), TimeWeightError> {
+        if self.method != removed.method {
+            return Err(TimeWeightError::MethodMismatch);
+        }
+        if self.max_gap != removed.max_gap {
+            return Err(TimeWeightError::MaxGapMismatch);
+        }
+        if removed.first != self.first || next.ts <= removed.last.ts || next.ts > self.last.ts {
+            return Err(TimeWeightError::OrderError);
+        }
+        let (segment, excluded) = self.segment(removed.last, next);
+        let rest = self
+            .moments()
+            .remove(&removed.moments().combine(&segment))
+            .ok_or(TimeWeightError::PrecisionLoss)?;
+        self.w_sum = rest.w_sum;
+        self.m2 = self.m2.and(removed.m2).map(|_| rest.m2);
+        self.excluded -= removed.excluded + excluded;
+        self.first = next;
+        Ok(())
+    }
+
     // This combine function is different than some other combine functions as it requires disjoint time ranges in order to work
     // correctly. The aggregate will never be parallel safe in the Postgres formulation because of this. However in the continuous
     // aggregate context (and potentially in a multinode context) where we can be sure of disjoint time ranges, this will work.
@@ -61,17 +160,28 @@ impl TimeWeightSummary {
         if self.method != next.method {
             return Err(TimeWeightError::MethodMismatch);
         }
+        if self.max_gap != next.max_gap {
+            return Err(TimeWeightError::MaxGapMismatch);
+        }
         if self.last.ts >= next.first.ts {
             // this combine function should always be pulling from disjoint sets, so duplicate values do not need to be handled
             // as we do in accum() (where duplicates are ignored) here we throw an error, because duplicate values should
             // always have been sorted into one or another bucket, and it means that the bounds of our buckets were wrong.
             return Err(TimeWeightError::OrderError);
         }
+        let (segment, excluded) = self.segment(self.last, next.first);
+        let m2 = self
+            .m2
+            .and(next.m2)
+            .map(|_| self.moments().combine(&segment).combine(&next.moments()).m2);
         let new = TimeWeightSummary {
             method: self.method,
             first: self.first,
             last: next.last,
-            w_sum: self.w_sum + next.w_sum + self.method.weighted_sum(self.last, next.first),
+            w_sum: self.w_sum + next.w_sum + segment.w_sum,
+            m2,
+            max_gap: self.max_gap,
+            excluded: self.excluded + next.excluded + excluded,
         };
         Ok(new)
     }
@@ -79,13 +189,21 @@ impl TimeWeightSummary {
     pub fn new_from_sorted_iter<'a>(
         iter: impl IntoIterator<Item = &'a TSPoint>,
         method: TimeWeightMethod,
+    ) -> Result<TimeWeightSummary, TimeWeightError> {
+        TimeWeightSummary::new_from_sorted_iter_with_max_gap(iter, method, None)
+    }
+
+    pub fn new_from_sorted_iter_with_max_gap<'a>(
+        iter: impl IntoIterator<Item = &'a TSPoint>,
+        method: TimeWeightMethod,
+        max_gap: Option<i64>,
     ) -> Result<TimeWeightSummary, TimeWeightError> {
         let mut t = iter.into_iter();
         let mut s = match t.next() {
             None => {
                 return Err(TimeWeightError::EmptyIterator);
             }
-            Some(val) => TimeWeightSummary::new(*val, method),
+            Some(val) => TimeWeightSummary::with_max_gap(*val, method, max_gap),
         };
         for p in t {
             s.accum(*p)?;
@@ -116,7 +234,7 @@ impl TimeWeightSummary {
     /// 1. The start_prev parameter is optional, but if a start is provided a previous point must be
     /// provided (for both linear and locf weighting methods).
     /// 2. The end_next parameter is also optional, if an end is provided and the locf weighting
-    /// method is specified, a next parameter isn't needed, with the linear method, the next
+    /// method is specified, a next parameter isn't needed, with the other methods, the next
     /// point is needed and we will error if it is not provided.
     pub fn with_bounds(
         &self,
@@ -129,7 +247,7 @@ impl TimeWeightSummary {
         }
 
         if let Some((end, next)) = end_next {
-            calc = self.with_next(end, next)?
+            calc = calc.with_next(end, next)?
         }
         Ok(calc)
     }
@@ -143,14 +261,27 @@ impl TimeWeightSummary {
             return Ok(*self);
         }
 
+        // the previous point is too far away to say anything about the value
+        // before our first point
+        if self.exceeds_max_gap(prev.ts, self.first.ts) {
+            return Ok(TimeWeightSummary {
+                first: TSPoint { ts: target_start, val: self.first.val },
+                excluded: self.excluded + (self.first.ts - target_start),
+                ..*self
+            });
+        }
+
         let new_first = self
             .method
             .interpolate(prev, Some(self.first), target_start)?;
-        let w_sum = self.w_sum + self.method.weighted_sum(new_first, self.first);
+        let before = self
+            .method
+            .moments_within(prev, self.first, target_start, self.first.ts);
 
         Ok(TimeWeightSummary {
             first: new_first,
-            w_sum,
+            w_sum: self.w_sum + before.w_sum,
+            m2: self.m2.map(|_| before.combine(&self.moments()).m2),
             ..*self
         })
     }
@@ -171,23 +302,96 @@ impl TimeWeightSummary {
             }
         }
 
+        // without a next point we only know the gap is at least as long as
+        // the extension, which may already be enough to exclude it
+        let gap_end = next.map_or(target_end, |next| next.ts);
+        if self.exceeds_max_gap(self.last.ts, gap_end) {
+            return Ok(TimeWeightSummary {
+                last: TSPoint { ts: target_end, val: self.last.val },
+                excluded: self.excluded + (target_end - self.last.ts),
+                ..*self
+            });
+        }
+
         let new_last = self.method.interpolate(self.last, next, target_end)?;
-        let w_sum = self.w_sum + self.method.weighted_sum(self.last, new_last);
+        let after = match next {
+            Some(next) => self
+                .method
+                .moments_within(self.last, next, self.last.ts, target_end),
+            None => self.method.moments(self.last, new_last),
+        };
 
         Ok(TimeWeightSummary {
             last: new_last,
-            w_sum,
+            w_sum: self.w_sum + after.w_sum,
+            m2: self.m2.map(|_| self.moments().combine(&after).m2),
             ..*self
         })
     }
 
+    /// Extend a summary of the points within `[interval_start, interval_start + interval_len)`
+    /// to the edges of that interval, using the last point of the summary for the previous
+    /// interval and the first point of the summary for the next one. This is meant for
+    /// bucketed data where those summaries come from neighbouring buckets. Without a `prev`
+    /// the summary starts at its first point; without a `next` LOCF summaries are still
+    /// carried to the end of the interval, but linear ones stop at their last point.
+    pub fn interpolate(
+        &self,
+        interval_start: i64,
+        interval_len: i64,
+        prev: Option<&TimeWeightSummary>,
+        next: Option<&TimeWeightSummary>,
+    ) -> Result<Self, TimeWeightError> {
+        let interval_end = interval_start + interval_len;
+        if self.first.ts < interval_start || self.last.ts > interval_end {
+            return Err(TimeWeightError::OrderError);
+        }
+        for neighbour in prev.iter().chain(next.iter()) {
+            if neighbour.method != self.method {
+                return Err(TimeWeightError::MethodMismatch);
+            }
+        }
+        let start_prev = prev.map(|prev| (interval_start, prev.last));
+        let end_next = match (self.method, next) {
+            (_, Some(next)) => Some((interval_end, Some(next.first))),
+            (TimeWeightMethod::LOCF, None) => Some((interval_end, None)),
+            (_, None) => None,
+        };
+        self.with_bounds(start_prev, end_next)
+    }
+
+    ///The total time covered by the summary, excluding any gaps longer than `max_gap`.
+    pub fn covered_duration(&self) -> i64 {
+        self.last.ts - self.first.ts - self.excluded
+    }
+
+    ///Evaluate the time weighted integral, ie the area under the curve, in units of value * microseconds.
+    pub fn time_weighted_integral(&self) -> f64 {
+        self.w_sum
+    }
+
     ///Evaluate the time_weighted_average from the summary.
     pub fn time_weighted_average(&self) -> Result<f64, TimeWeightError> {
-        if self.last.ts == self.first.ts {
+        let duration = self.covered_duration();
+        if duration == 0 {
             return Err(TimeWeightError::ZeroDuration);
         }
-        let duration = (self.last.ts - self.first.ts) as f64;
-        Ok(self.w_sum / duration)
+        Ok(self.w_sum / duration as f64)
+    }
+
+    ///Evaluate the time weighted (population) variance from the summary.
+    pub fn time_weighted_variance(&self) -> Result<f64, TimeWeightError> {
+        let duration = self.covered_duration();
+        if duration == 0 {
+            return Err(TimeWeightError::ZeroDuration);
+        }
+        let m2 = self.m2.ok_or(TimeWeightError::MissingVariance)?;
+        // removing points can leave this slightly negative due to rounding
+        Ok((m2 / duration as f64).max(0.0))
+    }
+
+    pub fn time_weighted_stddev(&self) -> Result<f64, TimeWeightError> {
+        self.time_weighted_variance().map(f64::sqrt)
     }
 }
 
@@ -211,9 +415,22 @@ impl TimeWeightMethod {
                 (TimeWeightMethod::Linear, Some(second)) => {
                     first.interpolate_linear(&second, target).unwrap()
                 }
-                (TimeWeightMethod::Linear, None) => {
-                    return Err(TimeWeightError::InterpolateMissingPoint)
+                (TimeWeightMethod::NOCB, Some(second)) => {
+                    if target == first.ts {
+                        first.val
+                    } else {
+                        second.val
+                    }
                 }
+                // ties go to the earlier point
+                (TimeWeightMethod::Nearest, Some(second)) => {
+                    if target - first.ts <= second.ts - target {
+                        first.val
+                    } else {
+                        second.val
+                    }
+                }
+                (_, None) => return Err(TimeWeightError::InterpolateMissingPoint),
             },
         };
         Ok(pt)
@@ -232,10 +449,126 @@ impl TimeWeightMethod {
             //midpoint of the two.
             //TODO: Stable midpoint calc? http://www.open-std.org/jtc1/sc22/wg21/docs/papers/2018/p0811r2.html
             TimeWeightMethod::Linear => (first.val + second.val) / 2.0 * duration,
+            TimeWeightMethod::NOCB => second.val * duration,
+            //the nearest value steps from first to second halfway between them,
+            //so the area is the same as for linear interpolation
+            TimeWeightMethod::Nearest => (first.val + second.val) / 2.0 * duration,
+        }
+    }
+
+    // the moments of the curve between two points
+    fn moments(&self, first: TSPoint, second: TSPoint) -> Moments {
+        debug_assert!(second.ts > first.ts);
+        let duration = (second.ts - first.ts) as f64;
+        let diff = second.val - first.val;
+        let m2 = match self {
+            TimeWeightMethod::LOCF | TimeWeightMethod::NOCB => 0.0,
+            // a line has the variance of a uniform distribution over its range
+            TimeWeightMethod::Linear => diff * diff * duration / 12.0,
+            // half the time at either value
+            TimeWeightMethod::Nearest => diff * diff * duration / 4.0,
+        };
+        Moments {
+            duration,
+            w_sum: self.weighted_sum(first, second),
+            m2,
+        }
+    }
+
+    // The weighted sum and moments over [from, to], a subrange of [first.ts, second.ts],
+    // of the curve the method draws between two adjacent points. For most
+    // methods this is the weighted sum between the interpolated points at
+    // either end, but the curve for Nearest steps in the middle, so the
+    // value at the ends doesn't determine the area between them.
+    fn moments_within(&self, first: TSPoint, second: TSPoint, from: i64, to: i64) -> Moments {
+        debug_assert!(first.ts <= from && from <= to && to <= second.ts);
+        if from == to {
+            return Moments::default();
+        }
+        match self {
+            TimeWeightMethod::Nearest => {
+                let mid = (first.ts as f64 + second.ts as f64) / 2.0;
+                let (from, to) = (from as f64, to as f64);
+                let constant = |duration: f64, val: f64| Moments {
+                    duration,
+                    w_sum: duration * val,
+                    m2: 0.0,
+                };
+                let first_part = constant((mid.min(to) - from).max(0.0), first.val);
+                let second_part = constant((to - mid.max(from)).max(0.0), second.val);
+                first_part.combine(&second_part)
+            }
+            _ => {
+                let start = self.interpolate(first, Some(second), from).unwrap();
+                let end = self.interpolate(first, Some(second), to).unwrap();
+                self.moments(start, end)
+            }
         }
     }
 }
 
+// The duration, weighted sum and centred second moment of a stretch of the
+// curve. These combine with the parallel update of Chan et al, like the sums
+// in stats-agg, which stays accurate when the values are large compared to
+// their spread, unlike subtracting the square of the mean from the mean of
+// the squares.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Moments {
+    duration: f64,
+    w_sum: f64,
+    m2: f64,
+}
+
+impl Moments {
+    // the moments of this stretch followed by `other`
+    fn combine(&self, other: &Moments) -> Moments {
+        if self.duration == 0.0 {
+            return Moments { m2: self.m2 + other.m2, ..*other };
+        }
+        if other.duration == 0.0 {
+            return Moments { m2: self.m2 + other.m2, ..*self };
+        }
+        let duration = self.duration + other.duration;
+        let delta = other.w_sum / other.duration - self.w_sum / self.duration;
+        Moments {
+            duration,
+            w_sum: self.w_sum + other.w_sum,
+            m2: self.m2 + other.m2 + delta * delta * self.duration * other.duration / duration,
+        }
+    }
+
+    // The inverse of combine(), the moments of the rest once `removed` is
+    // taken off the front. As in StatsSummary1D::remove(), if the rest is
+    // small next to what is removed its rounding error could be large compared
+    // to it, so this returns None and it should be recomputed from scratch.
+    fn remove(&self, removed: &Moments) -> Option<Moments> {
+        let duration = self.duration - removed.duration;
+        if duration == 0.0 {
+            // nothing that carries any weight is left
+            return Some(Moments::default());
+        }
+        let w_sum = self.w_sum - removed.w_sum;
+        if loses_precision(w_sum, removed.w_sum) {
+            return None;
+        }
+        let m2 = if removed.duration == 0.0 {
+            self.m2 - removed.m2
+        } else {
+            let delta = w_sum / duration - removed.w_sum / removed.duration;
+            self.m2 - removed.m2 - delta * delta * duration * removed.duration / self.duration
+        };
+        Some(Moments { duration, w_sum, m2 })
+    }
+}
+
+// see INV_FLOATING_ERROR_THRESHOLD in stats-agg
+const INV_FLOATING_ERROR_THRESHOLD: f64 = 0.99;
+
+fn loses_precision(remaining: f64, removed: f64) -> bool {
+    !remaining.is_finite()
+        || remaining.abs() < (1.0 - INV_FLOATING_ERROR_THRESHOLD) * removed.abs()
+}
+
 #[cfg(test)]
 mod tests {
     use crate::*;
@@ -314,6 +647,8 @@ mod tests {
     fn test_new_from_sorted_iter() {
         new_from_sorted_iter_test(TimeWeightMethod::LOCF);
         new_from_sorted_iter_test(TimeWeightMethod::Linear);
+        new_from_sorted_iter_test(TimeWeightMethod::NOCB);
+        new_from_sorted_iter_test(TimeWeightMethod::Nearest);
     }
 
     fn combine_test(t: TimeWeightMethod) {
@@ -348,6 +683,8 @@ mod tests {
     fn test_combine() {
         combine_test(TimeWeightMethod::LOCF);
         combine_test(TimeWeightMethod::Linear);
+        combine_test(TimeWeightMethod::NOCB);
+        combine_test(TimeWeightMethod::Nearest);
     }
 
     fn order_accum_test(t: TimeWeightMethod) {
@@ -408,6 +745,8 @@ mod tests {
     fn test_order_accum() {
         order_accum_test(TimeWeightMethod::LOCF);
         order_accum_test(TimeWeightMethod::Linear);
+        order_accum_test(TimeWeightMethod::NOCB);
+        order_accum_test(TimeWeightMethod::Nearest);
     }
 
     fn order_combine_test(t: TimeWeightMethod) {
@@ -435,6 +774,8 @@ mod tests {
     fn test_order_combine() {
         order_combine_test(TimeWeightMethod::LOCF);
         order_combine_test(TimeWeightMethod::Linear);
+        order_combine_test(TimeWeightMethod::NOCB);
+        order_combine_test(TimeWeightMethod::Nearest);
     }
 
     fn combine_sorted_iter_test(t: TimeWeightMethod) {
@@ -495,6 +836,8 @@ mod tests {
     fn test_combine_sorted_iter() {
         combine_sorted_iter_test(TimeWeightMethod::LOCF);
         combine_sorted_iter_test(TimeWeightMethod::Linear);
+        combine_sorted_iter_test(TimeWeightMethod::NOCB);
+        combine_sorted_iter_test(TimeWeightMethod::Nearest);
     }
 
     #[test]
@@ -542,6 +885,81 @@ mod tests {
 
         let linear = TimeWeightMethod::Linear.weighted_sum(pt1, pt2);
         assert_eq!(linear, -100.0);
+
+        let nocb = TimeWeightMethod::NOCB.weighted_sum(pt1, pt2);
+        assert_eq!(nocb, -400.0);
+
+        let nearest = TimeWeightMethod::Nearest.weighted_sum(pt1, pt2);
+        assert_eq!(nearest, -100.0);
+    }
+
+    #[test]
+    fn test_weighted_sum_within() {
+        let pt1 = TSPoint { ts: 10, val: 20.0 };
+        let pt2 = TSPoint { ts: 20, val: 40.0 };
+        for &method in &[
+            TimeWeightMethod::LOCF,
+            TimeWeightMethod::Linear,
+            TimeWeightMethod::NOCB,
+            TimeWeightMethod::Nearest,
+        ] {
+            // the whole range is the same as the weighted sum
+            assert_eq!(
+                method.moments_within(pt1, pt2, 10, 20).w_sum,
+                method.weighted_sum(pt1, pt2)
+            );
+            // splitting the range doesn't change the total
+            assert_eq!(
+                method.moments_within(pt1, pt2, 10, 12).w_sum
+                    + method.moments_within(pt1, pt2, 12, 17).w_sum
+                    + method.moments_within(pt1, pt2, 17, 20).w_sum,
+                method.weighted_sum(pt1, pt2)
+            );
+        }
+        assert_eq!(TimeWeightMethod::LOCF.moments_within(pt1, pt2, 12, 17).w_sum, 100.0);
+        assert_eq!(TimeWeightMethod::Linear.moments_within(pt1, pt2, 12, 17).w_sum, 5.0 * 29.0);
+        assert_eq!(TimeWeightMethod::NOCB.moments_within(pt1, pt2, 12, 17).w_sum, 200.0);
+        assert_eq!(TimeWeightMethod::Nearest.moments_within(pt1, pt2, 12, 17).w_sum, 3.0 * 20.0 + 2.0 * 40.0);
+    }
+
+    #[test]
+    fn test_nocb_and_nearest() {
+        let points = vec![
+            &TSPoint { ts: 10, val: 1.0 },
+            &TSPoint { ts: 20, val: 2.0 },
+            &TSPoint { ts: 40, val: 4.0 },
+        ];
+        let nocb = TimeWeightSummary::new_from_sorted_iter(points.clone(), TimeWeightMethod::NOCB).unwrap();
+        assert_eq!(nocb.w_sum, 10.0 * 2.0 + 20.0 * 4.0);
+        let nearest = TimeWeightSummary::new_from_sorted_iter(points, TimeWeightMethod::Nearest).unwrap();
+        assert_eq!(nearest.w_sum, 5.0 * 1.0 + 5.0 * 2.0 + 10.0 * 2.0 + 10.0 * 4.0);
+
+        // NOCB takes the value of the next point
+        let prev = TSPoint { ts: 0, val: 5.0 };
+        let next = TSPoint { ts: 60, val: 6.0 };
+        let bounded = nocb.with_bounds(Some((5, prev)), Some((50, Some(next)))).unwrap();
+        assert_eq!(bounded.first, TSPoint { ts: 5, val: 1.0 });
+        assert_eq!(bounded.last, TSPoint { ts: 50, val: 6.0 });
+        assert_eq!(bounded.w_sum, nocb.w_sum + 5.0 * 1.0 + 10.0 * 6.0);
+
+        // Nearest steps halfway between the points, at 5 and 50 here
+        let bounded = nearest.with_bounds(Some((2, prev)), Some((55, Some(next)))).unwrap();
+        assert_eq!(bounded.first, TSPoint { ts: 2, val: 5.0 });
+        assert_eq!(bounded.last, TSPoint { ts: 55, val: 6.0 });
+        assert_eq!(
+            bounded.w_sum,
+            nearest.w_sum + 3.0 * 5.0 + 5.0 * 1.0 + 10.0 * 4.0 + 5.0 * 6.0
+        );
+
+        // neither can extend past the last point without a next point
+        assert_eq!(
+            nocb.with_bounds(None, Some((50, None))).unwrap_err(),
+            TimeWeightError::InterpolateMissingPoint
+        );
+        assert_eq!(
+            nearest.with_bounds(None, Some((50, None))).unwrap_err(),
+            TimeWeightError::InterpolateMissingPoint
+        );
     }
 
     fn with_prev_common_test(t: TimeWeightMethod) {
@@ -638,6 +1056,8 @@ mod tests {
 
         // now some common tests:
         with_prev_common_test(TimeWeightMethod::Linear);
+        with_prev_common_test(TimeWeightMethod::NOCB);
+        with_prev_common_test(TimeWeightMethod::Nearest);
         with_prev_common_test(TimeWeightMethod::LOCF);
     }
 
@@ -745,9 +1165,393 @@ mod tests {
 
         // now some common tests:
         with_next_common_test(TimeWeightMethod::Linear);
+        with_next_common_test(TimeWeightMethod::NOCB);
+        with_next_common_test(TimeWeightMethod::Nearest);
         with_next_common_test(TimeWeightMethod::LOCF);
     }
 
+    #[test]
+    fn test_with_bounds() {
+        // extending both edges is the same as extending one then the other
+        for &method in &[TimeWeightMethod::LOCF, TimeWeightMethod::Linear] {
+            let test = TimeWeightSummary::new_from_sorted_iter(
+                vec![&TSPoint { ts: 10, val: 1.0 }, &TSPoint { ts: 20, val: 2.0 }],
+                method,
+            )
+            .unwrap();
+            let prev = TSPoint { ts: 0, val: 5.0 };
+            let next = TSPoint { ts: 30, val: 3.0 };
+            let expected = test
+                .with_prev(5, prev)
+                .unwrap()
+                .with_next(25, Some(next))
+                .unwrap();
+            assert_eq!(
+                test.with_bounds(Some((5, prev)), Some((25, Some(next)))).unwrap(),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_interpolate() {
+        let summary = |points: &[TSPoint], method| {
+            TimeWeightSummary::new_from_sorted_iter(points, method).unwrap()
+        };
+        for &method in &[TimeWeightMethod::LOCF, TimeWeightMethod::Linear] {
+            let prev = summary(&[TSPoint { ts: 0, val: 1.0 }, TSPoint { ts: 5, val: 2.0 }], method);
+            let test = summary(&[TSPoint { ts: 15, val: 4.0 }, TSPoint { ts: 18, val: 8.0 }], method);
+            let next = summary(&[TSPoint { ts: 30, val: 2.0 }, TSPoint { ts: 35, val: 3.0 }], method);
+            let expected = test
+                .with_bounds(
+                    Some((10, TSPoint { ts: 5, val: 2.0 })),
+                    Some((20, Some(TSPoint { ts: 30, val: 2.0 }))),
+                )
+                .unwrap();
+            let interpolated = test.interpolate(10, 10, Some(&prev), Some(&next)).unwrap();
+            assert_eq!(interpolated, expected);
+            assert_eq!(interpolated.first.ts, 10);
+            assert_eq!(interpolated.last.ts, 20);
+
+            // without neighbours we can only use the points we have, except
+            // that LOCF can always carry the last value forward
+            let interpolated = test.interpolate(10, 10, None, None).unwrap();
+            assert_eq!(interpolated.first, test.first);
+            match method {
+                TimeWeightMethod::LOCF => assert_eq!(interpolated.last, TSPoint { ts: 20, val: 8.0 }),
+                _ => assert_eq!(interpolated.last, test.last),
+            }
+
+            // the summary must be within the interval
+            assert_eq!(
+                test.interpolate(16, 10, Some(&prev), Some(&next)).unwrap_err(),
+                TimeWeightError::OrderError
+            );
+            let other = if method == TimeWeightMethod::LOCF {
+                TimeWeightMethod::Linear
+            } else {
+                TimeWeightMethod::LOCF
+            };
+            let prev = summary(&[TSPoint { ts: 0, val: 1.0 }], other);
+            assert_eq!(
+                test.interpolate(10, 10, Some(&prev), None).unwrap_err(),
+                TimeWeightError::MethodMismatch
+            );
+        }
+    }
+
+    #[test]
+    fn test_integral() {
+        let test = TimeWeightSummary::new_from_sorted_iter(
+            vec![&TSPoint { ts: 10, val: 1.0 }, &TSPoint { ts: 20, val: 2.0 }],
+            TimeWeightMethod::Linear,
+        )
+        .unwrap();
+        assert_eq!(test.time_weighted_integral(), 15.0);
+        // the integral over the bucket includes the extrapolated edges
+        let bounded = test
+            .with_bounds(Some((0, TSPoint { ts: 0, val: 0.0 })), Some((30, Some(TSPoint { ts: 30, val: 2.0 }))))
+            .unwrap();
+        assert_eq!(bounded.time_weighted_integral(), 5.0 + 15.0 + 20.0);
+
+        let single = TimeWeightSummary::new(TSPoint { ts: 20, val: 2.0 }, TimeWeightMethod::LOCF);
+        assert_eq!(single.time_weighted_integral(), 0.0);
+    }
+
+    // summaries built in a different order can differ in the rounding of
+    // their second moment
+    fn assert_same_summary(s: &TimeWeightSummary, expected: &TimeWeightSummary) {
+        assert_eq!(TimeWeightSummary { m2: None, ..*s }, TimeWeightSummary { m2: None, ..*expected });
+        let (m2, expected_m2) = (s.m2.unwrap(), expected.m2.unwrap());
+        assert!((m2 - expected_m2).abs() <= 1e-12 * expected_m2.max(1.0), "{} {}", m2, expected_m2);
+    }
+
+    #[test]
+    fn test_remove_first() {
+        for &method in &[
+            TimeWeightMethod::LOCF,
+            TimeWeightMethod::Linear,
+            TimeWeightMethod::NOCB,
+            TimeWeightMethod::Nearest,
+        ] {
+            let points = vec![
+                TSPoint { ts: 0, val: 1.0 },
+                TSPoint { ts: 10, val: 3.0 },
+                TSPoint { ts: 100, val: 5.0 },
+                TSPoint { ts: 110, val: 2.0 },
+            ];
+            let mut s = TimeWeightSummary::new_from_sorted_iter_with_max_gap(&points, method, Some(50))
+                .unwrap();
+            s.remove_first(points[1]).unwrap();
+            let expected = TimeWeightSummary::new_from_sorted_iter_with_max_gap(&points[1..], method, Some(50))
+                .unwrap();
+            assert_same_summary(&s, &expected);
+            // removing the point before a gap removes the exclusion too
+            s.remove_first(points[2]).unwrap();
+            assert_eq!(s.excluded, 0);
+            assert_eq!(s.covered_duration(), 10);
+            let expected = TimeWeightSummary::new_from_sorted_iter_with_max_gap(&points[2..], method, Some(50))
+                .unwrap();
+            assert_same_summary(&s, &expected);
+
+            // a moving window gives the same results as summarizing the window
+            let mut s = TimeWeightSummary::new(points[0], method);
+            s.accum(points[1]).unwrap();
+            s.accum(points[2]).unwrap();
+            s.remove_first(points[1]).unwrap();
+            s.accum(points[3]).unwrap();
+            let expected = TimeWeightSummary::new_from_sorted_iter(&points[1..], method).unwrap();
+            assert_same_summary(&s, &expected);
+
+            // the next point must be within the summary
+            assert_eq!(s.remove_first(points[1]), Err(TimeWeightError::OrderError));
+            assert_eq!(
+                s.remove_first(TSPoint { ts: 120, val: 0.0 }),
+                Err(TimeWeightError::OrderError)
+            );
+        }
+    }
+
+    #[test]
+    fn test_remove_first_summary() {
+        for &method in &[
+            TimeWeightMethod::LOCF,
+            TimeWeightMethod::Linear,
+            TimeWeightMethod::NOCB,
+            TimeWeightMethod::Nearest,
+        ] {
+            let points = vec![
+                TSPoint { ts: 0, val: 1.0 },
+                TSPoint { ts: 10, val: 3.0 },
+                TSPoint { ts: 100, val: 5.0 },
+                TSPoint { ts: 110, val: 2.0 },
+                TSPoint { ts: 200, val: 4.0 },
+            ];
+            let summary = |points: &[TSPoint]| {
+                TimeWeightSummary::new_from_sorted_iter_with_max_gap(points, method, Some(50)).unwrap()
+            };
+            let (a, b, c) = (summary(&points[..2]), summary(&points[2..4]), summary(&points[4..]));
+            let mut s = a.combine(&b).unwrap().combine(&c).unwrap();
+            s.remove_first_summary(&a, b.first).unwrap();
+            assert_same_summary(&s, &b.combine(&c).unwrap());
+            s.remove_first_summary(&b, c.first).unwrap();
+            assert_same_summary(&s, &c);
+
+            // only the first summary can be removed
+            let mut s = a.combine(&b).unwrap().combine(&c).unwrap();
+            assert_eq!(s.remove_first_summary(&b, c.first), Err(TimeWeightError::OrderError));
+            assert_eq!(s.remove_first_summary(&a, points[1]), Err(TimeWeightError::OrderError));
+        }
+    }
+
+    #[test]
+    fn test_remove_first_precision() {
+        // a spike that dwarfs the rest of the window cannot be removed
+        // without losing most of the precision of what is left
+        let points = vec![
+            TSPoint { ts: 0, val: 1e15 },
+            TSPoint { ts: 10, val: 0.1 },
+            TSPoint { ts: 20, val: 0.2 },
+        ];
+        let mut s = TimeWeightSummary::new_from_sorted_iter(&points, TimeWeightMethod::LOCF).unwrap();
+        let before = s;
+        assert_eq!(s.remove_first(points[1]), Err(TimeWeightError::PrecisionLoss));
+        assert_eq!(s, before);
+
+        // rebuilding on PrecisionLoss keeps a long moving window the same as
+        // summarizing the window from scratch
+        const WINDOW: usize = 50;
+        let points: Vec<_> = (0..1000)
+            .map(|i| TSPoint {
+                ts: i * 10,
+                val: if i % 300 == 100 { 1e12 } else { (i % 7) as f64 * 0.1 },
+            })
+            .collect();
+        for &method in &[
+            TimeWeightMethod::LOCF,
+            TimeWeightMethod::Linear,
+            TimeWeightMethod::NOCB,
+            TimeWeightMethod::Nearest,
+        ] {
+            let mut rebuilt = 0;
+            let mut s = TimeWeightSummary::new_from_sorted_iter(&points[..WINDOW], method).unwrap();
+            for end in WINDOW..points.len() {
+                s.accum(points[end]).unwrap();
+                let window = &points[end + 1 - WINDOW..=end];
+                match s.remove_first(window[0]) {
+                    Ok(()) => {}
+                    Err(TimeWeightError::PrecisionLoss) => {
+                        rebuilt += 1;
+                        s = TimeWeightSummary::new_from_sorted_iter(window, method).unwrap();
+                    }
+                    Err(e) => panic!("{:?}", e),
+                }
+                let expected = TimeWeightSummary::new_from_sorted_iter(window, method).unwrap();
+                assert_eq!(s.first, expected.first);
+                assert_eq!(s.last, expected.last);
+                let (avg, expected_avg) = (s.time_weighted_average().unwrap(), expected.time_weighted_average().unwrap());
+                assert!((avg - expected_avg).abs() <= 1e-9 * expected_avg.abs().max(1.0), "{} {}", avg, expected_avg);
+                let (var, expected_var) = (s.time_weighted_variance().unwrap(), expected.time_weighted_variance().unwrap());
+                assert!((var - expected_var).abs() <= 1e-9 * expected_var.max(1.0), "{} {}", var, expected_var);
+            }
+            assert!(rebuilt > 0);
+        }
+    }
+
+    #[test]
+    fn test_max_gap() {
+        for &method in &[
+            TimeWeightMethod::LOCF,
+            TimeWeightMethod::Linear,
+            TimeWeightMethod::NOCB,
+            TimeWeightMethod::Nearest,
+        ] {
+            let points = vec![
+                TSPoint { ts: 0, val: 1.0 },
+                TSPoint { ts: 10, val: 1.0 },
+                TSPoint { ts: 100, val: 5.0 },
+                TSPoint { ts: 110, val: 5.0 },
+            ];
+            let s = TimeWeightSummary::new_from_sorted_iter_with_max_gap(&points, method, Some(50))
+                .unwrap();
+            // the gap between 10 and 100 is ignored
+            assert_eq!(s.w_sum, 10.0 + 50.0);
+            assert_eq!(s.excluded, 90);
+            assert_eq!(s.covered_duration(), 20);
+            assert_eq!(s.time_weighted_average().unwrap(), 3.0);
+
+            // gaps exactly max_gap long still count
+            let s = TimeWeightSummary::new_from_sorted_iter_with_max_gap(&points, method, Some(90))
+                .unwrap();
+            assert_eq!(s.excluded, 0);
+            assert_eq!(s.covered_duration(), 110);
+
+            // the gap between summaries is treated the same way
+            let s = TimeWeightSummary::new_from_sorted_iter_with_max_gap(&points, method, Some(50))
+                .unwrap();
+            let s1 = TimeWeightSummary::new_from_sorted_iter_with_max_gap(&points[..2], method, Some(50))
+                .unwrap();
+            let s2 = TimeWeightSummary::new_from_sorted_iter_with_max_gap(&points[2..], method, Some(50))
+                .unwrap();
+            assert_eq!(s1.combine(&s2).unwrap(), s);
+            let other = TimeWeightSummary::new_from_sorted_iter_with_max_gap(&points[2..], method, Some(60))
+                .unwrap();
+            assert_eq!(s1.combine(&other), Err(TimeWeightError::MaxGapMismatch));
+            let other = TimeWeightSummary::new_from_sorted_iter(&points[2..], method).unwrap();
+            assert_eq!(s1.combine(&other), Err(TimeWeightError::MaxGapMismatch));
+
+            // as is extending a summary to bounds across a long gap
+            let bounded = s2
+                .with_bounds(Some((50, points[1])), Some((150, Some(TSPoint { ts: 200, val: 0.0 }))))
+                .unwrap();
+            assert_eq!(bounded.w_sum, s2.w_sum);
+            assert_eq!(bounded.first.ts, 50);
+            assert_eq!(bounded.last.ts, 150);
+            assert_eq!(bounded.covered_duration(), s2.covered_duration());
+        }
+
+        // without a next point LOCF excludes the extension if it is too long
+        let s = TimeWeightSummary::new_from_sorted_iter_with_max_gap(
+            vec![&TSPoint { ts: 0, val: 1.0 }, &TSPoint { ts: 10, val: 1.0 }],
+            TimeWeightMethod::LOCF,
+            Some(50),
+        )
+        .unwrap();
+        assert_eq!(s.with_bounds(None, Some((40, None))).unwrap().w_sum, 40.0);
+        let bounded = s.with_bounds(None, Some((100, None))).unwrap();
+        assert_eq!(bounded.w_sum, 10.0);
+        assert_eq!(bounded.covered_duration(), 10);
+    }
+
+    #[test]
+    fn test_variance() {
+        let points = vec![
+            &TSPoint { ts: 0, val: 1.0 },
+            &TSPoint { ts: 10, val: 3.0 },
+            &TSPoint { ts: 30, val: 3.0 },
+        ];
+        // LOCF is 1 for 10 then 3 for 20
+        let locf = TimeWeightSummary::new_from_sorted_iter(points.clone(), TimeWeightMethod::LOCF).unwrap();
+        let mean = (10.0 + 60.0) / 30.0;
+        let expected = (10.0 * (1.0 - mean) * (1.0 - mean) + 20.0 * (3.0 - mean) * (3.0 - mean)) / 30.0;
+        assert!((locf.time_weighted_variance().unwrap() - expected).abs() < 1e-12);
+        assert!((locf.time_weighted_stddev().unwrap() - expected.sqrt()).abs() < 1e-12);
+
+        // linear rises from 1 to 3 over the first 10, which has the variance
+        // of a uniform distribution on [1, 3] ie 1/3 around its mean of 2
+        let linear = TimeWeightSummary::new_from_sorted_iter(points.clone(), TimeWeightMethod::Linear).unwrap();
+        let mean = (20.0 + 60.0) / 30.0;
+        let expected = (10.0 * (1.0 / 3.0 + (2.0 - mean) * (2.0 - mean)) + 20.0 * (3.0 - mean) * (3.0 - mean)) / 30.0;
+        assert!((linear.time_weighted_variance().unwrap() - expected).abs() < 1e-12);
+
+        // a constant has no variance
+        let constant = TimeWeightSummary::new_from_sorted_iter(
+            vec![&TSPoint { ts: 0, val: 0.1 }, &TSPoint { ts: 7, val: 0.1 }, &TSPoint { ts: 13, val: 0.1 }],
+            TimeWeightMethod::Linear,
+        )
+        .unwrap();
+        // up to rounding in the means
+        assert!(constant.time_weighted_variance().unwrap() < 1e-30);
+
+        let single = TimeWeightSummary::new(TSPoint { ts: 20, val: 2.0 }, TimeWeightMethod::LOCF);
+        assert_eq!(single.time_weighted_variance(), Err(TimeWeightError::ZeroDuration));
+
+        for &method in &[
+            TimeWeightMethod::LOCF,
+            TimeWeightMethod::Linear,
+            TimeWeightMethod::NOCB,
+            TimeWeightMethod::Nearest,
+        ] {
+            // combining keeps the second moment
+            let full = TimeWeightSummary::new_from_sorted_iter(points.clone(), method).unwrap();
+            let first = TimeWeightSummary::new_from_sorted_iter(points[..1].to_vec(), method).unwrap();
+            let rest = TimeWeightSummary::new_from_sorted_iter(points[1..].to_vec(), method).unwrap();
+            assert_eq!(first.combine(&rest).unwrap(), full);
+
+            // and extending to bounds is the same as adding the extrapolated points
+            let prev = TSPoint { ts: -10, val: 5.0 };
+            let next = TSPoint { ts: 50, val: 0.0 };
+            let bounded = rest.with_bounds(Some((5, *points[0])), Some((40, Some(next)))).unwrap();
+            let within = |a: TSPoint, b: TSPoint, from: i64, to: i64| method.moments_within(a, b, from, to);
+            let expected = within(*points[0], *points[1], 5, 10)
+                .combine(&rest.moments())
+                .combine(&within(*points[2], next, 30, 40));
+            assert!((bounded.m2.unwrap() - expected.m2).abs() < 1e-9);
+            // splitting a segment doesn't change its moments
+            let whole = method.moments(prev, *points[1]);
+            let split = within(prev, *points[1], -10, 3).combine(&within(prev, *points[1], 3, 10));
+            assert!((split.w_sum - whole.w_sum).abs() < 1e-9);
+            assert!((split.m2 - whole.m2).abs() < 1e-9);
+
+            // the variance stays accurate when the values are large compared
+            // to their spread
+            let shifted: Vec<_> = (0..1000)
+                .map(|i| TSPoint { ts: i * 10, val: if i % 2 == 0 { 1e9 + 0.01 } else { 1e9 - 0.01 } })
+                .collect();
+            // the same deviations as stored in the shifted values, around 0
+            let points: Vec<_> = shifted
+                .iter()
+                .map(|p| TSPoint { val: p.val - 1e9, ..*p })
+                .collect();
+            let expected = TimeWeightSummary::new_from_sorted_iter(&points, method)
+                .unwrap()
+                .time_weighted_variance()
+                .unwrap();
+            let variance = TimeWeightSummary::new_from_sorted_iter(&shifted, method)
+                .unwrap()
+                .time_weighted_variance()
+                .unwrap();
+            assert!(expected > 0.0);
+            assert!((variance - expected).abs() <= 1e-5 * expected, "{} {}", variance, expected);
+        }
+
+        // summaries stored before the second moment was tracked have no variance
+        let old = TimeWeightSummary { m2: None, ..locf };
+        assert_eq!(old.time_weighted_variance(), Err(TimeWeightError::MissingVariance));
+        let next = TimeWeightSummary::new(TSPoint { ts: 40, val: 1.0 }, TimeWeightMethod::LOCF);
+        assert_eq!(old.combine(&next).unwrap().m2, None);
+    }
+
     // add average tests
     fn average_common_tests(t: TimeWeightMethod) {
         let single = TimeWeightSummary::new(TSPoint { ts: 20, val: 2.0 }, t);
@@ -759,6 +1563,8 @@ mod tests {
     #[test]
     fn test_average() {
         average_common_tests(TimeWeightMethod::Linear);
+        average_common_tests(TimeWeightMethod::NOCB);
+        average_common_tests(TimeWeightMethod::Nearest);
         average_common_tests(TimeWeightMethod::LOCF);
 
         let test = TimeWeightSummary::new_from_sorted_iter(
@@ -151,6 +151,19 @@ impl SketchHashMap {
         self.map.len()
     }
 
+    // Builds a map from (key, count) pairs sorted by increasing key.
+    fn from_sorted(entries: &[(SketchHashKey, u64)]) -> SketchHashMap {
+        let mut map = HashMap::with_capacity(entries.len());
+        for (i, &(key, count)) in entries.iter().enumerate() {
+            let next = entries.get(i + 1).map_or(SketchHashKey::Invalid, |e| e.0);
+            map.insert(key, SketchHashEntry { count, next });
+        }
+        SketchHashMap {
+            map,
+            head: entries.first().map_or(SketchHashKey::Invalid, |e| e.0),
+        }
+    }
+
     // Combine adjacent buckets
     fn compact(&mut self) {
         let mut target = self.head;
@@ -285,6 +298,23 @@ impl UDDSketch {
         self.values_sum += value;
     }
 
+    // Adds `count` occurrences of `value` at once, equivalent to calling
+    // `add_value` `count` times.
+    pub fn add_value_with_count(&mut self, value: f64, count: u64) {
+        if count == 0 {
+            return;
+        }
+        let key = self.key(value);
+        self.buckets.entry(key).count += count;
+
+        while self.buckets.len() > self.max_buckets as usize {
+            self.compact_buckets();
+        }
+
+        self.num_values += count;
+        self.values_sum += value * count as f64;
+    }
+
     pub fn merge_sketch(&mut self, other: &UDDSketch) {
         // Require matching initial parameters
         assert!(
@@ -326,6 +356,59 @@ impl UDDSketch {
         self.values_sum += other.values_sum;
     }
 
+    // The inverse of merge_sketch(), removes the values of a sketch that was
+    // previously merged into this one. Returns false, leaving this sketch
+    // unchanged, if `other` has been compacted more than this sketch or has
+    // values this one doesn't. The result stays at this sketch's compaction
+    // level, so it may be more compacted than a sketch built without `other`
+    // would be.
+    pub fn subtract_sketch(&mut self, other: &UDDSketch) -> bool {
+        assert!(
+            self.gamma
+                .powf(1.0 / f64::powi(2.0, self.compactions as i32))
+                == other
+                    .gamma
+                    .powf(1.0 / f64::powi(2.0, other.compactions as i32))
+        );
+        assert!(self.max_buckets == other.max_buckets);
+
+        if other.compactions > self.compactions || other.num_values > self.num_values {
+            return false;
+        }
+
+        let mut other = other.clone();
+        while other.compactions < self.compactions {
+            other.compact_buckets();
+        }
+
+        // both bucket lists are in increasing key order
+        let mut removed = other.buckets.iter().peekable();
+        let mut remaining = Vec::with_capacity(self.buckets.len());
+        for (key, count) in self.buckets.iter() {
+            let mut count = count;
+            if let Some(&(removed_key, removed_count)) = removed.peek() {
+                if removed_key < key || (removed_key == key && removed_count > count) {
+                    return false;
+                }
+                if removed_key == key {
+                    count -= removed_count;
+                    removed.next();
+                }
+            }
+            if count > 0 {
+                remaining.push((key, count));
+            }
+        }
+        if removed.next().is_some() {
+            return false;
+        }
+
+        self.buckets = SketchHashMap::from_sorted(&remaining);
+        self.num_values -= other.num_values;
+        self.values_sum -= other.values_sum;
+        true
+    }
+
     pub fn max_allowed_buckets(&self) -> u64 {
         self.max_buckets
     }
@@ -617,6 +700,75 @@ mod tests {
         assert!((sketch.mean() - 50.005).abs() < 0.001);
     }
 
+    #[test]
+    fn test_add_value_with_count() {
+        let mut sketch1 = UDDSketch::new(20, 0.1);
+        let mut sketch2 = UDDSketch::new(20, 0.1);
+        for i in 0..100 {
+            let value = (1.23 as f64).powi(i);
+            for _ in 0..(i % 7) {
+                sketch1.add_value(value);
+            }
+            sketch2.add_value_with_count(value, (i % 7) as u64);
+        }
+
+        assert_eq!(sketch1.count(), sketch2.count());
+        assert_eq!(sketch1.max_error(), sketch2.max_error());
+        assert_eq!(sketch1.times_compacted(), sketch2.times_compacted());
+        assert!((sketch1.sum() - sketch2.sum()).abs() / sketch1.sum() < 1e-12);
+        assert!(sketch1.bucket_iter().eq(sketch2.bucket_iter()));
+    }
+
+    #[test]
+    fn test_subtract_sketch() {
+        let mut sketch1 = UDDSketch::new(20, 0.1);
+        let mut sketch2 = UDDSketch::new(20, 0.1);
+        for i in 0..10 {
+            sketch1.add_value((1.23 as f64).powi(i));
+            sketch2.add_value((1.23 as f64).powi(i + 5));
+        }
+        let mut merged = sketch1.clone();
+        merged.merge_sketch(&sketch2);
+        assert_eq!(merged.times_compacted(), 0);
+
+        let mut subtracted = merged.clone();
+        assert!(subtracted.subtract_sketch(&sketch2));
+        assert_eq!(subtracted.count(), sketch1.count());
+        assert!((subtracted.sum() - sketch1.sum()).abs() / sketch1.sum() < 1e-12);
+        assert!(subtracted.bucket_iter().eq(sketch1.bucket_iter()));
+
+        assert!(subtracted.subtract_sketch(&sketch1));
+        assert_eq!(subtracted.count(), 0);
+        assert_eq!(subtracted.bucket_iter().count(), 0);
+        // nothing is left to remove
+        assert!(!subtracted.subtract_sketch(&sketch1));
+
+        // values that were never added can't be removed
+        let mut other = UDDSketch::new(20, 0.1);
+        other.add_value(1000.0);
+        let mut unchanged = merged.clone();
+        assert!(!unchanged.subtract_sketch(&other));
+        assert!(unchanged.bucket_iter().eq(merged.bucket_iter()));
+
+        // a sketch compacted less than the one it was merged into is compacted
+        // to match before being removed
+        let mut wide = UDDSketch::new(20, 0.1);
+        for i in 0..30 {
+            wide.add_value(1000.0 * (1.23 as f64).powi(i));
+        }
+        assert_eq!(wide.times_compacted(), 1);
+        let mut merged = wide.clone();
+        merged.merge_sketch(&sketch1);
+        assert!(merged.subtract_sketch(&sketch1));
+        // merging may have compacted further, which subtracting doesn't undo
+        let mut expected = wide.clone();
+        while expected.times_compacted() < merged.times_compacted() {
+            expected.compact_buckets();
+        }
+        assert!(merged.bucket_iter().eq(expected.bucket_iter()));
+        assert!(!sketch1.clone().subtract_sketch(&wide));
+    }
+
     #[test]
     fn test_extreme_quantile_at_value() {
         let mut sketch = UDDSketch::new(50, 0.1);
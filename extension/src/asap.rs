@@ -7,7 +7,7 @@ use crate::{
     aggregate_utils::in_aggregate_context, palloc::Internal,
 };
 
-use time_series::{TSPoint, TimeSeries as InternalTimeSeries, ExplicitTimeSeries, NormalTimeSeries, GapfillMethod, TimeSeriesError};
+use time_series::{TSPoint, TimeSeries as InternalTimeSeries, ExplicitTimeSeries, NormalTimeSeries, AggregationMethod, GapfillMethod, TimeSeriesError};
 
 use crate::time_series::TimeSeries;
 
@@ -108,9 +108,9 @@ fn asap_final(
                 // points, but still normalize them to equal sized buckets.
                 let normal = if series.points.len() >= 2 * state.resolution as usize {
                     let downsample_interval = find_downsample_interval(&series, state.resolution as i64);
-                    series.downsample_and_gapfill_to_normal_form(downsample_interval, GapfillMethod::Linear)
+                    series.downsample_and_gapfill_to_normal_form(downsample_interval, AggregationMethod::Average, GapfillMethod::Linear)
                 } else {
-                    series.downsample_and_gapfill_to_normal_form((series.points.last().unwrap().ts - series.points.first().unwrap().ts) / series.points.len() as i64, GapfillMethod::Linear)
+                    series.downsample_and_gapfill_to_normal_form((series.points.last().unwrap().ts - series.points.first().unwrap().ts) / series.points.len() as i64, AggregationMethod::Average, GapfillMethod::Linear)
                 };
                 let mut normal = match normal {
                     Ok(series) => series,
@@ -149,9 +149,9 @@ pub fn asap_on_timeseries(
             explicit.sort();
             let normal = if explicit.points.len() >= 2 * resolution as usize {
                 let downsample_interval = find_downsample_interval(&explicit, resolution as i64);
-                explicit.downsample_and_gapfill_to_normal_form(downsample_interval, GapfillMethod::Linear)
+                explicit.downsample_and_gapfill_to_normal_form(downsample_interval, AggregationMethod::Average, GapfillMethod::Linear)
             } else {
-                explicit.downsample_and_gapfill_to_normal_form((explicit.points.last().unwrap().ts - explicit.points.first().unwrap().ts) / explicit.points.len() as i64, GapfillMethod::Linear)
+                explicit.downsample_and_gapfill_to_normal_form((explicit.points.last().unwrap().ts - explicit.points.first().unwrap().ts) / explicit.points.len() as i64, AggregationMethod::Average, GapfillMethod::Linear)
             };
             match normal {
                 Ok(series) => series,
@@ -8,6 +8,7 @@ use pgx::*;
 use pg_sys::Datum;
 
 use flat_serialize::*;
+use flat_serialize_macro::FlatSerializable;
 
 use crate::{
     aggregate_utils::in_aggregate_context,
@@ -23,7 +24,9 @@ use time_series::{
 };
 
 use counter_agg::{
+    CounterError,
     CounterSummary as InternalCounterSummary,
+    ResetTolerance,
     range::I64Range,
 };
 use stats_agg::stats2d::StatsSummary2D;
@@ -36,8 +39,8 @@ type bytea = pg_sys::Datum;
 
 pg_type! {
     #[derive(Debug, PartialEq)]
-    struct CounterSummary {
-        stats: StatsSummary2D,
+    struct CounterSummary<'input> {
+        stats: CounterStats,
         first: TSPoint,
         second: TSPoint,
         penultimate:TSPoint,
@@ -47,9 +50,41 @@ pg_type! {
         num_changes: u64,
         #[flat_serialize::flatten]
         bounds: I64RangeWrapper,
+        // missing from summaries before version 2, which have no tolerance
+        #[serde(default)]
+        reset_tolerance: [ResetTolerance; if self.version >= 2 { 1 } else { 0 }],
+        // missing from summaries before version 3, which have no reset times
+        #[serde(default)]
+        num_reset_times: [u64; if self.version >= 3 { 1 } else { 0 }],
+        #[serde(default)]
+        reset_times: [i64; self.num_reset_times.first().copied().unwrap_or(0)],
+        // the rounding errors in stats.sx and stats.sy, missing from summaries
+        // before version 4
+        #[serde(default)]
+        stats_compensation: [f64; if self.version >= 4 { 2 } else { 0 }],
     }
 }
 
+// version 2 added the reset tolerance, version 3 the reset times, version 4
+// the compensation for rounding errors in the sums of the stats. Older
+// summaries are still readable, they're treated as having no tolerance, no
+// reset times, and no rounding error.
+const COUNTER_SUMMARY_VERSION: u8 = 4;
+
+// The part of a StatsSummary2D stored in a CounterSummary, which is the whole
+// of it as of version 1. None of the counter accessors use the higher moments,
+// so they aren't stored.
+#[derive(Clone, Copy, Debug, PartialEq, FlatSerializable, Serialize, Deserialize)]
+#[repr(C)]
+pub struct CounterStats {
+    n: u64,
+    sx: f64,
+    sxx: f64,
+    sy: f64,
+    syy: f64,
+    sxy: f64,
+}
+
 json_inout_funcs!(CounterSummary);
 
 // hack to allow us to qualify names with "toolkit_experimental"
@@ -70,15 +105,45 @@ impl<'input> CounterSummary<'input> {
             reset_sum: self.reset_sum,
             num_resets: self.num_resets,
             num_changes: self.num_changes,
-            stats: self.stats,
+            stats: self.internal_stats(),
             bounds: self.bounds.to_i64range(),
+            reset_tolerance: self.reset_tolerance.first().copied().unwrap_or_default(),
+            reset_times: self.reset_times.to_vec(),
+        }
+    }
+    fn internal_stats(&self) -> StatsSummary2D {
+        let (sxc, syc) = match *self.stats_compensation {
+            [sxc, syc] => (sxc, syc),
+            _ => (0.0, 0.0),
+        };
+        StatsSummary2D {
+            n: self.stats.n,
+            sx: self.stats.sx,
+            sxx: self.stats.sxx,
+            sy: self.stats.sy,
+            syy: self.stats.syy,
+            sxy: self.stats.sxy,
+            sx3: f64::NAN,
+            sx4: f64::NAN,
+            sy3: f64::NAN,
+            sy4: f64::NAN,
+            sxc,
+            syc,
         }
     }
     fn from_internal_counter_summary(st: InternalCounterSummary) -> Self {
+        let stats = CounterStats {
+            n: st.stats.n,
+            sx: st.stats.sx,
+            sxx: st.stats.sxx,
+            sy: st.stats.sy,
+            syy: st.stats.syy,
+            sxy: st.stats.sxy,
+        };
         unsafe{
             flatten!(
-            CounterSummary {
-                stats: st.stats,
+            CounterSummary version COUNTER_SUMMARY_VERSION {
+                stats: stats,
                 first: st.first,
                 second: st.second,
                 penultimate: st.penultimate,
@@ -86,7 +151,11 @@ impl<'input> CounterSummary<'input> {
                 reset_sum: st.reset_sum,
                 num_resets: st.num_resets,
                 num_changes: st.num_changes,
-                bounds: I64RangeWrapper::from_i64range(st.bounds)
+                bounds: I64RangeWrapper::from_i64range(st.bounds),
+                reset_tolerance: slice::from_ref(&st.reset_tolerance),
+                num_reset_times: &[st.reset_times.len() as u64],
+                reset_times: &st.reset_times,
+                stats_compensation: &[st.stats.sxc, st.stats.syc],
             })
         }
     }
@@ -95,12 +164,23 @@ impl<'input> CounterSummary<'input> {
     // }
 }
 
+fn counter_error(err: CounterError) -> ! {
+    match err {
+        CounterError::OrderError => panic!("counter summaries overlap in time"),
+        CounterError::BoundsInvalid => panic!("counter bounds invalid"),
+        CounterError::ToleranceMismatch =>
+            panic!("cannot combine counter summaries with different reset tolerances"),
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct CounterSummaryTransState {
     #[serde(skip)]
     point_buffer: Vec<TSPoint>,
     #[serde(skip)]
     bounds: Option<I64Range>, // stores bounds until we combine points, after which, the bounds are stored in each summary
+    #[serde(skip)]
+    reset_tolerance: ResetTolerance, // like bounds, stored in each summary once we combine points
     // We have a summary buffer here in order to deal with the fact that when the cmobine function gets called it
     // must first build up a buffer of InternalMetricSummaries, then sort them, then call the combine function in
     // the correct order.
@@ -122,14 +202,14 @@ impl CounterSummaryTransState {
         }
         self.point_buffer.sort_unstable_by_key(|p| p.ts);
         let mut iter = self.point_buffer.iter();
-        let mut summary = InternalCounterSummary::new( iter.next().unwrap(), self.bounds);
+        let mut summary = InternalCounterSummary::with_reset_tolerance(iter.next().unwrap(), self.bounds, self.reset_tolerance);
         for p in iter {
             summary.add_point(p).unwrap();
         }
         self.point_buffer.clear();
         // check bounds only after we've combined all the points, so we aren't doing it all the time.
         if !summary.bounds_valid() {
-            panic!("counter bounds invalid")
+            counter_error(CounterError::BoundsInvalid)
         }
         self.summary_buffer.push(summary);
     }
@@ -151,7 +231,9 @@ impl CounterSummaryTransState {
         let mut sum_iter = self.summary_buffer.iter();
         let mut new_summary = sum_iter.next().unwrap().clone();
         for sum in sum_iter {
-            new_summary.combine(sum).unwrap();
+            if let Err(err) = new_summary.combine(sum) {
+                counter_error(err)
+            }
         }
         self.summary_buffer = vec![new_summary];
     }
@@ -180,6 +262,19 @@ pub fn counter_agg_trans(
     val: Option<f64>,
     bounds: Option<tstzrange>,
     fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<CounterSummaryTransState>> {
+    counter_agg_trans_with_tolerance(state, ts, val, bounds, None, None, fcinfo)
+}
+
+#[pg_extern(schema = "toolkit_experimental")]
+pub fn counter_agg_trans_with_tolerance(
+    state: Option<Internal<CounterSummaryTransState>>,
+    ts: Option<pg_sys::TimestampTz>,
+    val: Option<f64>,
+    bounds: Option<tstzrange>,
+    abs_tolerance: Option<f64>,
+    rel_tolerance: Option<f64>,
+    fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<Internal<CounterSummaryTransState>> {
     unsafe {
         in_aggregate_context(fcinfo, || {
@@ -190,10 +285,17 @@ pub fn counter_agg_trans(
             };
             match state {
                 None => {
-                    let mut s = CounterSummaryTransState{point_buffer: vec![], bounds: None, summary_buffer: vec![]};
+                    let mut s = CounterSummaryTransState{point_buffer: vec![], bounds: None, reset_tolerance: ResetTolerance::default(), summary_buffer: vec![]};
                     if let Some(r) = bounds {
                         s.bounds = get_range(r as *mut pg_sys::varlena);
                     }
+                    s.reset_tolerance = ResetTolerance{
+                        absolute: abs_tolerance.unwrap_or(0.0),
+                        relative: rel_tolerance.unwrap_or(0.0),
+                    };
+                    if !(s.reset_tolerance.absolute >= 0.0 && s.reset_tolerance.relative >= 0.0) {
+                        panic!("reset tolerances must be non-negative")
+                    }
                     s.push_point(p);
                     Some(s.into())
                 },
@@ -225,7 +327,7 @@ pub fn counter_agg_summary_trans(
             match (state, value) {
                 (state, None) => state,
                 (None, Some(value)) => Some(
-                    CounterSummaryTransState{point_buffer: vec![], bounds: None, summary_buffer: vec![value.to_internal_counter_summary()]}.into()),
+                    CounterSummaryTransState{point_buffer: vec![], bounds: None, reset_tolerance: ResetTolerance::default(), summary_buffer: vec![value.to_internal_counter_summary()]}.into()),
                 (Some(mut state), Some(value)) => {
                     state.summary_buffer.push(value.to_internal_counter_summary());
                     Some(state)
@@ -278,7 +380,7 @@ fn counter_agg_final(
                 Some(st) => {
                     // there are some edge cases that this should prevent, but I'm not sure it's necessary, we do check the bounds in the functions that use them.
                     if !st.bounds_valid() {
-                        panic!("counter bounds invalid")
+                        counter_error(CounterError::BoundsInvalid)
                     }
                     Some(CounterSummary::from_internal_counter_summary(st).into())
                 }
@@ -301,6 +403,19 @@ CREATE AGGREGATE toolkit_experimental.counter_agg( ts timestamptz, value DOUBLE
 );
 "#);
 
+extension_sql!(r#"
+CREATE AGGREGATE toolkit_experimental.counter_agg( ts timestamptz, value DOUBLE PRECISION, bounds tstzrange, abs_tolerance DOUBLE PRECISION, rel_tolerance DOUBLE PRECISION )
+(
+    sfunc = toolkit_experimental.counter_agg_trans_with_tolerance,
+    stype = internal,
+    finalfunc = toolkit_experimental.counter_agg_final,
+    combinefunc = toolkit_experimental.counter_agg_combine,
+    serialfunc = toolkit_experimental.counter_summary_trans_serialize,
+    deserialfunc = toolkit_experimental.counter_summary_trans_deserialize,
+    parallel = restricted
+);
+"#);
+
 // allow calling counter agg without bounds provided.
 extension_sql!(r#"
 CREATE AGGREGATE toolkit_experimental.counter_agg( ts timestamptz, value DOUBLE PRECISION )
@@ -328,6 +443,22 @@ CREATE AGGREGATE toolkit_experimental.rollup(cs toolkit_experimental.CounterSumm
 );
 "#);
 
+// There is no inverse for rolling(), removing a summary from the start of a
+// frame would need to undo the reset detection at the boundary between it and
+// the next summary, so each frame is combined from scratch.
+extension_sql!(r#"
+CREATE AGGREGATE toolkit_experimental.rolling(cs toolkit_experimental.CounterSummary)
+(
+    sfunc = toolkit_experimental.counter_agg_summary_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.counter_agg_final,
+    combinefunc = toolkit_experimental.counter_agg_combine,
+    serialfunc = toolkit_experimental.counter_summary_trans_serialize,
+    deserialfunc = toolkit_experimental.counter_summary_trans_deserialize,
+    parallel = restricted
+);
+"#);
+
 #[pg_extern(name="delta", schema = "toolkit_experimental", strict, immutable)]
 fn counter_agg_delta(
     summary: toolkit_experimental::CounterSummary,
@@ -404,10 +535,16 @@ fn counter_agg_extrapolated_delta(
     method: String,
     _fcinfo: pg_sys::FunctionCallInfo,
 )-> Option<f64> {
-    match method.to_lowercase().as_str() {
+    match method.trim().to_lowercase().as_str() {
         "prometheus" => {
             summary.to_internal_counter_summary().prometheus_delta().unwrap()
         },
+        "linear_regression" => {
+            summary.to_internal_counter_summary().regression_delta().unwrap()
+        },
+        "clamped" | "none" => {
+            summary.to_internal_counter_summary().clamped_delta().unwrap()
+        },
         _ => panic!("unknown method"),
     }
 }
@@ -418,14 +555,62 @@ fn counter_agg_extrapolated_rate(
     method: String,
     _fcinfo: pg_sys::FunctionCallInfo,
 )-> Option<f64> {
-    match method.to_lowercase().as_str() {
+    match method.trim().to_lowercase().as_str() {
         "prometheus" => {
             summary.to_internal_counter_summary().prometheus_rate().unwrap()
         },
+        "linear_regression" => {
+            summary.to_internal_counter_summary().regression_rate().unwrap()
+        },
+        "clamped" | "none" => {
+            summary.to_internal_counter_summary().clamped_rate().unwrap()
+        },
         _ => panic!("unknown method"),
     }
 }
 
+#[pg_extern(name="first_time", schema = "toolkit_experimental", strict, immutable)]
+fn counter_agg_first_time(
+    summary: toolkit_experimental::CounterSummary,
+    _fcinfo: pg_sys::FunctionCallInfo,
+)-> pg_sys::TimestampTz {
+    summary.first.ts
+}
+
+#[pg_extern(name="first_val", schema = "toolkit_experimental", strict, immutable)]
+fn counter_agg_first_val(
+    summary: toolkit_experimental::CounterSummary,
+    _fcinfo: pg_sys::FunctionCallInfo,
+)-> f64 {
+    summary.first.val
+}
+
+#[pg_extern(name="last_time", schema = "toolkit_experimental", strict, immutable)]
+fn counter_agg_last_time(
+    summary: toolkit_experimental::CounterSummary,
+    _fcinfo: pg_sys::FunctionCallInfo,
+)-> pg_sys::TimestampTz {
+    summary.last.ts
+}
+
+#[pg_extern(name="last_val", schema = "toolkit_experimental", strict, immutable)]
+fn counter_agg_last_val(
+    summary: toolkit_experimental::CounterSummary,
+    _fcinfo: pg_sys::FunctionCallInfo,
+)-> f64 {
+    summary.last.val
+}
+
+// only the most recent counter_agg::MAX_RESET_TIMES resets are kept, use
+// num_resets for the total number of resets
+#[pg_extern(name="resets", schema = "toolkit_experimental", strict, immutable)]
+fn counter_agg_resets(
+    summary: toolkit_experimental::CounterSummary,
+) -> impl std::iter::Iterator<Item = pg_sys::TimestampTz> + '_ {
+    let reset_times = summary.reset_times;
+    reset_times.iter().copied()
+}
+
 #[pg_extern(name="num_elements", schema = "toolkit_experimental", strict, immutable)]
 fn counter_agg_num_elements(
     summary: toolkit_experimental::CounterSummary,
@@ -553,6 +738,20 @@ mod tests {
             let stmt = "SELECT extrapolated_rate(counter_agg(ts, val, '[2020-01-01 00:00:00+00, 2020-01-01 00:02:00+00)'), 'prometheus') FROM test";
             assert_relative_eq!(select_one!(client, stmt, f64), 20.0 / 120.0);
 
+            // with only two points every method extrapolates the same line
+            let stmt = "SELECT extrapolated_delta(counter_agg(ts, val, '[2020-01-01 00:00:00+00, 2020-01-01 00:02:00+00)'), 'linear_regression') FROM test";
+            assert_relative_eq!(select_one!(client, stmt, f64), 20.0, epsilon = 1e-6);
+            let stmt = "SELECT extrapolated_rate(counter_agg(ts, val, '[2020-01-01 00:00:00+00, 2020-01-01 00:02:00+00)'), 'linear_regression') FROM test";
+            assert_relative_eq!(select_one!(client, stmt, f64), 20.0 / 120.0, epsilon = 1e-6);
+
+            // prometheus only extrapolates by half a sample interval this far from the bounds, clamped extrapolates all the way
+            let stmt = "SELECT extrapolated_delta(counter_agg(ts, val, '[2020-01-01 00:00:00+00, 2020-01-01 00:04:00+00)'), 'prometheus') FROM test";
+            assert_relative_eq!(select_one!(client, stmt, f64), 15.0);
+            let stmt = "SELECT extrapolated_delta(counter_agg(ts, val, '[2020-01-01 00:00:00+00, 2020-01-01 00:04:00+00)'), 'clamped') FROM test";
+            assert_relative_eq!(select_one!(client, stmt, f64), 40.0);
+            let stmt = "SELECT extrapolated_rate(counter_agg(ts, val, '[2020-01-01 00:00:00+00, 2020-01-01 00:04:00+00)'), 'none') FROM test";
+            assert_relative_eq!(select_one!(client, stmt, f64), 40.0 / 240.0);
+
             let stmt = "INSERT INTO test VALUES('2020-01-01 00:02:00+00', 10.0), ('2020-01-01 00:03:00+00', 20.0), ('2020-01-01 00:04:00+00', 10.0)";
             client.select(stmt, None, None);
 
@@ -582,6 +781,22 @@ mod tests {
             let stmt = "SELECT num_changes(counter_agg(ts, val)) FROM test";
             assert_eq!(select_one!(client, stmt, i64), 7);
 
+            let stmt = "SELECT first_val(counter_agg(ts, val)) FROM test";
+            assert_relative_eq!(select_one!(client, stmt, f64), 10.0);
+
+            let stmt = "SELECT last_val(counter_agg(ts, val)) FROM test";
+            assert_relative_eq!(select_one!(client, stmt, f64), 40.0);
+
+            let stmt = "SELECT first_time(counter_agg(ts, val)) = '2020-01-01 00:00:00+00' FROM test";
+            assert!(select_one!(client, stmt, bool));
+
+            let stmt = "SELECT last_time(counter_agg(ts, val)) = '2020-01-01 00:20:00+00' FROM test";
+            assert!(select_one!(client, stmt, bool));
+
+            let stmt = "SELECT array_agg(r) = ARRAY['2020-01-01 00:02:00+00', '2020-01-01 00:04:00+00', '2020-01-01 00:10:30+00']::timestamptz[] \
+                FROM resets((SELECT counter_agg(ts, val) FROM test)) r";
+            assert!(select_one!(client, stmt, bool));
+
             //combine function works as expected
             let stmt = "SELECT counter_agg(ts, val) FROM test";
             let a = select_one!(client,stmt, toolkit_experimental::CounterSummary);
@@ -591,6 +806,100 @@ mod tests {
         });
     }
 
+    #[pg_test]
+    fn test_counter_reset_tolerance() {
+        Spi::execute(|client| {
+            client.select("CREATE TABLE test(ts timestamptz, val DOUBLE PRECISION)", None, None);
+            // set search_path after defining our table so we don't pollute the wrong schema
+            let stmt = "SELECT format('toolkit_experimental, %s',current_setting('search_path'))";
+            let search_path = select_one!(client, stmt, String);
+            client.select(&format!("SET LOCAL search_path TO {}", search_path), None, None);
+            let stmt = "INSERT INTO test VALUES('2020-01-01 00:00:00+00', 1000.0), ('2020-01-01 00:01:00+00', 999.9), ('2020-01-01 00:02:00+00', 1001.0), ('2020-01-01 00:03:00+00', 10.0)";
+            client.select(stmt, None, None);
+
+            let stmt = "SELECT num_resets(counter_agg(ts, val)) FROM test";
+            assert_eq!(select_one!(client, stmt, i64), 2);
+
+            let stmt = "SELECT num_resets(counter_agg(ts, val, NULL, 0.5, 0)) FROM test";
+            assert_eq!(select_one!(client, stmt, i64), 1);
+            let stmt = "SELECT delta(counter_agg(ts, val, NULL, 0.5, 0)) FROM test";
+            assert_relative_eq!(select_one!(client, stmt, f64), 11.0);
+
+            let stmt = "SELECT num_resets(counter_agg(ts, val, NULL, 0, 0.001)) FROM test";
+            assert_eq!(select_one!(client, stmt, i64), 1);
+
+            // the tolerance is kept in the summary and used by rollup
+            let stmt = "SELECT counter_agg(ts, val, NULL, 0.5, 0) FROM test";
+            let a = select_one!(client,stmt, toolkit_experimental::CounterSummary);
+            let stmt = "WITH t as (SELECT date_trunc('minute', ts), counter_agg(ts, val, NULL, 0.5, 0) as agg FROM test group by 1 ) SELECT rollup(agg) FROM t";
+            let b = select_one!(client,stmt, toolkit_experimental::CounterSummary);
+            assert_close_enough(&a.to_internal_counter_summary(), &b.to_internal_counter_summary());
+            assert_eq!(b.to_internal_counter_summary().num_resets, 1);
+        });
+    }
+
+    #[pg_test]
+    fn test_counter_io() {
+        Spi::execute(|client| {
+            client.select("CREATE TABLE test(ts timestamptz, val DOUBLE PRECISION)", None, None);
+            // set search_path after defining our table so we don't pollute the wrong schema
+            let stmt = "SELECT format('toolkit_experimental, %s',current_setting('search_path'))";
+            let search_path = select_one!(client, stmt, String);
+            client.select(&format!("SET LOCAL search_path TO {}", search_path), None, None);
+            let stmt = "INSERT INTO test VALUES('2020-01-01 00:02:00+00', 30.0), ('2020-01-01 00:03:00+00', 5.0)";
+            client.select(stmt, None, None);
+
+            // summaries from before the reset tolerance, reset times and
+            // compensated sums were stored are still readable
+            let v1 = "{\
+                \"version\":1,\
+                \"stats\":{\"n\":2,\"sx\":1262304060.0,\"sxx\":1800.0,\"sy\":30.0,\"syy\":50.0,\"sxy\":300.0},\
+                \"first\":{\"ts\":\"2020-01-01 00:00:00+00\",\"val\":10.0},\
+                \"second\":{\"ts\":\"2020-01-01 00:01:00+00\",\"val\":20.0},\
+                \"penultimate\":{\"ts\":\"2020-01-01 00:00:00+00\",\"val\":10.0},\
+                \"last\":{\"ts\":\"2020-01-01 00:01:00+00\",\"val\":20.0},\
+                \"reset_sum\":0.0,\
+                \"num_resets\":0,\
+                \"num_changes\":1,\
+                \"bounds\":{\"is_present\":0,\"has_left\":0,\"has_right\":0,\"padding\":[0,0,0,0,0],\"left\":null,\"right\":null}\
+            }";
+            let stmt = format!("SELECT delta('{}'::CounterSummary)", v1);
+            assert_relative_eq!(select_one!(client, &stmt, f64), 10.0);
+            let stmt = format!("SELECT slope('{}'::CounterSummary)", v1);
+            assert_relative_eq!(select_one!(client, &stmt, f64), 1.0 / 6.0);
+            let stmt = format!("SELECT num_resets('{}'::CounterSummary)", v1);
+            assert_eq!(select_one!(client, &stmt, i64), 0);
+
+            // and can be rolled up with new ones, which are written in the current version
+            let stmt = format!("WITH t AS (SELECT '{}'::CounterSummary agg UNION ALL SELECT counter_agg(ts, val) FROM test) \
+                SELECT rollup(agg)::TEXT FROM t", v1);
+            let text = select_one!(client, &stmt, String);
+            assert!(text.starts_with("{\"version\":4,"), "{}", text);
+            let summary = select_one!(client, &format!("SELECT '{}'::CounterSummary", text), toolkit_experimental::CounterSummary);
+            let summary = summary.to_internal_counter_summary();
+            assert_eq!(summary.stats.n, 4);
+            assert_relative_eq!(summary.delta(), 25.0);
+            assert_eq!(summary.num_resets, 1);
+            assert_eq!(summary.reset_times.len(), 1);
+        });
+    }
+
+    #[pg_test(error = "cannot combine counter summaries with different reset tolerances")]
+    fn test_counter_rollup_tolerance_mismatch() {
+        Spi::execute(|client| {
+            client.select("CREATE TABLE test(ts timestamptz, val DOUBLE PRECISION)", None, None);
+            // set search_path after defining our table so we don't pollute the wrong schema
+            let stmt = "SELECT format('toolkit_experimental, %s',current_setting('search_path'))";
+            let search_path = select_one!(client, stmt, String);
+            client.select(&format!("SET LOCAL search_path TO {}", search_path), None, None);
+            let stmt = "INSERT INTO test VALUES('2020-01-01 00:00:00+00', 1000.0), ('2020-01-01 00:01:00+00', 999.9)";
+            client.select(stmt, None, None);
+
+            let stmt = "WITH t as (SELECT counter_agg(ts, val, NULL, CASE WHEN ts < '2020-01-01 00:01:00+00' THEN 0.5 ELSE 0 END, 0) as agg FROM test group by ts) SELECT rollup(agg) FROM t";
+            client.select(stmt, None, None);
+        });
+    }
+
     // #[pg_test]
     // fn test_combine_aggregate(){
     //     Spi::execute(|client| {
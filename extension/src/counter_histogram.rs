@@ -0,0 +1,442 @@
+use serde::{Serialize, Deserialize};
+
+use std::{
+    slice,
+};
+
+use pgx::*;
+
+use flat_serialize::*;
+use flat_serialize_macro::FlatSerializable;
+
+use crate::{
+    aggregate_utils::in_aggregate_context,
+    json_inout_funcs,
+    flatten,
+    palloc::Internal,
+    pg_type,
+    uddsketch::UddSketch,
+};
+
+use time_series::{
+    TSPoint,
+};
+
+use counter_agg::{
+    CounterSummary as InternalCounterSummary,
+    ResetTolerance,
+    histogram::{
+        CounterHistogram as InternalCounterHistogram,
+        HistogramBucket,
+    },
+};
+use stats_agg::stats2d::StatsSummary2D;
+
+use uddsketch::UDDSketch as UddSketchInternal;
+
+#[allow(non_camel_case_types)]
+type bytea = pg_sys::Datum;
+
+// flattened form of a counter_agg::histogram::HistogramBucket
+#[derive(Clone, Copy, Debug, PartialEq, FlatSerializable, Serialize, Deserialize)]
+#[repr(C)]
+pub struct CounterHistogramBucket {
+    #[serde(with = "le_serde")]
+    le: f64,
+    stats: StatsSummary2D,
+    first: TSPoint,
+    second: TSPoint,
+    penultimate: TSPoint,
+    last: TSPoint,
+    reset_sum: f64,
+    num_resets: u64,
+    num_changes: u64,
+}
+
+// JSON cannot represent infinities, so the +Inf bucket is written the same
+// way Prometheus labels it.
+mod le_serde {
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+    pub fn serialize<S: Serializer>(le: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+        if *le == f64::INFINITY {
+            serializer.serialize_str("+Inf")
+        } else {
+            serializer.serialize_f64(*le)
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Le {
+            Value(f64),
+            Label(String),
+        }
+        match Le::deserialize(deserializer)? {
+            Le::Value(le) => Ok(le),
+            Le::Label(label) if label == "+Inf" => Ok(f64::INFINITY),
+            Le::Label(label) => Err(D::Error::custom(format!("invalid bucket bound {}", label))),
+        }
+    }
+}
+
+impl CounterHistogramBucket {
+    fn to_internal_bucket(&self) -> HistogramBucket {
+        HistogramBucket{
+            le: self.le,
+            counter: InternalCounterSummary{
+                first: self.first,
+                second: self.second,
+                penultimate: self.penultimate,
+                last: self.last,
+                reset_sum: self.reset_sum,
+                num_resets: self.num_resets,
+                num_changes: self.num_changes,
+                stats: self.stats,
+                bounds: None,
+                reset_tolerance: ResetTolerance::default(),
+                reset_times: vec![],
+            },
+        }
+    }
+
+    fn from_internal_bucket(bucket: &HistogramBucket) -> Self {
+        let counter = &bucket.counter;
+        CounterHistogramBucket{
+            le: bucket.le,
+            stats: counter.stats,
+            first: counter.first,
+            second: counter.second,
+            penultimate: counter.penultimate,
+            last: counter.last,
+            reset_sum: counter.reset_sum,
+            num_resets: counter.num_resets,
+            num_changes: counter.num_changes,
+        }
+    }
+}
+
+pg_type! {
+    #[derive(Debug)]
+    struct CounterHistogram<'input> {
+        num_buckets: u64,
+        buckets: [CounterHistogramBucket; self.num_buckets],
+    }
+}
+
+json_inout_funcs!(CounterHistogram);
+
+// hack to allow us to qualify names with "toolkit_experimental"
+// so that pgx generates the correct SQL
+mod toolkit_experimental {
+    pub(crate) use super::*;
+
+    varlena_type!(CounterHistogram);
+}
+
+impl<'input> CounterHistogram<'input> {
+    fn to_internal_counter_histogram(&self) -> InternalCounterHistogram {
+        InternalCounterHistogram{
+            buckets: self.buckets.iter().map(|b| b.to_internal_bucket()).collect(),
+        }
+    }
+
+    fn from_internal_counter_histogram(histogram: &InternalCounterHistogram) -> CounterHistogram<'static> {
+        let buckets: Vec<_> = histogram.buckets.iter()
+            .map(CounterHistogramBucket::from_internal_bucket)
+            .collect();
+        unsafe {
+            // version 2 added the higher moments to the stats of each bucket,
+            // version 3 the compensation for rounding errors in their sums
+            flatten!(
+                CounterHistogram version 3 {
+                    num_buckets: buckets.len() as u64,
+                    buckets: &buckets,
+                }
+            )
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CounterHistogramTransState {
+    // (le, point) pairs, the counter for each `le` is built once we have all the points for it
+    #[serde(skip)]
+    point_buffer: Vec<(f64, TSPoint)>,
+    // summaries for individual buckets, possibly several for the same `le`
+    // until we combine them
+    bucket_buffer: Vec<HistogramBucket>,
+}
+
+impl CounterHistogramTransState {
+    fn new() -> Self {
+        CounterHistogramTransState{point_buffer: vec![], bucket_buffer: vec![]}
+    }
+
+    fn push_point(&mut self, le: f64, value: TSPoint) {
+        if le.is_nan() {
+            panic!("histogram bucket bounds cannot be NaN")
+        }
+        self.point_buffer.push((le, value));
+    }
+
+    fn combine_points(&mut self) {
+        if self.point_buffer.is_empty() {
+            return
+        }
+        self.point_buffer.sort_unstable_by(|(le1, p1), (le2, p2)|
+            le1.partial_cmp(le2).unwrap().then(p1.ts.cmp(&p2.ts))
+        );
+        let mut current: Option<HistogramBucket> = None;
+        for (le, p) in self.point_buffer.drain(..) {
+            match &mut current {
+                Some(bucket) if bucket.le == le => bucket.counter.add_point(&p).unwrap(),
+                _ => {
+                    let next = HistogramBucket{le, counter: InternalCounterSummary::new(&p, None)};
+                    if let Some(bucket) = current.replace(next) {
+                        self.bucket_buffer.push(bucket);
+                    }
+                },
+            }
+        }
+        self.bucket_buffer.extend(current);
+    }
+
+    fn push_summary(&mut self, other: &CounterHistogramTransState) {
+        self.bucket_buffer.extend(other.bucket_buffer.iter().cloned());
+    }
+
+    fn combine_summaries(&mut self) {
+        self.combine_points();
+
+        let buckets = std::mem::take(&mut self.bucket_buffer);
+        self.bucket_buffer = InternalCounterHistogram::from_buckets(buckets).unwrap().buckets;
+    }
+}
+
+#[pg_extern(schema = "toolkit_experimental")]
+pub fn counter_histogram_trans_serialize(
+    mut state: Internal<CounterHistogramTransState>,
+) -> bytea {
+    state.combine_summaries();
+    crate::do_serialize!(state)
+}
+
+#[pg_extern(schema = "toolkit_experimental", strict)]
+pub fn counter_histogram_trans_deserialize(
+    bytes: bytea,
+    _internal: Option<Internal<()>>,
+) -> Internal<CounterHistogramTransState> {
+    crate::do_deserialize!(bytes, CounterHistogramTransState)
+}
+
+#[pg_extern(schema = "toolkit_experimental")]
+pub fn counter_histogram_trans(
+    state: Option<Internal<CounterHistogramTransState>>,
+    ts: Option<pg_sys::TimestampTz>,
+    le: Option<f64>,
+    val: Option<f64>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<CounterHistogramTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let (le, p) = match (ts, le, val) {
+                (Some(ts), Some(le), Some(val)) => (le, TSPoint{ts, val}),
+                _ => return state,
+            };
+            match state {
+                None => {
+                    let mut s = CounterHistogramTransState::new();
+                    s.push_point(le, p);
+                    Some(s.into())
+                },
+                Some(mut s) => {s.push_point(le, p); Some(s)},
+            }
+        })
+    }
+}
+
+#[pg_extern(schema = "toolkit_experimental")]
+pub fn counter_histogram_summary_trans(
+    state: Option<Internal<CounterHistogramTransState>>,
+    value: Option<toolkit_experimental::CounterHistogram>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<CounterHistogramTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let value = match value {
+                None => return state,
+                Some(value) => value.to_internal_counter_histogram(),
+            };
+            let mut state = match state {
+                None => CounterHistogramTransState::new().into(),
+                Some(state) => state,
+            };
+            state.bucket_buffer.extend(value.buckets);
+            Some(state)
+        })
+    }
+}
+
+#[pg_extern(schema = "toolkit_experimental")]
+pub fn counter_histogram_combine(
+    state1: Option<Internal<CounterHistogramTransState>>,
+    state2: Option<Internal<CounterHistogramTransState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+)  -> Option<Internal<CounterHistogramTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            match (state1, state2) {
+                (None, None) => None,
+                (None, Some(state2)) => {let mut s = state2.clone(); s.combine_points(); Some(s.into())},
+                (Some(state1), None) => {let mut s = state1.clone(); s.combine_points(); Some(s.into())},
+                (Some(state1), Some(state2)) => {
+                    let mut s1 = state1.clone();
+                    s1.combine_points();
+                    let mut s2 = state2.clone();
+                    s2.combine_points();
+                    s2.push_summary(&s1);
+                    Some(s2.into())
+                }
+            }
+        })
+    }
+}
+
+#[pg_extern(schema = "toolkit_experimental")]
+fn counter_histogram_final(
+    state: Option<Internal<CounterHistogramTransState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<toolkit_experimental::CounterHistogram<'static>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let mut state = match state {
+                None => return None,
+                Some(state) => state.clone(),
+            };
+            state.combine_summaries();
+            if state.bucket_buffer.is_empty() {
+                return None
+            }
+            let histogram = InternalCounterHistogram{buckets: state.bucket_buffer};
+            Some(CounterHistogram::from_internal_counter_histogram(&histogram))
+        })
+    }
+}
+
+extension_sql!(r#"
+CREATE AGGREGATE toolkit_experimental.counter_histogram_agg( ts timestamptz, le DOUBLE PRECISION, value DOUBLE PRECISION )
+(
+    sfunc = toolkit_experimental.counter_histogram_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.counter_histogram_final,
+    combinefunc = toolkit_experimental.counter_histogram_combine,
+    serialfunc = toolkit_experimental.counter_histogram_trans_serialize,
+    deserialfunc = toolkit_experimental.counter_histogram_trans_deserialize,
+    parallel = restricted
+);
+"#);
+
+extension_sql!(r#"
+CREATE AGGREGATE toolkit_experimental.rollup(histogram toolkit_experimental.CounterHistogram)
+(
+    sfunc = toolkit_experimental.counter_histogram_summary_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.counter_histogram_final,
+    combinefunc = toolkit_experimental.counter_histogram_combine,
+    serialfunc = toolkit_experimental.counter_histogram_trans_serialize,
+    deserialfunc = toolkit_experimental.counter_histogram_trans_deserialize,
+    parallel = restricted
+);
+"#);
+
+#[pg_extern(name="histogram_quantile", schema = "toolkit_experimental", strict, immutable)]
+fn counter_histogram_quantile(
+    quantile: f64,
+    histogram: toolkit_experimental::CounterHistogram,
+    _fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<f64> {
+    histogram.to_internal_counter_histogram().quantile(quantile)
+}
+
+// Each bucket's observations are added to the sketch at the bucket's midpoint,
+// so the sketch's error is bounded by the bucket widths, not just max_error.
+#[pg_extern(name="to_uddsketch", schema = "toolkit_experimental", strict, immutable)]
+fn counter_histogram_to_uddsketch(
+    histogram: toolkit_experimental::CounterHistogram,
+    size: default!(i32, 200),
+    max_error: default!(f64, 0.001),
+    _fcinfo: pg_sys::FunctionCallInfo,
+) -> UddSketch<'static> {
+    let mut sketch = UddSketchInternal::new(size as u64, max_error);
+    for (value, count) in histogram.to_internal_counter_histogram().observations() {
+        sketch.add_value_with_count(value, count.round() as u64);
+    }
+    UddSketch::from_internal(&sketch)
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+
+    use approx::assert_relative_eq;
+    use pgx::*;
+
+    macro_rules! select_one {
+        ($client:expr, $stmt:expr, $type:ty) => {
+            $client
+                .select($stmt, None, None)
+                .first()
+                .get_one::<$type>()
+                .unwrap()
+        };
+    }
+
+    #[pg_test]
+    fn test_counter_histogram() {
+        Spi::execute(|client| {
+            client.select("CREATE TABLE test(ts timestamptz, le DOUBLE PRECISION, val DOUBLE PRECISION)", None, None);
+            // set search_path after defining our table so we don't pollute the wrong schema
+            let stmt = "SELECT format('toolkit_experimental, %s',current_setting('search_path'))";
+            let search_path = select_one!(client, stmt, String);
+            client.select(&format!("SET LOCAL search_path TO {}", search_path), None, None);
+            let stmt = "INSERT INTO test VALUES \
+                ('2020-01-01 00:00:00+00', 0.5, 0.0), ('2020-01-01 00:00:00+00', 1.0, 0.0), ('2020-01-01 00:00:00+00', 'Infinity', 0.0), \
+                ('2020-01-01 00:01:00+00', 0.5, 50.0), ('2020-01-01 00:01:00+00', 1.0, 80.0), ('2020-01-01 00:01:00+00', 'Infinity', 100.0)";
+            client.select(stmt, None, None);
+
+            let stmt = "SELECT histogram_quantile(0.5, counter_histogram_agg(ts, le, val)) FROM test";
+            assert_relative_eq!(select_one!(client, stmt, f64), 0.5);
+            let stmt = "SELECT histogram_quantile(0.65, counter_histogram_agg(ts, le, val)) FROM test";
+            assert_relative_eq!(select_one!(client, stmt, f64), 0.75);
+            // ranks in the +Inf bucket return the highest finite bound
+            let stmt = "SELECT histogram_quantile(0.9, counter_histogram_agg(ts, le, val)) FROM test";
+            assert_relative_eq!(select_one!(client, stmt, f64), 1.0);
+
+            // the increase across the boundary between the groups is kept by rollup
+            let stmt = "SELECT histogram_quantile(0.65, rollup(h)) FROM \
+                (SELECT counter_histogram_agg(ts, le, val) h FROM test GROUP BY ts) s";
+            assert_relative_eq!(select_one!(client, stmt, f64), 0.75);
+
+            // a histogram without a +Inf bucket has no quantiles
+            let stmt = "SELECT histogram_quantile(0.5, counter_histogram_agg(ts, le, val)) FROM test WHERE le < 'Infinity'";
+            assert!(client.select(stmt, None, None).first().get_one::<f64>().is_none());
+
+            let stmt = "SELECT counter_histogram_agg(ts, le, val)::text FROM test WHERE le = 'Infinity'";
+            let text = select_one!(client, stmt, String);
+            assert!(text.contains(r#""le":"+Inf""#), "{}", text);
+            let stmt = format!("SELECT histogram_quantile(0.5, rollup(h)) FROM \
+                (SELECT '{}'::CounterHistogram h UNION ALL SELECT counter_histogram_agg(ts, le, val) FROM test WHERE le < 'Infinity') s",
+                text);
+            assert_relative_eq!(select_one!(client, &stmt, f64), 0.5);
+
+            let stmt = "SELECT num_vals(to_uddsketch(counter_histogram_agg(ts, le, val))) FROM test";
+            assert_relative_eq!(select_one!(client, stmt, f64), 100.0);
+            // bucket midpoints are 0.25, 0.75 and 1.0 for the +Inf bucket
+            let stmt = "SELECT approx_percentile(0.4, to_uddsketch(counter_histogram_agg(ts, le, val))) FROM test";
+            assert_relative_eq!(select_one!(client, stmt, f64), 0.25, max_relative = 0.001);
+            let stmt = "SELECT approx_percentile(0.6, to_uddsketch(counter_histogram_agg(ts, le, val))) FROM test";
+            assert_relative_eq!(select_one!(client, stmt, f64), 0.75, max_relative = 0.001);
+        });
+    }
+}
@@ -0,0 +1,28 @@
+use pgx::pg_sys;
+
+// pgx doesn't wrap INTERVAL, so we pass them around as raw Datums
+pub type Interval = pg_sys::Datum;
+
+// Months and years don't have a fixed length so intervals containing them are
+// rejected, days are treated as 24 hours.
+pub fn interval_to_micros(interval: Interval) -> i64 {
+    unsafe {
+        let interval = interval as *const pg_sys::Interval;
+        if (*interval).month != 0 {
+            panic!("intervals cannot contain months or years")
+        }
+        (*interval).time + (*interval).day as i64 * 24 * 60 * 60 * 1_000_000
+    }
+}
+
+pub fn interval_from_micros(micros: i64) -> Interval {
+    interval_from_parts(0, 0, micros)
+}
+
+pub fn interval_from_parts(months: i32, days: i32, micros: i64) -> Interval {
+    unsafe {
+        let interval = pg_sys::palloc(std::mem::size_of::<pg_sys::Interval>()) as *mut pg_sys::Interval;
+        *interval = pg_sys::Interval{time: micros, day: days, month: months};
+        interval as Interval
+    }
+}
@@ -0,0 +1,283 @@
+use pgx::*;
+
+use flat_serialize::*;
+
+use crate::{
+    aggregate_utils::in_aggregate_context,
+    datum_utils::{interval_to_micros, Interval},
+    json_inout_funcs,
+    flatten,
+    palloc::Internal,
+    pg_type,
+};
+
+use stats_agg::StatsError;
+use stats_agg::ewma::EwmaSummary as InternalEwmaSummary;
+
+#[allow(non_camel_case_types)]
+type bytea = pg_sys::Datum;
+
+pg_type! {
+    #[derive(Debug)]
+    struct EwmaSummary {
+        half_life: i64,
+        n: u64,
+        reference_time: i64,
+        weight: f64,
+        mean: f64,
+        m2: f64,
+    }
+}
+
+json_inout_funcs!(EwmaSummary);
+
+// hack to allow us to qualify names with "toolkit_experimental"
+// so that pgx generates the correct SQL
+mod toolkit_experimental {
+    pub(crate) use super::*;
+
+    varlena_type!(EwmaSummary);
+}
+
+impl<'input> EwmaSummary<'input> {
+    fn to_internal(&self) -> InternalEwmaSummary {
+        InternalEwmaSummary{
+            half_life: self.half_life,
+            n: self.n,
+            reference_time: self.reference_time,
+            weight: self.weight,
+            mean: self.mean,
+            m2: self.m2,
+        }
+    }
+
+    fn from_internal(st: &InternalEwmaSummary) -> EwmaSummary<'static> {
+        unsafe {
+            flatten!(
+                EwmaSummary {
+                    half_life: st.half_life,
+                    n: st.n,
+                    reference_time: st.reference_time,
+                    weight: st.weight,
+                    mean: st.mean,
+                    m2: st.m2,
+                }
+            )
+        }
+    }
+}
+
+fn unwrap_half_life<T>(result: Result<T, StatsError>) -> T {
+    match result {
+        Ok(t) => t,
+        Err(StatsError::HalfLifeMismatch) => panic!("cannot combine ewma summaries with different half-lives"),
+        Err(e) => panic!("ewma error: {:?}", e),
+    }
+}
+
+#[pg_extern(schema = "toolkit_experimental")]
+pub fn ewma_trans_serialize(
+    state: Internal<InternalEwmaSummary>,
+) -> bytea {
+    let ser: &InternalEwmaSummary = &*state;
+    crate::do_serialize!(ser)
+}
+
+#[pg_extern(schema = "toolkit_experimental", strict)]
+pub fn ewma_trans_deserialize(
+    bytes: bytea,
+    _internal: Option<Internal<()>>,
+) -> Internal<InternalEwmaSummary> {
+    crate::do_deserialize!(bytes, InternalEwmaSummary)
+}
+
+#[pg_extern(schema = "toolkit_experimental")]
+pub fn ewma_trans(
+    state: Option<Internal<InternalEwmaSummary>>,
+    ts: Option<pg_sys::TimestampTz>,
+    value: Option<f64>,
+    half_life: Option<Interval>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<InternalEwmaSummary>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let (ts, value, half_life) = match (ts, value, half_life) {
+                (Some(ts), Some(value), Some(half_life)) => (ts, value, interval_to_micros(half_life)),
+                _ => return state,
+            };
+            if half_life <= 0 {
+                panic!("half_life must be positive")
+            }
+            let mut state = match state {
+                None => InternalEwmaSummary::new(half_life).into(),
+                Some(state) => state,
+            };
+            if state.half_life != half_life {
+                panic!("half_life must be the same for every row of an ewma")
+            }
+            unwrap_half_life(state.accum(ts, value));
+            Some(state)
+        })
+    }
+}
+
+#[pg_extern(schema = "toolkit_experimental")]
+pub fn ewma_summary_trans(
+    state: Option<Internal<InternalEwmaSummary>>,
+    value: Option<toolkit_experimental::EwmaSummary>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<InternalEwmaSummary>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            match (state, value) {
+                (state, None) => state,
+                (None, Some(value)) => Some(value.to_internal().into()),
+                (Some(state), Some(value)) => {
+                    let s = unwrap_half_life(state.combine(&value.to_internal()));
+                    Some(s.into())
+                }
+            }
+        })
+    }
+}
+
+#[pg_extern(schema = "toolkit_experimental")]
+pub fn ewma_combine(
+    state1: Option<Internal<InternalEwmaSummary>>,
+    state2: Option<Internal<InternalEwmaSummary>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<InternalEwmaSummary>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            match (state1, state2) {
+                (None, None) => None,
+                (None, Some(state2)) => Some((*state2).into()),
+                (Some(state1), None) => Some((*state1).into()),
+                (Some(state1), Some(state2)) => {
+                    let s = unwrap_half_life(state1.combine(&*state2));
+                    Some(s.into())
+                }
+            }
+        })
+    }
+}
+
+#[pg_extern(schema = "toolkit_experimental")]
+fn ewma_final(
+    state: Option<Internal<InternalEwmaSummary>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<toolkit_experimental::EwmaSummary<'static>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            state.map(|state| EwmaSummary::from_internal(&*state))
+        })
+    }
+}
+
+extension_sql!(r#"
+CREATE AGGREGATE toolkit_experimental.ewma( ts TIMESTAMPTZ, value DOUBLE PRECISION, half_life INTERVAL )
+(
+    sfunc = toolkit_experimental.ewma_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.ewma_final,
+    combinefunc = toolkit_experimental.ewma_combine,
+    serialfunc = toolkit_experimental.ewma_trans_serialize,
+    deserialfunc = toolkit_experimental.ewma_trans_deserialize,
+    parallel = safe
+);
+"#);
+
+extension_sql!(r#"
+CREATE AGGREGATE toolkit_experimental.rollup(summary toolkit_experimental.ewmasummary)
+(
+    sfunc = toolkit_experimental.ewma_summary_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.ewma_final,
+    combinefunc = toolkit_experimental.ewma_combine,
+    serialfunc = toolkit_experimental.ewma_trans_serialize,
+    deserialfunc = toolkit_experimental.ewma_trans_deserialize,
+    parallel = safe
+);
+"#);
+
+#[pg_extern(name="average", schema = "toolkit_experimental", strict, immutable)]
+fn ewma_average(
+    summary: toolkit_experimental::EwmaSummary,
+    _fcinfo: pg_sys::FunctionCallInfo,
+)-> Option<f64> {
+    summary.to_internal().mean()
+}
+
+#[pg_extern(name="variance", schema = "toolkit_experimental", strict, immutable)]
+fn ewma_variance(
+    summary: toolkit_experimental::EwmaSummary,
+    _fcinfo: pg_sys::FunctionCallInfo,
+)-> Option<f64> {
+    summary.to_internal().variance()
+}
+
+#[pg_extern(name="stddev", schema = "toolkit_experimental", strict, immutable)]
+fn ewma_stddev(
+    summary: toolkit_experimental::EwmaSummary,
+    _fcinfo: pg_sys::FunctionCallInfo,
+)-> Option<f64> {
+    Some(summary.to_internal().variance()?.sqrt())
+}
+
+#[pg_extern(name="num_vals", schema = "toolkit_experimental", strict, immutable)]
+fn ewma_num_vals(
+    summary: toolkit_experimental::EwmaSummary,
+    _fcinfo: pg_sys::FunctionCallInfo,
+)-> i64 {
+    summary.to_internal().count()
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+
+    use approx::assert_relative_eq;
+    use pgx::*;
+
+    macro_rules! select_one {
+        ($client:expr, $stmt:expr, $type:ty) => {
+            $client
+                .select($stmt, None, None)
+                .first()
+                .get_one::<$type>()
+                .unwrap()
+        };
+    }
+
+    #[pg_test]
+    fn test_ewma() {
+        Spi::execute(|client| {
+            client.select("CREATE TABLE test(ts TIMESTAMPTZ, val DOUBLE PRECISION)", None, None);
+            // set search_path after defining our table so we don't pollute the wrong schema
+            let stmt = "SELECT format('toolkit_experimental, %s',current_setting('search_path'))";
+            let search_path = select_one!(client, stmt, String);
+            client.select(&format!("SET LOCAL search_path TO {}", search_path), None, None);
+            client.select("INSERT INTO test VALUES \
+                ('2020-01-01 00:00:00+00', 1.0), \
+                ('2020-01-01 00:10:00+00', 4.0), \
+                ('2020-01-01 00:20:00+00', 10.0)", None, None);
+
+            // the weights are 1/4, 1/2 and 1
+            let stmt = "SELECT average(ewma(ts, val, '10 minutes')) FROM test";
+            assert_relative_eq!(select_one!(client, stmt, f64), 12.25 / 1.75, max_relative = 1e-12);
+            let expected_variance = (0.25 * 36.0 + 0.5 * 9.0 + 9.0) / 1.75;
+            let stmt = "SELECT variance(ewma(ts, val, '10 minutes')) FROM test";
+            assert_relative_eq!(select_one!(client, stmt, f64), expected_variance, max_relative = 1e-12);
+            let stmt = "SELECT num_vals(ewma(ts, val, '10 minutes')) FROM test";
+            assert_eq!(select_one!(client, stmt, i64), 3);
+
+            // rollup gives the same result in either order
+            let stmt = "SELECT average(rollup(summary ORDER BY ts DESC)) FROM ( \
+                    SELECT ts, ewma(ts, val, '10 minutes') AS summary FROM test GROUP BY ts \
+                ) s";
+            assert_relative_eq!(select_one!(client, stmt, f64), 12.25 / 1.75, max_relative = 1e-12);
+
+            let stmt = "SELECT average(ewma(ts, val, '10 minutes')) FROM test WHERE false";
+            assert!(client.select(stmt, None, None).first().get_one::<f64>().is_none());
+        });
+    }
+}
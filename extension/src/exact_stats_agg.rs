@@ -0,0 +1,625 @@
+use pgx::*;
+
+use serde::{Deserialize, Serialize};
+
+use flat_serialize::*;
+
+use crate::{
+    aggregate_utils::in_aggregate_context,
+    json_inout_funcs,
+    flatten,
+    palloc::Internal,
+    pg_type,
+};
+
+use stats_agg::StatsError;
+use stats_agg::decimal::Decimal;
+use stats_agg::stats1d::StatsSummary1D as InternalStatsSummary1D;
+use stats_agg::exact1d::ExactStatsSummary1D as InternalExactStatsSummary1D;
+
+#[allow(non_camel_case_types)]
+type bytea = pg_sys::Datum;
+
+// stats_agg() for bigint and numeric values, which also keeps the sum of the
+// values exactly so that sum() returns the same numeric as the builtin
+// aggregate. The exact sum is stored as the two halves of an i128 and the
+// number of decimal places, or, if it doesn't fit in those, as the text of a
+// numeric in `numeric_sum`, which is otherwise empty.
+pg_type! {
+    #[derive(Debug, PartialEq)]
+    struct ExactStatsSummary1D<'input> {
+        n: u64,
+        sx: f64,
+        sxx: f64,
+        sx3: f64,
+        sx4: f64,
+        sxc: f64,
+        sum_high: i64,
+        sum_low: u64,
+        sum_scale: u64,
+        numeric_sum_len: u32,
+        numeric_sum: [u8; self.numeric_sum_len],
+    }
+}
+
+json_inout_funcs!(ExactStatsSummary1D);
+
+// hack to allow us to qualify names with "toolkit_experimental"
+// so that pgx generates the correct SQL
+mod toolkit_experimental {
+    pub(crate) use super::*;
+
+    varlena_type!(ExactStatsSummary1D);
+}
+
+impl<'input> ExactStatsSummary1D<'input> {
+    fn to_internal(&self) -> ExactStatsTransState {
+        let numeric_sum = match self.numeric_sum.is_empty() {
+            true => None,
+            false => Some(String::from_utf8(self.numeric_sum.to_vec()).unwrap()),
+        };
+        ExactStatsTransState {
+            summary: InternalExactStatsSummary1D {
+                stats: InternalStatsSummary1D {
+                    n: self.n,
+                    sx: self.sx,
+                    sxx: self.sxx,
+                    sx3: self.sx3,
+                    sx4: self.sx4,
+                    sxc: self.sxc,
+                },
+                sum: Decimal {
+                    value: ((self.sum_high as i128) << 64) | self.sum_low as i128,
+                    scale: self.sum_scale as u32,
+                },
+            },
+            numeric_sum,
+        }
+    }
+
+    fn from_internal(st: &ExactStatsTransState) -> ExactStatsSummary1D<'static> {
+        let summary = &st.summary;
+        let numeric_sum = st.numeric_sum.as_deref().unwrap_or("").as_bytes();
+        unsafe {
+            flatten!(
+                ExactStatsSummary1D {
+                    n: summary.stats.n,
+                    sx: summary.stats.sx,
+                    sxx: summary.stats.sxx,
+                    sx3: summary.stats.sx3,
+                    sx4: summary.stats.sx4,
+                    sxc: summary.stats.sxc,
+                    sum_high: (summary.sum.value >> 64) as i64,
+                    sum_low: summary.sum.value as u64,
+                    sum_scale: summary.sum.scale as u64,
+                    numeric_sum_len: numeric_sum.len() as u32,
+                    numeric_sum: numeric_sum,
+                }
+            )
+        }
+    }
+}
+
+// The transition state. The sum is kept as a Decimal while it fits in one.
+// Once it doesn't, or a value doesn't, such as NaN, an infinity or a numeric
+// with more than 38 digits, it is kept as a Postgres numeric instead, and
+// Postgres does the arithmetic.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct ExactStatsTransState {
+    // summary.sum is zero and unused once numeric_sum is set
+    summary: InternalExactStatsSummary1D,
+    numeric_sum: Option<String>,
+}
+
+fn unwrap_stats<T>(result: Result<T, StatsError>) -> T {
+    result.unwrap_or_else(|e| panic!("stats_agg error: {:?}", e))
+}
+
+// applies a numeric operator such as numeric_add to numerics in text form
+fn numeric_op(op: unsafe fn(pg_sys::FunctionCallInfo) -> pg_sys::Datum, a: &str, b: &str) -> String {
+    let (a, b) = (pgx::Numeric(a.to_string()), pgx::Numeric(b.to_string()));
+    direct_function_call::<pgx::Numeric>(op, vec![a.into_datum(), b.into_datum()]).unwrap().0
+}
+
+// the nearest double to a numeric, including NaN and the infinities
+fn numeric_to_f64(value: &str) -> f64 {
+    value.parse().unwrap()
+}
+
+impl ExactStatsTransState {
+    fn new() -> Self {
+        ExactStatsTransState {
+            summary: InternalExactStatsSummary1D::new(),
+            numeric_sum: None,
+        }
+    }
+
+    fn sum_text(&self) -> String {
+        match &self.numeric_sum {
+            Some(sum) => sum.clone(),
+            None => self.summary.sum.to_string(),
+        }
+    }
+
+    // `value` is the text form of a numeric
+    fn accum(&mut self, value: &str) {
+        if self.numeric_sum.is_none() {
+            if let Ok(decimal) = value.parse() {
+                match self.summary.accum(decimal) {
+                    Ok(()) => return,
+                    Err(StatsError::SumOverflow) => (),
+                    Err(e) => panic!("stats_agg error: {:?}", e),
+                }
+            }
+        }
+        self.numeric_sum = Some(numeric_op(pg_sys::numeric_add, &self.sum_text(), value));
+        self.summary.sum = Decimal::zero();
+        unwrap_stats(self.summary.stats.accum(numeric_to_f64(value)));
+    }
+
+    fn remove(&self, value: &str) -> Option<Self> {
+        if let (None, Ok(decimal)) = (&self.numeric_sum, value.parse()) {
+            return Some(ExactStatsTransState {
+                summary: self.summary.remove(decimal)?,
+                numeric_sum: None,
+            });
+        }
+        Some(ExactStatsTransState {
+            summary: InternalExactStatsSummary1D {
+                stats: self.summary.stats.remove(numeric_to_f64(value))?,
+                sum: Decimal::zero(),
+            },
+            numeric_sum: Some(numeric_op(pg_sys::numeric_sub, &self.sum_text(), value)),
+        })
+    }
+
+    fn combine(&self, other: &ExactStatsTransState) -> Self {
+        if self.numeric_sum.is_none() && other.numeric_sum.is_none() {
+            match self.summary.combine(&other.summary) {
+                Ok(summary) => return ExactStatsTransState { summary, numeric_sum: None },
+                Err(StatsError::SumOverflow) => (),
+                Err(e) => panic!("stats_agg error: {:?}", e),
+            }
+        }
+        ExactStatsTransState {
+            summary: InternalExactStatsSummary1D {
+                stats: unwrap_stats(self.summary.stats.combine(other.summary.stats)),
+                sum: Decimal::zero(),
+            },
+            numeric_sum: Some(numeric_op(pg_sys::numeric_add, &self.sum_text(), &other.sum_text())),
+        }
+    }
+
+    fn remove_combined(&self, other: &ExactStatsTransState) -> Option<Self> {
+        if self.numeric_sum.is_none() && other.numeric_sum.is_none() {
+            return Some(ExactStatsTransState {
+                summary: self.summary.remove_combined(&other.summary)?,
+                numeric_sum: None,
+            });
+        }
+        Some(ExactStatsTransState {
+            summary: InternalExactStatsSummary1D {
+                stats: self.summary.stats.remove_combined(other.summary.stats)?,
+                sum: Decimal::zero(),
+            },
+            numeric_sum: Some(numeric_op(pg_sys::numeric_sub, &self.sum_text(), &other.sum_text())),
+        })
+    }
+
+    fn sum(&self) -> Option<pgx::Numeric> {
+        if self.summary.count() == 0 {
+            return None;
+        }
+        Some(pgx::Numeric(self.sum_text()))
+    }
+
+    // a sum kept as a numeric is divided the same way as the builtin avg()
+    fn avg(&self) -> Option<pgx::Numeric> {
+        match &self.numeric_sum {
+            None => self.summary.avg().map(|avg| pgx::Numeric(avg.to_string())),
+            Some(sum) => {
+                let count = self.summary.count().to_string();
+                Some(pgx::Numeric(numeric_op(pg_sys::numeric_div, sum, &count)))
+            }
+        }
+    }
+}
+
+#[pg_extern(schema = "toolkit_experimental")]
+pub fn exact_stats1d_trans_serialize(
+    state: Internal<ExactStatsTransState>,
+) -> bytea {
+    let ser: &ExactStatsTransState = &*state;
+    crate::do_serialize!(ser)
+}
+
+#[pg_extern(schema = "toolkit_experimental", strict)]
+pub fn exact_stats1d_trans_deserialize(
+    bytes: bytea,
+    _internal: Option<Internal<()>>,
+) -> Internal<ExactStatsTransState> {
+    crate::do_deserialize!(bytes, ExactStatsTransState)
+}
+
+fn exact_stats1d_trans_inner(
+    state: Option<Internal<ExactStatsTransState>>,
+    val: Option<String>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<ExactStatsTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            // return an empty state rather than NULL, as with stats1d_trans, so
+            // that the inverse function works in the window context
+            let mut state = match state {
+                None => ExactStatsTransState::new().into(),
+                Some(state) => state,
+            };
+            if let Some(val) = val {
+                state.accum(&val);
+            }
+            Some(state)
+        })
+    }
+}
+
+fn exact_stats1d_inv_trans_inner(
+    state: Option<Internal<ExactStatsTransState>>,
+    val: Option<String>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<ExactStatsTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            match (state, val) {
+                (None, _) => panic!("Inverse function should never be called with NULL state"),
+                (Some(state), None) => Some(state),
+                (Some(state), Some(val)) => state.remove(&val).map(Into::into),
+            }
+        })
+    }
+}
+
+#[pg_extern(schema = "toolkit_experimental")]
+pub fn exact_stats1d_numeric_trans(
+    state: Option<Internal<ExactStatsTransState>>,
+    val: Option<pgx::Numeric>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<ExactStatsTransState>> {
+    exact_stats1d_trans_inner(state, val.map(|val| val.0), fcinfo)
+}
+
+#[pg_extern(schema = "toolkit_experimental")]
+pub fn exact_stats1d_bigint_trans(
+    state: Option<Internal<ExactStatsTransState>>,
+    val: Option<i64>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<ExactStatsTransState>> {
+    exact_stats1d_trans_inner(state, val.map(|val| val.to_string()), fcinfo)
+}
+
+#[pg_extern(schema = "toolkit_experimental")]
+pub fn exact_stats1d_numeric_inv_trans(
+    state: Option<Internal<ExactStatsTransState>>,
+    val: Option<pgx::Numeric>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<ExactStatsTransState>> {
+    exact_stats1d_inv_trans_inner(state, val.map(|val| val.0), fcinfo)
+}
+
+#[pg_extern(schema = "toolkit_experimental")]
+pub fn exact_stats1d_bigint_inv_trans(
+    state: Option<Internal<ExactStatsTransState>>,
+    val: Option<i64>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<ExactStatsTransState>> {
+    exact_stats1d_inv_trans_inner(state, val.map(|val| val.to_string()), fcinfo)
+}
+
+#[pg_extern(schema = "toolkit_experimental")]
+pub fn exact_stats1d_summary_trans(
+    state: Option<Internal<ExactStatsTransState>>,
+    value: Option<toolkit_experimental::ExactStatsSummary1D>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<ExactStatsTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            match (state, value) {
+                (state, None) => state,
+                (None, Some(value)) => Some(value.to_internal().into()),
+                (Some(state), Some(value)) => Some(state.combine(&value.to_internal()).into()),
+            }
+        })
+    }
+}
+
+#[pg_extern(schema = "toolkit_experimental")]
+pub fn exact_stats1d_summary_inv_trans(
+    state: Option<Internal<ExactStatsTransState>>,
+    value: Option<toolkit_experimental::ExactStatsSummary1D>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<ExactStatsTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            match (state, value) {
+                (None, _) => panic!("Inverse function should never be called with NULL state"),
+                (Some(state), None) => Some(state),
+                (Some(state), Some(value)) => state.remove_combined(&value.to_internal()).map(Into::into),
+            }
+        })
+    }
+}
+
+#[pg_extern(schema = "toolkit_experimental")]
+pub fn exact_stats1d_combine(
+    state1: Option<Internal<ExactStatsTransState>>,
+    state2: Option<Internal<ExactStatsTransState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<ExactStatsTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            match (state1, state2) {
+                (None, None) => None,
+                (None, Some(state2)) => Some((*state2).clone().into()),
+                (Some(state1), None) => Some((*state1).clone().into()),
+                (Some(state1), Some(state2)) => Some(state1.combine(&*state2).into()),
+            }
+        })
+    }
+}
+
+#[pg_extern(schema = "toolkit_experimental")]
+fn exact_stats1d_final(
+    state: Option<Internal<ExactStatsTransState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<toolkit_experimental::ExactStatsSummary1D<'static>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            state.map(|state| ExactStatsSummary1D::from_internal(&*state))
+        })
+    }
+}
+
+extension_sql!(r#"
+CREATE AGGREGATE toolkit_experimental.stats_agg( value NUMERIC )
+(
+    sfunc = toolkit_experimental.exact_stats1d_numeric_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.exact_stats1d_final,
+    combinefunc = toolkit_experimental.exact_stats1d_combine,
+    serialfunc = toolkit_experimental.exact_stats1d_trans_serialize,
+    deserialfunc = toolkit_experimental.exact_stats1d_trans_deserialize,
+    msfunc = toolkit_experimental.exact_stats1d_numeric_trans,
+    minvfunc = toolkit_experimental.exact_stats1d_numeric_inv_trans,
+    mstype = internal,
+    mfinalfunc = toolkit_experimental.exact_stats1d_final,
+    parallel = safe
+);
+"#);
+
+extension_sql!(r#"
+CREATE AGGREGATE toolkit_experimental.stats_agg( value BIGINT )
+(
+    sfunc = toolkit_experimental.exact_stats1d_bigint_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.exact_stats1d_final,
+    combinefunc = toolkit_experimental.exact_stats1d_combine,
+    serialfunc = toolkit_experimental.exact_stats1d_trans_serialize,
+    deserialfunc = toolkit_experimental.exact_stats1d_trans_deserialize,
+    msfunc = toolkit_experimental.exact_stats1d_bigint_trans,
+    minvfunc = toolkit_experimental.exact_stats1d_bigint_inv_trans,
+    mstype = internal,
+    mfinalfunc = toolkit_experimental.exact_stats1d_final,
+    parallel = safe
+);
+"#);
+
+extension_sql!(r#"
+CREATE AGGREGATE toolkit_experimental.rollup(ss toolkit_experimental.exactstatssummary1d)
+(
+    sfunc = toolkit_experimental.exact_stats1d_summary_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.exact_stats1d_final,
+    combinefunc = toolkit_experimental.exact_stats1d_combine,
+    serialfunc = toolkit_experimental.exact_stats1d_trans_serialize,
+    deserialfunc = toolkit_experimental.exact_stats1d_trans_deserialize,
+    parallel = safe
+);
+"#);
+
+extension_sql!(r#"
+CREATE AGGREGATE toolkit_experimental.rolling(ss toolkit_experimental.exactstatssummary1d)
+(
+    sfunc = toolkit_experimental.exact_stats1d_summary_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.exact_stats1d_final,
+    combinefunc = toolkit_experimental.exact_stats1d_combine,
+    serialfunc = toolkit_experimental.exact_stats1d_trans_serialize,
+    deserialfunc = toolkit_experimental.exact_stats1d_trans_deserialize,
+    msfunc = toolkit_experimental.exact_stats1d_summary_trans,
+    minvfunc = toolkit_experimental.exact_stats1d_summary_inv_trans,
+    mstype = internal,
+    mfinalfunc = toolkit_experimental.exact_stats1d_final,
+    parallel = safe
+);
+"#);
+
+#[pg_extern(name="average", schema = "toolkit_experimental", strict, immutable)]
+fn exact_stats1d_average(
+    summary: toolkit_experimental::ExactStatsSummary1D,
+    _fcinfo: pg_sys::FunctionCallInfo,
+)-> Option<pgx::Numeric> {
+    summary.to_internal().avg()
+}
+
+#[pg_extern(name="sum", schema = "toolkit_experimental", strict, immutable)]
+fn exact_stats1d_sum(
+    summary: toolkit_experimental::ExactStatsSummary1D,
+    _fcinfo: pg_sys::FunctionCallInfo,
+)-> Option<pgx::Numeric> {
+    summary.to_internal().sum()
+}
+
+#[pg_extern(name="num_vals", schema = "toolkit_experimental", strict, immutable)]
+fn exact_stats1d_num_vals(
+    summary: toolkit_experimental::ExactStatsSummary1D,
+    _fcinfo: pg_sys::FunctionCallInfo,
+)-> i64 {
+    summary.to_internal().summary.count()
+}
+
+#[pg_extern(name="stddev", schema = "toolkit_experimental", immutable)]
+fn exact_stats1d_stddev(
+    summary: Option<toolkit_experimental::ExactStatsSummary1D>,
+    method: default!(String, "population"),
+    _fcinfo: pg_sys::FunctionCallInfo,
+)-> Option<f64> {
+    match method.trim().to_lowercase().as_str() {
+        "population" | "pop" => summary?.to_internal().summary.stats.stddev_pop(),
+        "sample" | "samp" => summary?.to_internal().summary.stats.stddev_samp(),
+        _ => panic!("unknown analysis method"),
+    }
+}
+
+#[pg_extern(name="variance", schema = "toolkit_experimental", immutable)]
+fn exact_stats1d_variance(
+    summary: Option<toolkit_experimental::ExactStatsSummary1D>,
+    method: default!(String, "population"),
+    _fcinfo: pg_sys::FunctionCallInfo,
+)-> Option<f64> {
+    match method.trim().to_lowercase().as_str() {
+        "population" | "pop" => summary?.to_internal().summary.stats.var_pop(),
+        "sample" | "samp" => summary?.to_internal().summary.stats.var_samp(),
+        _ => panic!("unknown analysis method"),
+    }
+}
+
+#[pg_extern(name="skewness", schema = "toolkit_experimental", immutable)]
+fn exact_stats1d_skewness(
+    summary: Option<toolkit_experimental::ExactStatsSummary1D>,
+    method: default!(String, "population"),
+    _fcinfo: pg_sys::FunctionCallInfo,
+)-> Option<f64> {
+    match method.trim().to_lowercase().as_str() {
+        "population" | "pop" => summary?.to_internal().summary.stats.skewness_pop(),
+        "sample" | "samp" => summary?.to_internal().summary.stats.skewness_samp(),
+        _ => panic!("unknown analysis method"),
+    }
+}
+
+#[pg_extern(name="kurtosis", schema = "toolkit_experimental", immutable)]
+fn exact_stats1d_kurtosis(
+    summary: Option<toolkit_experimental::ExactStatsSummary1D>,
+    method: default!(String, "population"),
+    _fcinfo: pg_sys::FunctionCallInfo,
+)-> Option<f64> {
+    match method.trim().to_lowercase().as_str() {
+        "population" | "pop" => summary?.to_internal().summary.stats.kurtosis_pop(),
+        "sample" | "samp" => summary?.to_internal().summary.stats.kurtosis_samp(),
+        _ => panic!("unknown analysis method"),
+    }
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+
+    use approx::assert_relative_eq;
+    use pgx::*;
+
+    macro_rules! select_one {
+        ($client:expr, $stmt:expr, $type:ty) => {
+            $client
+                .select($stmt, None, None)
+                .first()
+                .get_one::<$type>()
+                .unwrap()
+        };
+    }
+
+    #[pg_test]
+    fn test_exact_stats_agg() {
+        Spi::execute(|client| {
+            client.select("CREATE TABLE test(big BIGINT, num NUMERIC)", None, None);
+            // set search_path after defining our table so we don't pollute the wrong schema
+            let stmt = "SELECT format('toolkit_experimental, %s',current_setting('search_path'))";
+            let search_path = select_one!(client, stmt, String);
+            client.select(&format!("SET LOCAL search_path TO {}", search_path), None, None);
+            client.select("INSERT INTO test VALUES \
+                (9007199254740993, 0.1), \
+                (9007199254740993, 0.2), \
+                (1, 0.30), \
+                (NULL, NULL)", None, None);
+
+            // doubles would round the bigints and the sum of the numerics
+            let stmt = "SELECT sum(stats_agg(big))::text = sum(big)::text FROM test";
+            assert!(select_one!(client, stmt, bool));
+            // we round averages to 16 decimal places, Postgres to 16 significant digits
+            let stmt = "SELECT round(average(stats_agg(big)), 6) = round(avg(big), 6) FROM test";
+            assert!(select_one!(client, stmt, bool));
+            let stmt = "SELECT sum(stats_agg(num))::text FROM test";
+            assert_eq!(select_one!(client, stmt, String), "0.60");
+            let stmt = "SELECT average(stats_agg(num)) = avg(num) FROM test";
+            assert!(select_one!(client, stmt, bool));
+            let stmt = "SELECT num_vals(stats_agg(num)) FROM test";
+            assert_eq!(select_one!(client, stmt, i64), 3);
+            let stmt = "SELECT variance(stats_agg(num), 'sample') FROM test";
+            assert_relative_eq!(select_one!(client, stmt, f64), 0.01, max_relative = 1e-12);
+
+            let stmt = "SELECT sum(rollup(s))::text FROM (SELECT stats_agg(num) s FROM test GROUP BY big) ss";
+            assert_eq!(select_one!(client, stmt, String), "0.60");
+
+            // the inverse transition keeps the sums exact as values leave the window
+            let stmt = "SELECT bool_and(exact = builtin) FROM ( \
+                    SELECT sum(stats_agg(num) OVER w)::text exact, sum(num) OVER w ::text builtin \
+                    FROM test WINDOW w AS (ORDER BY num ROWS BETWEEN 1 PRECEDING AND CURRENT ROW) \
+                ) s";
+            assert!(select_one!(client, stmt, bool));
+            let stmt = "SELECT bool_and(exact = builtin) FROM ( \
+                    SELECT sum(rolling(s) OVER w)::text exact, sum(b) OVER w ::text builtin \
+                    FROM (SELECT big b, stats_agg(big) s FROM test GROUP BY big) t \
+                    WINDOW w AS (ORDER BY b ROWS BETWEEN 1 PRECEDING AND CURRENT ROW) \
+                ) s";
+            assert!(select_one!(client, stmt, bool));
+        });
+    }
+
+    #[pg_test]
+    fn test_exact_stats_agg_numeric_sum() {
+        Spi::execute(|client| {
+            client.select("CREATE TABLE test(num NUMERIC)", None, None);
+            let stmt = "SELECT format('toolkit_experimental, %s',current_setting('search_path'))";
+            let search_path = select_one!(client, stmt, String);
+            client.select(&format!("SET LOCAL search_path TO {}", search_path), None, None);
+            // too many digits for an i128, on their own or once summed
+            client.select("INSERT INTO test VALUES \
+                (1e-40), \
+                (12345678901234567890123456789012345678901234567890), \
+                (99999999999999999999999999999999999999), \
+                (99999999999999999999999999999999999999), \
+                (0.5)", None, None);
+
+            let stmt = "SELECT sum(stats_agg(num))::text = sum(num)::text FROM test";
+            assert!(select_one!(client, stmt, bool));
+            let stmt = "SELECT average(stats_agg(num)) = avg(num) FROM test";
+            assert!(select_one!(client, stmt, bool));
+            let stmt = "SELECT num_vals(stats_agg(num)) FROM test";
+            assert_eq!(select_one!(client, stmt, i64), 5);
+            let stmt = "SELECT sum(stats_agg(num))::text = sum(num)::text FROM test WHERE num > 1";
+            assert!(select_one!(client, stmt, bool));
+
+            // through the serialized summary, rollup() and the inverse transition
+            let stmt = "SELECT sum(rollup(s))::text = (SELECT sum(num)::text FROM test) \
+                FROM (SELECT stats_agg(num)::text::exactstatssummary1d s FROM test GROUP BY num > 1) ss";
+            assert!(select_one!(client, stmt, bool));
+            let stmt = "SELECT bool_and(exact = builtin) FROM ( \
+                    SELECT sum(stats_agg(num) OVER w)::text exact, sum(num) OVER w ::text builtin \
+                    FROM test WINDOW w AS (ORDER BY num ROWS BETWEEN 1 PRECEDING AND CURRENT ROW) \
+                ) s";
+            assert!(select_one!(client, stmt, bool));
+
+            client.select("INSERT INTO test VALUES ('NaN')", None, None);
+            let stmt = "SELECT sum(stats_agg(num))::text FROM test";
+            assert_eq!(select_one!(client, stmt, String), "NaN");
+            let stmt = "SELECT average(stats_agg(num))::text FROM test";
+            assert_eq!(select_one!(client, stmt, String), "NaN");
+        });
+    }
+}
@@ -3,16 +3,23 @@ pub mod tdigest;
 pub mod hyperloglog;
 pub mod uddsketch;
 pub mod time_weighted_average;
+pub mod time_weighted_percentile;
 pub mod asap;
 pub mod lttb;
 pub mod counter_agg;
+pub mod counter_histogram;
+pub mod state_agg;
 pub mod range;
 pub mod stats_agg;
+pub mod exact_stats_agg;
+pub mod regression_agg;
+pub mod ewma;
 pub mod utilities;
 pub mod time_series;
 
 mod palloc;
 mod aggregate_utils;
+mod datum_utils;
 mod type_builder;
 mod serialization;
 mod schema_test;
@@ -0,0 +1,297 @@
+use pgx::*;
+
+use flat_serialize::*;
+
+use crate::{
+    aggregate_utils::in_aggregate_context,
+    json_inout_funcs,
+    flatten,
+    palloc::Internal,
+    pg_type,
+};
+
+use stats_agg::StatsError;
+use stats_agg::regression::RegressionSummary as InternalRegressionSummary;
+
+#[allow(non_camel_case_types)]
+type bytea = pg_sys::Datum;
+
+pg_type! {
+    #[derive(Debug)]
+    struct RegressionSummary<'input> {
+        n: u64,
+        num_x: u64,
+        // the mean of each x followed by the mean of y
+        means: [f64; self.num_x + 1],
+        // packed lower triangular, as in the stats_agg crate
+        comoments: [f64; (self.num_x + 1) * (self.num_x + 2) / 2],
+    }
+}
+
+json_inout_funcs!(RegressionSummary);
+
+// hack to allow us to qualify names with "toolkit_experimental"
+// so that pgx generates the correct SQL
+mod toolkit_experimental {
+    pub(crate) use super::*;
+
+    varlena_type!(RegressionSummary);
+}
+
+impl<'input> RegressionSummary<'input> {
+    fn to_internal(&self) -> InternalRegressionSummary {
+        InternalRegressionSummary{
+            n: self.n,
+            means: self.means.to_vec(),
+            comoments: self.comoments.to_vec(),
+        }
+    }
+
+    fn from_internal(st: &InternalRegressionSummary) -> RegressionSummary<'static> {
+        unsafe {
+            flatten!(
+                RegressionSummary {
+                    n: st.n,
+                    num_x: st.num_x() as u64,
+                    means: &st.means,
+                    comoments: &st.comoments,
+                }
+            )
+        }
+    }
+}
+
+// every row of a regression_agg must have the same number of x values
+fn unwrap_dims<T>(result: Result<T, StatsError>) -> T {
+    match result {
+        Ok(t) => t,
+        Err(StatsError::DimensionMismatch) => panic!("regression_agg inputs must all have the same number of x values"),
+        Err(e) => panic!("regression_agg error: {:?}", e),
+    }
+}
+
+#[pg_extern(schema = "toolkit_experimental")]
+pub fn regression_trans_serialize(
+    state: Internal<InternalRegressionSummary>,
+) -> bytea {
+    let ser: &InternalRegressionSummary = &*state;
+    crate::do_serialize!(ser)
+}
+
+#[pg_extern(schema = "toolkit_experimental", strict)]
+pub fn regression_trans_deserialize(
+    bytes: bytea,
+    _internal: Option<Internal<()>>,
+) -> Internal<InternalRegressionSummary> {
+    crate::do_deserialize!(bytes, InternalRegressionSummary)
+}
+
+// As with stats_agg, if y or any of the x values are NULL we disregard the
+// entire row, use COALESCE to treat NULLs as a particular value instead.
+#[pg_extern(schema = "toolkit_experimental")]
+pub fn regression_trans(
+    state: Option<Internal<InternalRegressionSummary>>,
+    y: Option<f64>,
+    x: Option<Vec<Option<f64>>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<InternalRegressionSummary>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let x: Option<Vec<f64>> = x.and_then(|x| x.into_iter().collect());
+            let (y, x) = match (y, x) {
+                (Some(y), Some(x)) => (y, x),
+                _ => return state,
+            };
+            let mut state = match state {
+                None => InternalRegressionSummary::new(x.len()).into(),
+                Some(state) => state,
+            };
+            unwrap_dims(state.accum(y, &x));
+            Some(state)
+        })
+    }
+}
+
+#[pg_extern(schema = "toolkit_experimental")]
+pub fn regression_summary_trans(
+    state: Option<Internal<InternalRegressionSummary>>,
+    value: Option<toolkit_experimental::RegressionSummary>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<InternalRegressionSummary>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            match (state, value) {
+                (state, None) => state,
+                (None, Some(value)) => Some(value.to_internal().into()),
+                (Some(state), Some(value)) => {
+                    let s = unwrap_dims(state.combine(&value.to_internal()));
+                    Some(s.into())
+                }
+            }
+        })
+    }
+}
+
+#[pg_extern(schema = "toolkit_experimental")]
+pub fn regression_combine(
+    state1: Option<Internal<InternalRegressionSummary>>,
+    state2: Option<Internal<InternalRegressionSummary>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<InternalRegressionSummary>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            match (state1, state2) {
+                (None, None) => None,
+                (None, Some(state2)) => Some(state2.clone().into()),
+                (Some(state1), None) => Some(state1.clone().into()),
+                (Some(state1), Some(state2)) => {
+                    let s = unwrap_dims(state1.combine(&*state2));
+                    Some(s.into())
+                }
+            }
+        })
+    }
+}
+
+#[pg_extern(schema = "toolkit_experimental")]
+fn regression_final(
+    state: Option<Internal<InternalRegressionSummary>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<toolkit_experimental::RegressionSummary<'static>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            state.map(|state| RegressionSummary::from_internal(&*state))
+        })
+    }
+}
+
+extension_sql!(r#"
+CREATE AGGREGATE toolkit_experimental.regression_agg( y DOUBLE PRECISION, x DOUBLE PRECISION[] )
+(
+    sfunc = toolkit_experimental.regression_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.regression_final,
+    combinefunc = toolkit_experimental.regression_combine,
+    serialfunc = toolkit_experimental.regression_trans_serialize,
+    deserialfunc = toolkit_experimental.regression_trans_deserialize,
+    parallel = safe
+);
+"#);
+
+extension_sql!(r#"
+CREATE AGGREGATE toolkit_experimental.rollup(summary toolkit_experimental.regressionsummary)
+(
+    sfunc = toolkit_experimental.regression_summary_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.regression_final,
+    combinefunc = toolkit_experimental.regression_combine,
+    serialfunc = toolkit_experimental.regression_trans_serialize,
+    deserialfunc = toolkit_experimental.regression_trans_deserialize,
+    parallel = safe
+);
+"#);
+
+#[pg_extern(name="coefficients", schema = "toolkit_experimental", strict, immutable)]
+fn regression_coefficients(
+    summary: toolkit_experimental::RegressionSummary,
+    _fcinfo: pg_sys::FunctionCallInfo,
+)-> Option<Vec<f64>> {
+    summary.to_internal().coefficients()
+}
+
+#[pg_extern(name="intercept", schema = "toolkit_experimental", strict, immutable)]
+fn regression_intercept(
+    summary: toolkit_experimental::RegressionSummary,
+    _fcinfo: pg_sys::FunctionCallInfo,
+)-> Option<f64> {
+    summary.to_internal().intercept()
+}
+
+#[pg_extern(name="determination_coeff", schema = "toolkit_experimental", strict, immutable)]
+fn regression_determination_coeff(
+    summary: toolkit_experimental::RegressionSummary,
+    _fcinfo: pg_sys::FunctionCallInfo,
+)-> Option<f64> {
+    summary.to_internal().determination_coeff()
+}
+
+#[pg_extern(name="predict", schema = "toolkit_experimental", strict, immutable)]
+fn regression_predict(
+    summary: toolkit_experimental::RegressionSummary,
+    x: Vec<Option<f64>>,
+    _fcinfo: pg_sys::FunctionCallInfo,
+)-> Option<f64> {
+    let x: Vec<f64> = x.into_iter().collect::<Option<_>>()?;
+    match summary.to_internal().predict(&x) {
+        Ok(prediction) => prediction,
+        Err(_) => panic!("expected {} x values, got {}", summary.num_x, x.len()),
+    }
+}
+
+#[pg_extern(name="num_vals", schema = "toolkit_experimental", strict, immutable)]
+fn regression_num_vals(
+    summary: toolkit_experimental::RegressionSummary,
+    _fcinfo: pg_sys::FunctionCallInfo,
+)-> i64 {
+    summary.n as i64
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+
+    use approx::assert_relative_eq;
+    use pgx::*;
+
+    macro_rules! select_one {
+        ($client:expr, $stmt:expr, $type:ty) => {
+            $client
+                .select($stmt, None, None)
+                .first()
+                .get_one::<$type>()
+                .unwrap()
+        };
+    }
+
+    #[pg_test]
+    fn test_regression_agg() {
+        Spi::execute(|client| {
+            client.select("CREATE TABLE test(y DOUBLE PRECISION, x1 DOUBLE PRECISION, x2 DOUBLE PRECISION)", None, None);
+            // set search_path after defining our table so we don't pollute the wrong schema
+            let stmt = "SELECT format('toolkit_experimental, %s',current_setting('search_path'))";
+            let search_path = select_one!(client, stmt, String);
+            client.select(&format!("SET LOCAL search_path TO {}", search_path), None, None);
+            // y = 1 + 2 * x1 - 3 * x2
+            client.select("INSERT INTO test SELECT 1 + 2 * x1 - 3 * x2, x1, x2 \
+                FROM generate_series(1, 10) x1, generate_series(1, 5) x2", None, None);
+
+            client.select("CREATE VIEW fit AS SELECT regression_agg(y, ARRAY[x1, x2]) AS summary FROM test", None, None);
+            let stmt = "SELECT (coefficients(summary))[1] FROM fit";
+            assert_relative_eq!(select_one!(client, stmt, f64), 2.0, max_relative = 1e-10);
+            let stmt = "SELECT (coefficients(summary))[2] FROM fit";
+            assert_relative_eq!(select_one!(client, stmt, f64), -3.0, max_relative = 1e-10);
+            let stmt = "SELECT intercept(summary) FROM fit";
+            assert_relative_eq!(select_one!(client, stmt, f64), 1.0, max_relative = 1e-10);
+            let stmt = "SELECT determination_coeff(summary) FROM fit";
+            assert_relative_eq!(select_one!(client, stmt, f64), 1.0, max_relative = 1e-10);
+            let stmt = "SELECT predict(summary, ARRAY[100.0, 10.0]) FROM fit";
+            assert_relative_eq!(select_one!(client, stmt, f64), 171.0, max_relative = 1e-10);
+            let stmt = "SELECT num_vals(summary) FROM fit";
+            assert_eq!(select_one!(client, stmt, i64), 50);
+
+            // rollup gives the same fit as aggregating all the rows at once
+            let stmt = "SELECT (coefficients(rollup(summary)))[2] FROM ( \
+                    SELECT regression_agg(y, ARRAY[x1, x2]) AS summary FROM test GROUP BY x1 % 3 \
+                ) s";
+            assert_relative_eq!(select_one!(client, stmt, f64), -3.0, max_relative = 1e-10);
+
+            // rows with a NULL x are ignored
+            client.select("INSERT INTO test VALUES (1000, NULL, 1), (1000, 1, NULL)", None, None);
+            let stmt = "SELECT intercept(summary) FROM fit";
+            assert_relative_eq!(select_one!(client, stmt, f64), 1.0, max_relative = 1e-10);
+
+            // x2 is a multiple of x1 so the coefficients aren't unique
+            let stmt = "SELECT coefficients(regression_agg(y, ARRAY[x1, 2 * x1])) IS NULL FROM test";
+            assert!(select_one!(client, stmt, bool));
+        });
+    }
+}
@@ -136,6 +136,9 @@ mod tests {
         "function rollup(timeweightsummary)",
         "function time_weight_combine(internal,internal)",
         "function time_weight_final(internal)",
+        "function time_weight_moving_final(internal)",
+        "function time_weight_moving_inv(internal,text,timestamp with time zone,double precision)",
+        "function time_weight_moving_trans(internal,text,timestamp with time zone,double precision)",
         "function time_weight_summary_trans(internal,timeweightsummary)",
         "function time_weight_trans(internal,text,timestamp with time zone,double precision)",
         "function time_weight_trans_deserialize(bytea,internal)",
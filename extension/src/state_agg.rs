@@ -0,0 +1,506 @@
+use serde::{Serialize, Deserialize};
+
+use std::{
+    slice,
+};
+
+use pgx::*;
+
+use flat_serialize::*;
+use flat_serialize_macro::FlatSerializable;
+
+use crate::{
+    aggregate_utils::in_aggregate_context,
+    datum_utils::{Interval, interval_from_micros},
+    json_inout_funcs,
+    flatten,
+    palloc::Internal,
+    pg_type,
+};
+
+#[allow(non_camel_case_types)]
+type bytea = pg_sys::Datum;
+
+// The time spent in each state is determined only by the times at which the
+// state changed, so that is all we store: the distinct states seen, each
+// transition between them, and the time of the last point, which is when the
+// final state stops being counted. As with TimeWeightSummary, combining two
+// summaries attributes the gap between them to the last state of the earlier
+// one.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StateSummary {
+    states: Vec<String>,
+    // (time, index into `states`), starts with the first point and only
+    // contains points where the state differs from the one before
+    transitions: Vec<(i64, usize)>,
+    last_ts: i64,
+}
+
+impl StateSummary {
+    fn new(ts: i64, state: &str) -> Self {
+        StateSummary {
+            states: vec![state.to_string()],
+            transitions: vec![(ts, 0)],
+            last_ts: ts,
+        }
+    }
+
+    // points must be sorted by time, if several points share a time the first
+    // of them is used
+    fn new_from_sorted<'a>(points: impl IntoIterator<Item = &'a (i64, String)>) -> Option<Self> {
+        let mut points = points.into_iter();
+        let (ts, state) = points.next()?;
+        let mut summary = StateSummary::new(*ts, state);
+        for (ts, state) in points {
+            summary.add_point(*ts, state);
+        }
+        Some(summary)
+    }
+
+    fn first_ts(&self) -> i64 {
+        self.transitions[0].0
+    }
+
+    fn state_index(&mut self, state: &str) -> usize {
+        match self.states.iter().position(|s| s == state) {
+            Some(idx) => idx,
+            None => {
+                self.states.push(state.to_string());
+                self.states.len() - 1
+            },
+        }
+    }
+
+    fn add_point(&mut self, ts: i64, state: &str) {
+        assert!(ts >= self.last_ts, "state_agg points must be added in time order");
+        if ts == self.last_ts {
+            return
+        }
+        let idx = self.state_index(state);
+        if self.transitions.last().unwrap().1 != idx {
+            self.transitions.push((ts, idx));
+        }
+        self.last_ts = ts;
+    }
+
+    // `other` must start strictly after `self` ends
+    fn combine(&mut self, other: &StateSummary) -> Result<(), ()> {
+        if other.first_ts() <= self.last_ts {
+            return Err(())
+        }
+        for &(ts, idx) in &other.transitions {
+            let idx = self.state_index(&other.states[idx]);
+            if self.transitions.last().unwrap().1 != idx {
+                self.transitions.push((ts, idx));
+            }
+        }
+        self.last_ts = other.last_ts;
+        Ok(())
+    }
+
+    // summaries must be sorted by their first time
+    fn combine_sorted<'a>(summaries: impl IntoIterator<Item = &'a StateSummary>) -> Option<Self> {
+        let mut summaries = summaries.into_iter();
+        let mut combined = summaries.next()?.clone();
+        for summary in summaries {
+            if combined.combine(summary).is_err() {
+                panic!("state_agg summaries cannot overlap in time")
+            }
+        }
+        Some(combined)
+    }
+
+    // (state, start, end) for each period spent in a single state
+    fn timeline(&self) -> impl Iterator<Item = (&str, i64, i64)> + '_ {
+        let ends = self.transitions.iter().skip(1)
+            .map(|&(ts, _)| ts)
+            .chain(std::iter::once(self.last_ts));
+        self.transitions.iter().zip(ends)
+            .map(move |(&(start, idx), end)| (&*self.states[idx], start, end))
+    }
+
+    // the total time spent in each state, in the order the states were first seen
+    fn durations(&self) -> Vec<(&str, i64)> {
+        let mut durations: Vec<_> = self.states.iter().map(|s| (&**s, 0)).collect();
+        for (state, start, end) in self.timeline() {
+            let idx = self.states.iter().position(|s| s == state).unwrap();
+            durations[idx].1 += end - start;
+        }
+        durations
+    }
+
+    fn duration_in(&self, state: &str) -> i64 {
+        self.timeline()
+            .filter(|&(s, _, _)| s == state)
+            .map(|(_, start, end)| end - start)
+            .sum()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, FlatSerializable, Serialize, Deserialize)]
+#[repr(C)]
+pub struct StateTransition {
+    ts: i64,
+    state: u64,
+}
+
+pg_type! {
+    #[derive(Debug)]
+    struct StateAgg<'input> {
+        last_ts: i64,
+        num_transitions: u64,
+        num_states: u64,
+        states_len: u64,
+        transitions: [StateTransition; self.num_transitions],
+        // the end offset of each state's name within `states`
+        state_ends: [u64; self.num_states],
+        states: [u8; self.states_len],
+    }
+}
+
+json_inout_funcs!(StateAgg);
+
+// hack to allow us to qualify names with "toolkit_experimental"
+// so that pgx generates the correct SQL
+mod toolkit_experimental {
+    pub(crate) use super::*;
+
+    varlena_type!(StateAgg);
+}
+
+impl<'input> StateAgg<'input> {
+    fn to_state_summary(&self) -> StateSummary {
+        let mut start = 0;
+        let states = self.state_ends.iter()
+            .map(|&end| {
+                let state = std::str::from_utf8(&self.states[start..end as usize])
+                    .expect("invalid state name in StateAgg");
+                start = end as usize;
+                state.to_string()
+            })
+            .collect();
+        StateSummary {
+            states,
+            transitions: self.transitions.iter().map(|t| (t.ts, t.state as usize)).collect(),
+            last_ts: self.last_ts,
+        }
+    }
+
+    fn from_state_summary(summary: &StateSummary) -> StateAgg<'static> {
+        let transitions: Vec<_> = summary.transitions.iter()
+            .map(|&(ts, state)| StateTransition{ts, state: state as u64})
+            .collect();
+        let mut states = Vec::new();
+        let mut state_ends = Vec::with_capacity(summary.states.len());
+        for state in &summary.states {
+            states.extend_from_slice(state.as_bytes());
+            state_ends.push(states.len() as u64);
+        }
+        unsafe {
+            flatten!(
+                StateAgg {
+                    last_ts: summary.last_ts,
+                    num_transitions: transitions.len() as u64,
+                    num_states: state_ends.len() as u64,
+                    states_len: states.len() as u64,
+                    transitions: &transitions,
+                    state_ends: &state_ends,
+                    states: &states,
+                }
+            )
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StateAggTransState {
+    #[serde(skip)]
+    point_buffer: Vec<(i64, String)>,
+    summary_buffer: Vec<StateSummary>,
+}
+
+impl StateAggTransState {
+    fn new() -> Self {
+        StateAggTransState{point_buffer: vec![], summary_buffer: vec![]}
+    }
+
+    fn push_point(&mut self, ts: i64, state: String) {
+        self.point_buffer.push((ts, state));
+    }
+
+    fn combine_points(&mut self) {
+        if self.point_buffer.is_empty() {
+            return
+        }
+        // stable so that the first of several points with the same time wins
+        self.point_buffer.sort_by_key(|(ts, _)| *ts);
+        self.summary_buffer.extend(StateSummary::new_from_sorted(&self.point_buffer));
+        self.point_buffer.clear();
+    }
+
+    fn push_summary(&mut self, other: &StateAggTransState) {
+        self.summary_buffer.extend(other.summary_buffer.iter().cloned());
+    }
+
+    fn combine_summaries(&mut self) {
+        self.combine_points();
+        if self.summary_buffer.len() <= 1 {
+            return
+        }
+        self.summary_buffer.sort_unstable_by_key(|s| s.first_ts());
+        self.summary_buffer = StateSummary::combine_sorted(&self.summary_buffer).into_iter().collect();
+    }
+}
+
+#[pg_extern(schema = "toolkit_experimental")]
+pub fn state_agg_trans_serialize(
+    mut state: Internal<StateAggTransState>,
+) -> bytea {
+    state.combine_summaries();
+    crate::do_serialize!(state)
+}
+
+#[pg_extern(schema = "toolkit_experimental", strict)]
+pub fn state_agg_trans_deserialize(
+    bytes: bytea,
+    _internal: Option<Internal<()>>,
+) -> Internal<StateAggTransState> {
+    crate::do_deserialize!(bytes, StateAggTransState)
+}
+
+#[pg_extern(schema = "toolkit_experimental")]
+pub fn state_agg_trans(
+    state: Option<Internal<StateAggTransState>>,
+    ts: Option<pg_sys::TimestampTz>,
+    value: Option<String>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<StateAggTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let (ts, value) = match (ts, value) {
+                (Some(ts), Some(value)) => (ts, value),
+                _ => return state,
+            };
+            match state {
+                None => {
+                    let mut s = StateAggTransState::new();
+                    s.push_point(ts, value);
+                    Some(s.into())
+                },
+                Some(mut s) => {s.push_point(ts, value); Some(s)},
+            }
+        })
+    }
+}
+
+#[pg_extern(schema = "toolkit_experimental")]
+pub fn state_agg_summary_trans(
+    state: Option<Internal<StateAggTransState>>,
+    value: Option<toolkit_experimental::StateAgg>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<StateAggTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let value = match value {
+                None => return state,
+                Some(value) => value.to_state_summary(),
+            };
+            let mut state = match state {
+                None => StateAggTransState::new().into(),
+                Some(state) => state,
+            };
+            state.summary_buffer.push(value);
+            Some(state)
+        })
+    }
+}
+
+#[pg_extern(schema = "toolkit_experimental")]
+pub fn state_agg_combine(
+    state1: Option<Internal<StateAggTransState>>,
+    state2: Option<Internal<StateAggTransState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<StateAggTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            match (state1, state2) {
+                (None, None) => None,
+                (None, Some(state2)) => {let mut s = state2.clone(); s.combine_points(); Some(s.into())},
+                (Some(state1), None) => {let mut s = state1.clone(); s.combine_points(); Some(s.into())},
+                (Some(state1), Some(state2)) => {
+                    let mut s1 = state1.clone();
+                    s1.combine_points();
+                    let mut s2 = state2.clone();
+                    s2.combine_points();
+                    s2.push_summary(&s1);
+                    Some(s2.into())
+                }
+            }
+        })
+    }
+}
+
+#[pg_extern(schema = "toolkit_experimental")]
+fn state_agg_final(
+    state: Option<Internal<StateAggTransState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<toolkit_experimental::StateAgg<'static>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let mut state = match state {
+                None => return None,
+                Some(state) => state.clone(),
+            };
+            state.combine_summaries();
+            debug_assert!(state.summary_buffer.len() <= 1);
+            state.summary_buffer.pop().map(|s| StateAgg::from_state_summary(&s))
+        })
+    }
+}
+
+extension_sql!(r#"
+CREATE AGGREGATE toolkit_experimental.state_agg( ts timestamptz, value TEXT )
+(
+    sfunc = toolkit_experimental.state_agg_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.state_agg_final,
+    combinefunc = toolkit_experimental.state_agg_combine,
+    serialfunc = toolkit_experimental.state_agg_trans_serialize,
+    deserialfunc = toolkit_experimental.state_agg_trans_deserialize,
+    parallel = restricted
+);
+"#);
+
+extension_sql!(r#"
+CREATE AGGREGATE toolkit_experimental.rollup(agg toolkit_experimental.StateAgg)
+(
+    sfunc = toolkit_experimental.state_agg_summary_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.state_agg_final,
+    combinefunc = toolkit_experimental.state_agg_combine,
+    serialfunc = toolkit_experimental.state_agg_trans_serialize,
+    deserialfunc = toolkit_experimental.state_agg_trans_deserialize,
+    parallel = restricted
+);
+"#);
+
+#[pg_extern(name="duration_in", schema = "toolkit_experimental", strict, immutable)]
+fn state_agg_duration_in(
+    agg: toolkit_experimental::StateAgg,
+    state: String,
+    _fcinfo: pg_sys::FunctionCallInfo,
+) -> Interval {
+    interval_from_micros(agg.to_state_summary().duration_in(&state))
+}
+
+#[pg_extern(name="into_values", schema = "toolkit_experimental", strict, immutable)]
+fn state_agg_into_values(
+    agg: toolkit_experimental::StateAgg,
+    _fcinfo: pg_sys::FunctionCallInfo,
+) -> impl std::iter::Iterator<Item = (name!(state, String), name!(duration, Interval))> + '_ {
+    let summary = agg.to_state_summary();
+    let values: Vec<_> = summary.durations().into_iter()
+        .map(|(state, duration)| (state.to_string(), interval_from_micros(duration)))
+        .collect();
+    values.into_iter()
+}
+
+#[pg_extern(name="state_timeline", schema = "toolkit_experimental", strict, immutable)]
+fn state_agg_timeline(
+    agg: toolkit_experimental::StateAgg,
+    _fcinfo: pg_sys::FunctionCallInfo,
+) -> impl std::iter::Iterator<Item = (name!(state, String), name!(start_time, pg_sys::TimestampTz), name!(end_time, pg_sys::TimestampTz))> + '_ {
+    let summary = agg.to_state_summary();
+    let timeline: Vec<_> = summary.timeline()
+        .map(|(state, start, end)| (state.to_string(), start, end))
+        .collect();
+    timeline.into_iter()
+}
+
+#[cfg(test)]
+mod summary_tests {
+    use super::StateSummary;
+
+    fn summary(points: &[(i64, &str)]) -> StateSummary {
+        let points: Vec<_> = points.iter().map(|&(ts, s)| (ts, s.to_string())).collect();
+        StateSummary::new_from_sorted(&points).unwrap()
+    }
+
+    #[test]
+    fn test_state_summary_combine() {
+        let full = summary(&[(0, "a"), (10, "b"), (15, "b"), (20, "a"), (30, "c"), (35, "c")]);
+        assert_eq!(full.durations(), vec![("a", 20), ("b", 10), ("c", 5)]);
+        assert_eq!(
+            full.timeline().collect::<Vec<_>>(),
+            vec![("a", 0, 10), ("b", 10, 20), ("a", 20, 30), ("c", 30, 35)],
+        );
+
+        // the gap between the summaries belongs to the last state of the first
+        let mut combined = summary(&[(0, "a"), (10, "b"), (15, "b")]);
+        combined.combine(&summary(&[(20, "a"), (30, "c"), (35, "c")])).unwrap();
+        assert_eq!(combined.durations(), full.durations());
+        assert_eq!(combined.timeline().collect::<Vec<_>>(), full.timeline().collect::<Vec<_>>());
+
+        // a summary starting in the state the previous one ended in continues it
+        let mut combined = summary(&[(0, "a"), (10, "b")]);
+        combined.combine(&summary(&[(15, "b"), (20, "a"), (30, "c"), (35, "c")])).unwrap();
+        assert_eq!(combined, full);
+
+        assert!(summary(&[(0, "a"), (10, "b")]).combine(&summary(&[(10, "b")])).is_err());
+    }
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+    use pgx::*;
+
+    macro_rules! select_one {
+        ($client:expr, $stmt:expr, $type:ty) => {
+            $client
+                .select($stmt, None, None)
+                .first()
+                .get_one::<$type>()
+                .unwrap()
+        };
+    }
+
+    #[pg_test]
+    fn test_state_agg() {
+        Spi::execute(|client| {
+            client.select("CREATE TABLE test(ts timestamptz, state TEXT)", None, None);
+            // set search_path after defining our table so we don't pollute the wrong schema
+            let stmt = "SELECT format('toolkit_experimental, %s',current_setting('search_path'))";
+            let search_path = select_one!(client, stmt, String);
+            client.select(&format!("SET LOCAL search_path TO {}", search_path), None, None);
+            client.select("INSERT INTO test VALUES \
+                ('2020-01-01 00:00:00+00', 'RUNNING'), ('2020-01-01 00:10:00+00', 'IDLE'), \
+                ('2020-01-01 00:20:00+00', 'RUNNING'), ('2020-01-01 00:45:00+00', 'FAULT'), \
+                ('2020-01-01 01:00:00+00', 'FAULT'), ('2020-01-01 01:10:00+00', NULL), \
+                ('2020-01-01 01:20:00+00', 'RUNNING')", None, None);
+
+            let stmt = "SELECT duration_in(state_agg(ts, state), 'RUNNING')::text FROM test";
+            assert_eq!(select_one!(client, stmt, String), "00:35:00");
+            let stmt = "SELECT duration_in(state_agg(ts, state), 'FAULT')::text FROM test";
+            assert_eq!(select_one!(client, stmt, String), "00:35:00");
+            let stmt = "SELECT duration_in(state_agg(ts, state), 'OFF')::text FROM test";
+            assert_eq!(select_one!(client, stmt, String), "00:00:00");
+
+            // rolling up hourly buckets gives the same durations as the whole
+            let stmt = "SELECT duration_in(rollup(agg), 'FAULT')::text FROM \
+                (SELECT state_agg(ts, state) agg FROM test GROUP BY date_trunc('hour', ts)) s";
+            assert_eq!(select_one!(client, stmt, String), "00:35:00");
+
+            let stmt = "SELECT string_agg(state || ' ' || duration::text, ', ') FROM \
+                into_values((SELECT state_agg(ts, state) FROM test))";
+            assert_eq!(select_one!(client, stmt, String), "RUNNING 00:35:00, IDLE 00:10:00, FAULT 00:35:00");
+
+            let stmt = "SELECT string_agg(state || ' ' || extract(epoch FROM end_time - start_time)::text, ', ') FROM \
+                state_timeline((SELECT state_agg(ts, state) FROM test))";
+            assert_eq!(select_one!(client, stmt, String), "RUNNING 600, IDLE 600, RUNNING 1500, FAULT 2100, RUNNING 0");
+
+            // the text form round-trips
+            let stmt = "SELECT duration_in(state_agg(ts, state)::text::StateAgg, 'IDLE')::text FROM test";
+            assert_eq!(select_one!(client, stmt, String), "00:10:00");
+        });
+    }
+}
@@ -25,28 +25,54 @@ type bytea = pg_sys::Datum;
 
 pg_type! {
     #[derive(Debug, PartialEq)]
-    struct StatsSummary1D {
+    struct StatsSummary1D<'input> {
         n: u64,
         sx: f64,
         sxx: f64,
+        // the third and fourth moments, missing from summaries before version 2
+        #[serde(default)]
+        sx3: [f64; if self.version >= 2 { 1 } else { 0 }],
+        #[serde(default)]
+        sx4: [f64; if self.version >= 2 { 1 } else { 0 }],
+        // the rounding error in sx, missing from summaries before version 3
+        #[serde(default)]
+        compensation: [f64; if self.version >= 3 { 1 } else { 0 }],
     }
 }
 
 pg_type! {
     #[derive(Debug, PartialEq)]
-    struct StatsSummary2D {
+    struct StatsSummary2D<'input> {
         n: u64,
         sx: f64,
         sxx: f64,
         sy: f64,
         syy: f64,
         sxy: f64,
+        // the third and fourth moments, missing from summaries before version 2
+        #[serde(default)]
+        sx3: [f64; if self.version >= 2 { 1 } else { 0 }],
+        #[serde(default)]
+        sx4: [f64; if self.version >= 2 { 1 } else { 0 }],
+        #[serde(default)]
+        sy3: [f64; if self.version >= 2 { 1 } else { 0 }],
+        #[serde(default)]
+        sy4: [f64; if self.version >= 2 { 1 } else { 0 }],
+        // the rounding errors in sx and sy, missing from summaries before version 3
+        #[serde(default)]
+        compensation: [f64; if self.version >= 3 { 2 } else { 0 }],
     }
 }
 
 json_inout_funcs!(StatsSummary1D);
 json_inout_funcs!(StatsSummary2D);
 
+// version 2 added the third and fourth moments, version 3 the compensation
+// for rounding errors in the sums. Older summaries are still readable, those
+// before version 2 have no skewness or kurtosis, and those before version 3
+// are treated as having no rounding error.
+const STATS_SUMMARY_VERSION: u8 = 3;
+
 
 // hack to allow us to qualify names with "toolkit_experimental"
 // so that pgx generates the correct SQL
@@ -58,20 +84,54 @@ mod toolkit_experimental {
 
 }
 
+// a moment missing from an old summary is NaN in the internal summaries,
+// which have no skewness or kurtosis then
+fn higher_moment(stored: &[f64]) -> f64 {
+    stored.first().copied().unwrap_or(f64::NAN)
+}
+
+// NaN isn't valid JSON, so summaries without the higher moments, such as the
+// rollup of an old summary, are written as version 1 again, with the rounding
+// error folded into the sums.
+fn summary_version(has_higher_moments: bool) -> u8 {
+    match has_higher_moments {
+        true => STATS_SUMMARY_VERSION,
+        false => 1,
+    }
+}
+
+fn stored_moment(version: u8, moment: &f64) -> &[f64] {
+    match version {
+        1 => &[],
+        _ => slice::from_ref(moment),
+    }
+}
+
 impl<'input> StatsSummary1D<'input> {
     fn to_internal(&self) -> InternalStatsSummary1D {
         InternalStatsSummary1D{
             n: self.n,
             sx: self.sx,
             sxx: self.sxx,
+            sx3: higher_moment(&self.sx3),
+            sx4: higher_moment(&self.sx4),
+            sxc: self.compensation.first().copied().unwrap_or(0.0),
         }
     }
     fn from_internal(st: InternalStatsSummary1D) -> Self {
+        let version = summary_version(st.has_higher_moments());
+        let (sx, compensation) = match version {
+            1 => (st.sx + st.sxc, &[][..]),
+            _ => (st.sx, slice::from_ref(&st.sxc)),
+        };
         unsafe{
-            flatten!(StatsSummary1D {
+            flatten!(StatsSummary1D version version {
                 n: st.n,
-                sx: st.sx,
+                sx: sx,
                 sxx: st.sxx,
+                sx3: stored_moment(version, &st.sx3),
+                sx4: stored_moment(version, &st.sx4),
+                compensation: compensation,
             })
         }
     }
@@ -86,18 +146,35 @@ impl<'input> StatsSummary2D<'input> {
             sy: self.sy,
             syy: self.syy,
             sxy: self.sxy,
+            sx3: higher_moment(&self.sx3),
+            sx4: higher_moment(&self.sx4),
+            sy3: higher_moment(&self.sy3),
+            sy4: higher_moment(&self.sy4),
+            sxc: self.compensation.get(0).copied().unwrap_or(0.0),
+            syc: self.compensation.get(1).copied().unwrap_or(0.0),
         }
     }
     fn from_internal(st: InternalStatsSummary2D) -> Self {
+        let version = summary_version(st.has_higher_moments());
+        let compensation = [st.sxc, st.syc];
+        let (sx, sy, compensation) = match version {
+            1 => (st.sx + st.sxc, st.sy + st.syc, &[][..]),
+            _ => (st.sx, st.sy, &compensation[..]),
+        };
         unsafe{
             flatten!(
-            StatsSummary2D {
+            StatsSummary2D version version {
                 n: st.n,
-                sx: st.sx,
+                sx: sx,
                 sxx: st.sxx,
-                sy: st.sy,
+                sy: sy,
                 syy: st.syy,
                 sxy: st.sxy,
+                sx3: stored_moment(version, &st.sx3),
+                sx4: stored_moment(version, &st.sx4),
+                sy3: stored_moment(version, &st.sy3),
+                sy4: stored_moment(version, &st.sy4),
+                compensation: compensation,
             })
         }
     }
@@ -256,6 +333,124 @@ pub fn stats2d_inv_trans<'s>(
 }
 
 
+// Weighted forms of the above, for when each row stands for `weight`
+// observations, eg when aggregating data that has already been bucketed.
+// Rows with a NULL or zero weight are ignored like rows with a NULL value.
+fn to_weight(weight: i64) -> u64 {
+    if weight < 0 {
+        panic!("stats_agg weights cannot be negative")
+    }
+    weight as u64
+}
+
+#[pg_extern(schema = "toolkit_experimental")]
+pub fn stats1d_weighted_trans<'s>(
+    state: Option<Internal<StatsSummary1D<'s>>>,
+    val: Option<f64>,
+    weight: Option<i64>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<StatsSummary1D<'s>>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let val = match (val, weight) {
+                (Some(val), Some(weight)) => Some((val, to_weight(weight))),
+                _ => None,
+            };
+            match (state, val) {
+                (None, None) => Some(StatsSummary1D::from_internal(InternalStatsSummary1D::new()).into()), // return an empty one from the trans function because otherwise it breaks in the window context
+                (Some(state), None) => Some(state),
+                (None, Some((val, weight))) => {
+                    let mut s = InternalStatsSummary1D::new();
+                    s.accum_weighted(val, weight).unwrap();
+                    Some(StatsSummary1D::from_internal(s).into())
+                },
+                (Some(mut state), Some((val, weight))) => {
+                    let mut s: InternalStatsSummary1D = state.to_internal();
+                    s.accum_weighted(val, weight).unwrap();
+                    *state = StatsSummary1D::from_internal(s);
+                    Some(state)
+                },
+            }
+        })
+    }
+}
+
+#[pg_extern(schema = "toolkit_experimental")]
+pub fn stats2d_weighted_trans<'s>(
+    state: Option<Internal<StatsSummary2D<'s>>>,
+    y: Option<f64>,
+    x: Option<f64>,
+    weight: Option<i64>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<StatsSummary2D<'s>>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let val = match (y, x, weight) {
+                (Some(y), Some(x), Some(weight)) => Some((XYPair{y, x}, to_weight(weight))),
+                _ => None,
+            };
+            match (state, val) {
+                (None, None) => Some(StatsSummary2D::from_internal(InternalStatsSummary2D::new()).into()), // return an empty one from the trans function because otherwise it breaks in the window context
+                (Some(state), None) => Some(state),
+                (None, Some((val, weight))) => {
+                    let mut s = InternalStatsSummary2D::new();
+                    s.accum_weighted(val, weight).unwrap();
+                    Some(StatsSummary2D::from_internal(s).into())
+                },
+                (Some(mut state), Some((val, weight))) => {
+                    let mut s: InternalStatsSummary2D = state.to_internal();
+                    s.accum_weighted(val, weight).unwrap();
+                    *state = StatsSummary2D::from_internal(s);
+                    Some(state)
+                },
+            }
+        })
+    }
+}
+
+#[pg_extern(schema = "toolkit_experimental")]
+pub fn stats1d_weighted_inv_trans<'s>(
+    state: Option<Internal<StatsSummary1D<'s>>>,
+    val: Option<f64>,
+    weight: Option<i64>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<StatsSummary1D<'s>>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            match (state, val, weight) {
+                (None, _, _) => panic!("Inverse function should never be called with NULL state"),
+                (Some(state), Some(val), Some(weight)) => {
+                    let s = state.to_internal().remove_weighted(val, to_weight(weight));
+                    s.map(|s| StatsSummary1D::from_internal(s).into())
+                },
+                (Some(state), _, _) => Some(state),
+            }
+        })
+    }
+}
+
+#[pg_extern(schema = "toolkit_experimental")]
+pub fn stats2d_weighted_inv_trans<'s>(
+    state: Option<Internal<StatsSummary2D<'s>>>,
+    y: Option<f64>,
+    x: Option<f64>,
+    weight: Option<i64>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<StatsSummary2D<'s>>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            match (state, y, x, weight) {
+                (None, _, _, _) => panic!("Inverse function should never be called with NULL state"),
+                (Some(state), Some(y), Some(x), Some(weight)) => {
+                    let s = state.to_internal().remove_weighted(XYPair{y, x}, to_weight(weight));
+                    s.map(|s| StatsSummary2D::from_internal(s).into())
+                },
+                (Some(state), _, _, _) => Some(state),
+            }
+        })
+    }
+}
+
 #[pg_extern(schema = "toolkit_experimental")]
 pub fn stats1d_summary_trans<'s, 'v>(
     state: Option<Internal<StatsSummary1D<'s>>>,
@@ -507,6 +702,42 @@ CREATE AGGREGATE toolkit_experimental.stats_agg_no_inv( y DOUBLE PRECISION, x DO
 );
 "#);
 
+// The 1-D weighted form can't be an overload of stats_agg, as stats_agg(value, weight)
+// would be ambiguous with stats_agg(y, x) for integer weights, the 2-D one can.
+extension_sql!(r#"
+CREATE AGGREGATE toolkit_experimental.weighted_stats_agg( value DOUBLE PRECISION, weight BIGINT )
+(
+    sfunc = toolkit_experimental.stats1d_weighted_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.stats1d_final,
+    combinefunc = toolkit_experimental.stats1d_combine,
+    serialfunc = toolkit_experimental.stats1d_trans_serialize,
+    deserialfunc = toolkit_experimental.stats1d_trans_deserialize,
+    msfunc = toolkit_experimental.stats1d_weighted_trans,
+    minvfunc = toolkit_experimental.stats1d_weighted_inv_trans,
+    mstype = internal,
+    mfinalfunc = toolkit_experimental.stats1d_final,
+    parallel = safe
+);
+"#);
+
+extension_sql!(r#"
+CREATE AGGREGATE toolkit_experimental.stats_agg( y DOUBLE PRECISION, x DOUBLE PRECISION, weight BIGINT )
+(
+    sfunc = toolkit_experimental.stats2d_weighted_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.stats2d_final,
+    combinefunc = toolkit_experimental.stats2d_combine,
+    serialfunc = toolkit_experimental.stats2d_trans_serialize,
+    deserialfunc = toolkit_experimental.stats2d_trans_deserialize,
+    msfunc = toolkit_experimental.stats2d_weighted_trans,
+    minvfunc = toolkit_experimental.stats2d_weighted_inv_trans,
+    mstype = internal,
+    mfinalfunc = toolkit_experimental.stats2d_final,
+    parallel = safe
+);
+"#);
+
 //  Currently, rollup does not have the inverse function so if you want the behavior where we don't use the inverse,
 // you can use it in your window functions (useful for our own perf testing as well)
 
@@ -619,6 +850,58 @@ fn stats1d_variance(
     }
 }
 
+#[pg_extern(name="skewness", schema = "toolkit_experimental", immutable)]
+fn stats1d_skewness(
+    summary: Option<toolkit_experimental::StatsSummary1D>,
+    method: default!(String, "population"),
+    _fcinfo: pg_sys::FunctionCallInfo,
+)-> Option<f64> {
+    match method.trim().to_lowercase().as_str() {
+        "population" | "pop" => summary?.to_internal().skewness_pop(),
+        "sample" | "samp" => summary?.to_internal().skewness_samp(),
+        _ => panic!("unknown analysis method"),
+    }
+}
+
+#[pg_extern(name="kurtosis", schema = "toolkit_experimental", immutable)]
+fn stats1d_kurtosis(
+    summary: Option<toolkit_experimental::StatsSummary1D>,
+    method: default!(String, "population"),
+    _fcinfo: pg_sys::FunctionCallInfo,
+)-> Option<f64> {
+    match method.trim().to_lowercase().as_str() {
+        "population" | "pop" => summary?.to_internal().kurtosis_pop(),
+        "sample" | "samp" => summary?.to_internal().kurtosis_samp(),
+        _ => panic!("unknown analysis method"),
+    }
+}
+
+#[pg_extern(name="z_score", schema = "toolkit_experimental", immutable)]
+fn stats1d_z_score(
+    summary: Option<toolkit_experimental::StatsSummary1D>,
+    value: Option<f64>,
+    method: default!(String, "population"),
+    _fcinfo: pg_sys::FunctionCallInfo,
+)-> Option<f64> {
+    match method.trim().to_lowercase().as_str() {
+        "population" | "pop" => summary?.to_internal().z_score_pop(value?),
+        "sample" | "samp" => summary?.to_internal().z_score_samp(value?),
+        _ => panic!("unknown analysis method"),
+    }
+}
+
+// returns no rows if the test is undefined, see stats_agg::hypothesis
+#[pg_extern(name="welch_t_test", schema = "toolkit_experimental", strict, immutable)]
+fn stats1d_welch_t_test(
+    a: toolkit_experimental::StatsSummary1D,
+    b: toolkit_experimental::StatsSummary1D,
+    _fcinfo: pg_sys::FunctionCallInfo,
+) -> impl std::iter::Iterator<Item = (name!(t, f64), name!(df, f64), name!(p_value, f64))> {
+    stats_agg::hypothesis::welch_t_test(&a.to_internal(), &b.to_internal())
+        .map(|test| (test.t, test.df, test.p_value))
+        .into_iter()
+}
+
 #[pg_extern(name="num_vals", schema = "toolkit_experimental", strict, immutable)]
 fn stats1d_num_vals(
     summary: toolkit_experimental::StatsSummary1D,
@@ -711,6 +994,58 @@ fn stats2d_variance_y(
     }
 }
 
+#[pg_extern(name="skewness_x", schema = "toolkit_experimental", immutable)]
+fn stats2d_skewness_x(
+    summary: Option<toolkit_experimental::StatsSummary2D>,
+    method: default!(String, "population"),
+    _fcinfo: pg_sys::FunctionCallInfo,
+)-> Option<f64> {
+    match method.trim().to_lowercase().as_str() {
+        "population" | "pop" => Some(summary?.to_internal().skewness_pop()?.x),
+        "sample" | "samp" => Some(summary?.to_internal().skewness_samp()?.x),
+        _ => panic!("unknown analysis method"),
+    }
+}
+
+#[pg_extern(name="skewness_y", schema = "toolkit_experimental", immutable)]
+fn stats2d_skewness_y(
+    summary: Option<toolkit_experimental::StatsSummary2D>,
+    method: default!(String, "population"),
+    _fcinfo: pg_sys::FunctionCallInfo,
+)-> Option<f64> {
+    match method.trim().to_lowercase().as_str() {
+        "population" | "pop" => Some(summary?.to_internal().skewness_pop()?.y),
+        "sample" | "samp" => Some(summary?.to_internal().skewness_samp()?.y),
+        _ => panic!("unknown analysis method"),
+    }
+}
+
+#[pg_extern(name="kurtosis_x", schema = "toolkit_experimental", immutable)]
+fn stats2d_kurtosis_x(
+    summary: Option<toolkit_experimental::StatsSummary2D>,
+    method: default!(String, "population"),
+    _fcinfo: pg_sys::FunctionCallInfo,
+)-> Option<f64> {
+    match method.trim().to_lowercase().as_str() {
+        "population" | "pop" => Some(summary?.to_internal().kurtosis_pop()?.x),
+        "sample" | "samp" => Some(summary?.to_internal().kurtosis_samp()?.x),
+        _ => panic!("unknown analysis method"),
+    }
+}
+
+#[pg_extern(name="kurtosis_y", schema = "toolkit_experimental", immutable)]
+fn stats2d_kurtosis_y(
+    summary: Option<toolkit_experimental::StatsSummary2D>,
+    method: default!(String, "population"),
+    _fcinfo: pg_sys::FunctionCallInfo,
+)-> Option<f64> {
+    match method.trim().to_lowercase().as_str() {
+        "population" | "pop" => Some(summary?.to_internal().kurtosis_pop()?.y),
+        "sample" | "samp" => Some(summary?.to_internal().kurtosis_samp()?.y),
+        _ => panic!("unknown analysis method"),
+    }
+}
+
 #[pg_extern(name="num_vals", schema = "toolkit_experimental", strict, immutable)]
 fn stats2d_num_vals(
     summary: toolkit_experimental::StatsSummary2D,
@@ -759,6 +1094,46 @@ fn stats2d_determination_coeff(
     summary.to_internal().determination_coeff()
 }
 
+#[pg_extern(name="slope_stderr", schema = "toolkit_experimental", strict, immutable)]
+fn stats2d_slope_stderr(
+    summary: toolkit_experimental::StatsSummary2D,
+    _fcinfo: pg_sys::FunctionCallInfo,
+)-> Option<f64> {
+    summary.to_internal().slope_stderr()
+}
+
+#[pg_extern(name="intercept_stderr", schema = "toolkit_experimental", strict, immutable)]
+fn stats2d_intercept_stderr(
+    summary: toolkit_experimental::StatsSummary2D,
+    _fcinfo: pg_sys::FunctionCallInfo,
+)-> Option<f64> {
+    summary.to_internal().intercept_stderr()
+}
+
+#[pg_extern(name="predict", schema = "toolkit_experimental", strict, immutable)]
+fn stats2d_predict(
+    summary: toolkit_experimental::StatsSummary2D,
+    x: f64,
+    _fcinfo: pg_sys::FunctionCallInfo,
+)-> Option<f64> {
+    summary.to_internal().predict(x)
+}
+
+// returns no rows if the interval is undefined, ie there are fewer than 3
+// points or x is constant
+#[pg_extern(name="prediction_interval", schema = "toolkit_experimental", strict, immutable)]
+fn stats2d_prediction_interval(
+    summary: toolkit_experimental::StatsSummary2D,
+    x: f64,
+    confidence: default!(f64, 0.95),
+    _fcinfo: pg_sys::FunctionCallInfo,
+) -> impl std::iter::Iterator<Item = (name!(lower, f64), name!(upper, f64))> {
+    if !(confidence > 0.0 && confidence < 1.0) {
+        panic!("confidence must be between 0 and 1")
+    }
+    summary.to_internal().prediction_interval(x, confidence).into_iter()
+}
+
 #[pg_extern(name="covariance", schema = "toolkit_experimental", immutable)]
 fn stats2d_covar(
     summary: Option<toolkit_experimental::StatsSummary2D>,
@@ -773,6 +1148,100 @@ fn stats2d_covar(
 }
 
 
+#[cfg(any(test, feature = "pg_test"))]
+mod io_tests {
+    use pgx::*;
+
+    macro_rules! select_one {
+        ($client:expr, $stmt:expr, $type:ty) => {
+            $client
+                .select($stmt, None, None)
+                .first()
+                .get_one::<$type>()
+        };
+    }
+
+    #[pg_test]
+    fn test_stats_summary_v1_io() {
+        Spi::execute(|client| {
+            let search_path = select_one!(client, "SELECT format('toolkit_experimental, %s',current_setting('search_path'))", String).unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", search_path), None, None);
+
+            // the summaries of 1, 2, 3 and 4, and of (1, 2) and (3, 6), as
+            // written before the higher moments were added
+            let v1 = "'{\"version\":1,\"n\":4,\"sx\":10.0,\"sxx\":5.0}'::statssummary1d";
+            let v1_2d = "'{\"version\":1,\"n\":2,\"sx\":4.0,\"sxx\":2.0,\"sy\":8.0,\"syy\":8.0,\"sxy\":4.0}'::statssummary2d";
+
+            assert_eq!(select_one!(client, &format!("SELECT average({})", v1), f64), Some(2.5));
+            assert_eq!(select_one!(client, &format!("SELECT variance({})", v1), f64), Some(1.25));
+            assert_eq!(select_one!(client, &format!("SELECT skewness({})", v1), f64), None);
+            assert_eq!(select_one!(client, &format!("SELECT kurtosis({}, 'sample')", v1), f64), None);
+            // and they stay unknown when rolled up with newer summaries
+            let stmt = format!("SELECT kurtosis(rollup(s)) FROM (SELECT {} s UNION ALL SELECT stats_agg(5.0::float8)) v", v1);
+            assert_eq!(select_one!(client, &stmt, f64), None);
+
+            assert_eq!(select_one!(client, &format!("SELECT average_y({})", v1_2d), f64), Some(4.0));
+            assert_eq!(select_one!(client, &format!("SELECT variance_x({})", v1_2d), f64), Some(1.0));
+            assert_eq!(select_one!(client, &format!("SELECT slope({})", v1_2d), f64), Some(2.0));
+            assert_eq!(select_one!(client, &format!("SELECT skewness_x({})", v1_2d), f64), None);
+            assert_eq!(select_one!(client, &format!("SELECT kurtosis_y({})", v1_2d), f64), None);
+        });
+    }
+
+    #[pg_test]
+    fn test_stats_summary_round_trip() {
+        Spi::execute(|client| {
+            let search_path = select_one!(client, "SELECT format('toolkit_experimental, %s',current_setting('search_path'))", String).unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", search_path), None, None);
+
+            let v1 = "{\"version\":1,\"n\":4,\"sx\":10.0,\"sxx\":5.0}";
+            let v1_2d = "{\"version\":1,\"n\":2,\"sx\":4.0,\"sxx\":2.0,\"sy\":8.0,\"syy\":8.0,\"sxy\":4.0}";
+            let stmt = format!("SELECT sum('{}'::statssummary1d)", v1);
+            assert_eq!(select_one!(client, &stmt, f64), Some(10.0));
+            let stmt = format!("SELECT average('{}'::statssummary1d)", v1);
+            assert_eq!(select_one!(client, &stmt, f64), Some(2.5));
+            let stmt = format!("SELECT sum_x('{0}'::statssummary2d) + average_y('{0}'::statssummary2d)", v1_2d);
+            assert_eq!(select_one!(client, &stmt, f64), Some(8.0));
+
+            // the rollup of an old summary is written out as version 1 again
+            let stmt = format!("SELECT r::text LIKE '{{\"version\":1,%', sum(r::text::statssummary1d), average(r::text::statssummary1d) \
+                FROM (SELECT rollup(s) r FROM (SELECT '{}'::statssummary1d s) v) q", v1);
+            let (old, sum, average) = client.select(&stmt, None, None).first().get_three::<bool, f64, f64>();
+            assert_eq!((old, sum, average), (Some(true), Some(10.0), Some(2.5)));
+            let stmt = format!("SELECT sum_y(rollup(s)::text::statssummary2d) FROM (SELECT '{}'::statssummary2d s) v", v1_2d);
+            assert_eq!(select_one!(client, &stmt, f64), Some(8.0));
+
+            // current summaries read back the same
+            let stmt = "SELECT s::text LIKE '{\"version\":3,%', sum(s::text::statssummary1d), average(s::text::statssummary1d) \
+                FROM (SELECT stats_agg(v) s FROM (VALUES (1.0::float8), (2.0), (3.0), (4.0)) t(v)) q";
+            let (current, sum, average) = client.select(stmt, None, None).first().get_three::<bool, f64, f64>();
+            assert_eq!((current, sum, average), (Some(true), Some(10.0), Some(2.5)));
+            let stmt = "SELECT average_x(s::text::statssummary2d), sum_y(s::text::statssummary2d) \
+                FROM (SELECT stats_agg(y, x) s FROM (VALUES (2.0::float8, 1.0::float8), (6.0, 3.0)) t(y, x)) q";
+            let (average_x, sum_y) = client.select(stmt, None, None).first().get_two::<f64, f64>();
+            assert_eq!((average_x, sum_y), (Some(2.0), Some(8.0)));
+        });
+    }
+
+    #[pg_test]
+    fn test_weighted_stats_agg() {
+        Spi::execute(|client| {
+            let search_path = select_one!(client, "SELECT format('toolkit_experimental, %s',current_setting('search_path'))", String).unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", search_path), None, None);
+
+            let stmt = "SELECT num_vals(s), sum(s) \
+                FROM (SELECT weighted_stats_agg(v, w) s FROM (VALUES (1.0::float8, 2::bigint), (4.0, 1), (9.0, NULL)) t(v, w)) q";
+            let (n, sum) = client.select(stmt, None, None).first().get_two::<i64, f64>();
+            assert_eq!((n, sum), (Some(3), Some(6.0)));
+
+            let stmt = "SELECT num_vals(s), sum_y(s), sum_x(s) \
+                FROM (SELECT stats_agg(y, x, w) s FROM (VALUES (2.0::float8, 1.0::float8, 2::bigint), (6.0, 3.0, 1)) t(y, x, w)) q";
+            let (n, sum_y, sum_x) = client.select(stmt, None, None).first().get_three::<i64, f64, f64>();
+            assert_eq!((n, sum_y, sum_x), (Some(3), Some(10.0), Some(5.0)));
+        });
+    }
+}
+
 // TODO: Add testing - probably want to do some fuzz testing against the Postgres implementations of the same. Possibly translate the Postgres tests as well?
 // #[cfg(any(test, feature = "pg_test"))]
 // mod tests {
@@ -304,6 +304,22 @@ CREATE AGGREGATE rollup(
 );
 "#);
 
+// Centroids can't be removed from a t-digest once merged, so there is no
+// inverse for rolling() and each frame is combined from scratch.
+extension_sql!(r#"
+CREATE AGGREGATE toolkit_experimental.rolling(
+    tdigest
+) (
+    sfunc = tdigest_compound_trans,
+    stype = internal,
+    finalfunc = tdigest_compound_final,
+    combinefunc = tdigest_compound_combine,
+    serialfunc = tdigest_compound_serialize,
+    deserialfunc = tdigest_compound_deserialize,
+    parallel = safe
+);
+"#);
+
 //---- Available PG operations on the digest
 
 // Approximate the value at the given quantile (0.0-1.0)
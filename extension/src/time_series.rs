@@ -5,9 +5,10 @@ use pgx::*;
 
 use crate::{
     aggregate_utils::in_aggregate_context, json_inout_funcs, pg_type, flatten, palloc::Internal,
+    datum_utils::{interval_from_parts, interval_to_micros}, range::get_range,
 };
 
-use time_series::{TSPoint, TimeSeries as InternalTimeSeries, ExplicitTimeSeries, NormalTimeSeries, GapfillMethod};
+use time_series::{TSPoint, TimeSeries as InternalTimeSeries, ExplicitTimeSeries, NormalTimeSeries, AggregationMethod, GapfillMethod};
 
 use flat_serialize::*;
 
@@ -154,6 +155,166 @@ impl<'input> TimeSeries<'input> {
                 true,
         }
     }
+
+    // An explicit series of `points`, which are known to be in time order if
+    // `sorted` is true.
+    fn from_points(points: &[TSPoint], sorted: bool) -> TimeSeries<'static> {
+        unsafe {
+            if sorted {
+                flatten!(
+                    TimeSeries {
+                        series: SeriesType::SortedSeries {
+                            num_points: points.len() as u64,
+                            points,
+                        }
+                    }
+                )
+            } else {
+                flatten!(
+                    TimeSeries {
+                        series: SeriesType::ExplicitSeries {
+                            num_points: points.len() as u64,
+                            points,
+                        }
+                    }
+                )
+            }
+        }
+    }
+
+    fn from_values(start_ts: i64, step_interval: i64, values: &[f64]) -> TimeSeries<'static> {
+        unsafe {
+            flatten!(
+                TimeSeries {
+                    series: SeriesType::NormalSeries {
+                        start_ts,
+                        step_interval,
+                        num_vals: values.len() as u64,
+                        values,
+                    }
+                }
+            )
+        }
+    }
+
+    // A series of the same kind and with the same times as this one, with `f`
+    // applied to each value.
+    pub fn map_values(&self, f: impl Fn(f64) -> f64) -> TimeSeries<'static> {
+        let map_points = |points: &[TSPoint]| -> Vec<TSPoint> {
+            points.iter().map(|p| TSPoint{ts: p.ts, val: f(p.val)}).collect()
+        };
+        match self.series {
+            SeriesType::SortedSeries{points, ..} =>
+                TimeSeries::from_points(&map_points(points), true),
+            SeriesType::ExplicitSeries{points, ..} =>
+                TimeSeries::from_points(&map_points(points), false),
+            SeriesType::NormalSeries{start_ts, step_interval, values, ..} => {
+                let values: Vec<f64> = values.iter().map(|&v| f(v)).collect();
+                TimeSeries::from_values(start_ts, step_interval, &values)
+            },
+        }
+    }
+
+    // A series with a point for each pair of consecutive points in this one,
+    // at the time of the later point and with the value `f(earlier, later)`.
+    // A normal series stays normal, explicit ones are sorted first.
+    pub fn map_pairs(&self, f: impl Fn(TSPoint, TSPoint) -> f64) -> TimeSeries<'static> {
+        let map_sorted = |points: &[TSPoint]| -> TimeSeries<'static> {
+            let points: Vec<TSPoint> = points.windows(2)
+                .map(|pair| TSPoint{ts: pair[1].ts, val: f(pair[0], pair[1])})
+                .collect();
+            TimeSeries::from_points(&points, true)
+        };
+        match self.series {
+            SeriesType::SortedSeries{points, ..} => map_sorted(points),
+            SeriesType::ExplicitSeries{points, ..} => {
+                let mut points = points.to_vec();
+                points.sort_unstable_by_key(|p| p.ts);
+                map_sorted(&points)
+            },
+            SeriesType::NormalSeries{start_ts, step_interval, values, ..} => {
+                let values: Vec<f64> = values.windows(2).enumerate().map(|(i, pair)| {
+                    let ts = start_ts + i as i64 * step_interval;
+                    f(TSPoint{ts, val: pair[0]}, TSPoint{ts: ts + step_interval, val: pair[1]})
+                }).collect();
+                TimeSeries::from_values(start_ts + step_interval, step_interval, &values)
+            },
+        }
+    }
+
+    // The points with times in [start, end), a bound of None is unbounded.
+    // Sorted series are sliced using a binary search, or for normal series
+    // arithmetic, without visiting the points outside the range. An explicit
+    // series stays in the same order.
+    pub fn time_slice(&self, start: Option<i64>, end: Option<i64>) -> TimeSeries<'static> {
+        match self.series {
+            SeriesType::SortedSeries{points, ..} => {
+                let index = |bound: Option<i64>, default: usize| {
+                    bound.map_or(default, |bound| points.partition_point(|p| p.ts < bound))
+                };
+                let from = index(start, 0);
+                let to = index(end, points.len()).max(from);
+                TimeSeries::from_points(&points[from..to], true)
+            },
+            SeriesType::ExplicitSeries{points, ..} => {
+                let points: Vec<TSPoint> = points.iter()
+                    .filter(|p| start.map_or(true, |s| p.ts >= s) && end.map_or(true, |e| p.ts < e))
+                    .cloned()
+                    .collect();
+                TimeSeries::from_points(&points, false)
+            },
+            SeriesType::NormalSeries{start_ts, step_interval, values, ..} => {
+                // the number of values before `bound`, ie ceil((bound - start_ts) / step),
+                // in i128 as the bounds may be +-infinity
+                let index = |bound: Option<i64>, default: usize| {
+                    bound.map_or(default, |bound| {
+                        let offset = bound as i128 - start_ts as i128;
+                        let steps = -(-offset).div_euclid(step_interval as i128);
+                        steps.clamp(0, values.len() as i128) as usize
+                    })
+                };
+                let from = index(start, 0);
+                let to = index(end, values.len()).max(from);
+                TimeSeries::from_values(start_ts + from as i64 * step_interval, step_interval, &values[from..to])
+            },
+        }
+    }
+
+    // The points for which `keep(value)` is true. Normal series only stay
+    // normal if every point is kept.
+    pub fn filter_values(&self, keep: impl Fn(f64) -> bool) -> TimeSeries<'static> {
+        match self.series {
+            SeriesType::SortedSeries{points, ..} | SeriesType::ExplicitSeries{points, ..} => {
+                let kept: Vec<TSPoint> = points.iter().filter(|p| keep(p.val)).cloned().collect();
+                TimeSeries::from_points(&kept, self.is_sorted())
+            },
+            SeriesType::NormalSeries{start_ts, step_interval, values, ..} => {
+                if values.iter().all(|&v| keep(v)) {
+                    return TimeSeries::from_values(start_ts, step_interval, values);
+                }
+                let kept: Vec<TSPoint> = (0..values.len())
+                    .filter_map(|i| self.get(i))
+                    .filter(|p| keep(p.val))
+                    .collect();
+                TimeSeries::from_points(&kept, true)
+            },
+        }
+    }
+
+    // The earliest and latest points, without sorting
+    pub fn first(&self) -> Option<TSPoint> {
+        match self.series {
+            SeriesType::ExplicitSeries{points, ..} => points.iter().min_by_key(|p| p.ts).cloned(),
+            _ => self.get(0),
+        }
+    }
+
+    pub fn last(&self) -> Option<TSPoint> {
+        match self.series {
+            SeriesType::ExplicitSeries{points, ..} => points.iter().max_by_key(|p| p.ts).cloned(),
+            _ => self.get(self.num_points().checked_sub(1)?),
+        }
+    }
 }
 
 #[pg_extern(schema = "toolkit_experimental")]
@@ -299,6 +460,18 @@ CREATE AGGREGATE toolkit_experimental.rollup(
 
 type Interval = pg_sys::Datum;
 
+#[allow(non_camel_case_types)]
+type tstzrange = pg_sys::Datum;
+
+fn parse_gapfill_method(method: &str) -> GapfillMethod {
+    match method.trim().to_ascii_lowercase().as_str() {
+        "locf" => GapfillMethod::LOCF,
+        "nearest" => GapfillMethod::Nearest,
+        "interpolate" => GapfillMethod::Linear,
+        _ => panic!("Unknown normalization method: {} - valid methods are locf, nearest, or interpolate", method)
+    }
+}
+
 #[pg_extern(schema = "toolkit_experimental", name="normalize")]
 pub fn normalize_default_range (
     series: crate::time_series::toolkit_experimental::TimeSeries<'static>,
@@ -307,9 +480,14 @@ pub fn normalize_default_range (
     truncate: Option<bool>,
     _fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<crate::time_series::toolkit_experimental::TimeSeries<'static>> {
-    normalize(series, interval, method, truncate, None, None, _fcinfo)
+    normalize(series, interval, method, truncate, None, None, None, _fcinfo)
 }
 
+// Intervals containing days or months step through the calendar of
+// `time_zone`, or the session's time zone if that is NULL. As the steps aren't
+// always the same length these return a sorted series, unless they happen to
+// be, such as for '1 day' in UTC. Smaller intervals return a normal series
+// stepping by a fixed amount of time.
 #[pg_extern(schema = "toolkit_experimental")]
 pub fn normalize (
     series: crate::time_series::toolkit_experimental::TimeSeries<'static>,
@@ -318,102 +496,429 @@ pub fn normalize (
     truncate: Option<bool>,
     range_start: Option<pg_sys::TimestampTz>,
     range_end: Option<pg_sys::TimestampTz>,
+    time_zone: default!(Option<String>, NULL),
     _fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<crate::time_series::toolkit_experimental::TimeSeries<'static>> {
-    unsafe {
-        let interval = interval as *const pg_sys::Interval;
-        if (*interval).day > 0 || (*interval).month > 0 {
-            panic!("Normalization intervals are currently restricted to stable units (hours or smaller)");
-        }
-        let interval = (*interval).time;
-        let method = match method.to_ascii_lowercase().as_str() {
-            "locf" => GapfillMethod::LOCF,
-            "nearest" => GapfillMethod::Nearest,
-            "interpolate" => GapfillMethod::Linear,
-            _ => panic!("Unknown normalization method: {} - valid methods are locf, nearest, or interpolate", method)
-        };
-        let truncate = match truncate {
-            Some(x) => x,
-            None => true,
-        };
-        if series.len() < 2 {
-            panic!("Need at least two points to normalize a timeseries")
-        }
+    let method = parse_gapfill_method(&method);
+    let truncate = match truncate {
+        Some(x) => x,
+        None => true,
+    };
+    if series.len() < 2 {
+        panic!("Need at least two points to normalize a timeseries")
+    }
+
+    // TODO: if series is sorted we should be able to do this without a copy
+    let mut series = series.to_internal_time_series();
+    series.sort();
 
-        // TODO: if series is sorted we should be able to do this without a copy
-        let mut series = series.to_internal_time_series();
-        series.sort();
+    let (months, days, micros) = unsafe {
+        let interval = interval as *const pg_sys::Interval;
+        ((*interval).month, (*interval).day, (*interval).time)
+    };
+    if months < 0 || days < 0 || micros < 0 || (months == 0 && days == 0 && micros == 0) {
+        panic!("Normalization interval must be positive")
+    }
 
-        let align = if truncate {interval} else {1};
+    if months != 0 || days != 0 {
+        let calendar = LocalCalendar { months, days, micros, time_zone };
         let start = match range_start {
             Some(t) => t,
             None => series.first().unwrap().ts,
-        } / align * align;
-
+        };
+        let start = if truncate { calendar.truncate(start) } else { start };
         let end = match range_end {
             Some(t) => t,
             None => series.last().unwrap().ts,
-        } / align * align;
+        };
+        let times = calendar.times(start, end);
+        let points = normalized_points(&series, times.iter().copied(), method);
+        let step = match times.get(1) {
+            Some(second) => second - times[0],
+            None => 0,
+        };
+        if step > 0 && times.windows(2).all(|w| w[1] - w[0] == step) {
+            let values: Vec<f64> = points.iter().map(|p| p.val).collect();
+            return Some(TimeSeries::from_values(start, step, &values))
+        }
+        return Some(TimeSeries::from_points(&points, true))
+    }
 
-        let mut iter = series.iter().peekable();
-        let mut first = iter.next().unwrap();
-        let mut second = iter.next().unwrap();
+    let align = if truncate {micros} else {1};
+    let start = match range_start {
+        Some(t) => t,
+        None => series.first().unwrap().ts,
+    } / align * align;
 
-        while second.ts < start && iter.peek().is_some() {
-            first = second;
-            second = iter.next().unwrap();
-        }
+    let end = match range_end {
+        Some(t) => t,
+        None => series.last().unwrap().ts,
+    } / align * align;
 
+    let times = (start..=end).step_by(micros as usize);
+    let values: Vec<f64> = normalized_points(&series, times, method).iter().map(|p| p.val).collect();
+    Some(TimeSeries::from_values(start, micros, &values))
+}
+
+// The value of `series`, which must be sorted, at each of `times`, which must
+// be increasing. Times without a point of their own get their value from the
+// points around them using `method`.
+fn normalized_points(
+    series: &InternalTimeSeries,
+    times: impl Iterator<Item=i64>,
+    method: GapfillMethod,
+) -> Vec<TSPoint> {
+    let mut iter = series.iter().peekable();
+    let mut left = iter.next().unwrap();
+    let mut right = iter.next().unwrap();
 
-        // TODO: should be able to create new TimeSeries in place
-        let mut result =
-            InternalTimeSeries::new_normal_series(
-                if start < first.ts {
-                    method.predict_left(start, first, Some(second))
-                } else if start == first.ts {
-                    first
-                } else if start < second.ts {
-                    method.gapfill(start, first, second)
-                } else {
-                    method.predict_right(start, second, Some(first))
-                }, interval);
-
-        let mut next = start + interval;
-
-        while next < first.ts {
-            result.add_point(method.predict_left(next, first, Some(second)));
-            next += interval;
+    times.map(|time| {
+        while right.ts <= time && iter.peek().is_some() {
+            left = right;
+            right = iter.next().unwrap();
         }
+        if time < left.ts {
+            method.predict_left(time, left, Some(right))
+        } else if time == left.ts {
+            left
+        } else if time < right.ts {
+            method.gapfill(time, left, right)
+        } else {
+            // This will still behave correctly if time == right.ts
+            method.predict_right(time, right, Some(left))
+        }
+    }).collect()
+}
 
-        let mut left = first;
-        let mut right = second;
+// Steps through the local calendar of a time zone by an interval containing
+// days or months. Times are converted to the local time, stepped with Postgres'
+// timestamp + interval and converted back, so '1 day' always lands on local
+// midnight, even when DST starts or ends, and '1 month' on the same day of each
+// month, or the last day of the shorter ones.
+struct LocalCalendar {
+    months: i32,
+    days: i32,
+    micros: i64,
+    // the session's time zone if None
+    time_zone: Option<String>,
+}
 
-        while next <= end {
-            if next == left.ts {
-                result.add_point(left);
-                next += interval;
-            }
-            while next < right.ts && next <= end {
-                result.add_point(method.gapfill(next, left, right));
-                next += interval;
+impl LocalCalendar {
+    fn to_local(&self, time: pg_sys::TimestampTz) -> pg_sys::Timestamp {
+        let time = Some(time as pg_sys::Datum);
+        match &self.time_zone {
+            Some(zone) => direct_function_call(pg_sys::timestamptz_zone, vec![zone.as_str().into_datum(), time]),
+            None => direct_function_call(pg_sys::timestamptz_timestamp, vec![time]),
+        }.unwrap()
+    }
+
+    fn from_local(&self, local: pg_sys::Timestamp) -> pg_sys::TimestampTz {
+        let local = Some(local as pg_sys::Datum);
+        match &self.time_zone {
+            Some(zone) => direct_function_call(pg_sys::timestamp_zone, vec![zone.as_str().into_datum(), local]),
+            None => direct_function_call(pg_sys::timestamp_timestamptz, vec![local]),
+        }.unwrap()
+    }
+
+    // `steps` intervals after the local time `local`. This is added in one go
+    // rather than step by step so that a month after the 31st of January is
+    // the 28th or 29th of February, but two months after is the 31st of March.
+    fn step(&self, local: pg_sys::Timestamp, steps: i32) -> pg_sys::Timestamp {
+        let interval = interval_from_parts(self.months * steps, self.days * steps, self.micros * steps as i64);
+        direct_function_call(pg_sys::timestamp_pl_interval, vec![Some(local as pg_sys::Datum), Some(interval)])
+            .unwrap()
+    }
+
+    // The start of the local calendar period containing `time`. Periods of
+    // months are counted from the start of the year 0, so '3 months' gives
+    // quarters and '1 year' years, periods of whole weeks start on Mondays, and
+    // other periods are counted from midnight on 2000-01-01.
+    fn truncate(&self, time: pg_sys::TimestampTz) -> pg_sys::TimestampTz {
+        const USECS_PER_DAY: i64 = 24 * 60 * 60 * 1_000_000;
+        let local = self.to_local(time);
+        let local = if self.months != 0 {
+            if self.days != 0 || self.micros != 0 {
+                panic!("Normalization intervals containing months cannot also contain days or smaller units when truncating")
             }
-            if iter.peek().is_some() {
-                left = right;
-                right = iter.next().unwrap();
-            } else {
-                while next <= end {
-                    // This will still behave correctly if next == right.ts
-                    result.add_point(method.predict_right(next, right, Some(left)));
-                    next += interval;
+            unsafe {
+                let mut tm: pg_sys::pg_tm = std::mem::MaybeUninit::zeroed().assume_init();
+                let mut fsec = 0;
+                let err = pg_sys::timestamp2tm(local, std::ptr::null_mut(), &mut tm, &mut fsec, std::ptr::null_mut(), std::ptr::null_mut());
+                if err != 0 {
+                    panic!("timestamp out of range")
+                }
+                let month = tm.tm_year * 12 + tm.tm_mon - 1;
+                let month = month - month.rem_euclid(self.months);
+                tm.tm_year = month.div_euclid(12);
+                tm.tm_mon = month.rem_euclid(12) + 1;
+                tm.tm_mday = 1;
+                tm.tm_hour = 0;
+                tm.tm_min = 0;
+                tm.tm_sec = 0;
+                let mut start = 0;
+                if pg_sys::tm2timestamp(&mut tm, 0, std::ptr::null_mut(), &mut start) != 0 {
+                    panic!("timestamp out of range")
                 }
+                start
             }
+        } else {
+            // local timestamps count from 2000-01-01, the 3rd was a Monday
+            let period = self.days as i64 * USECS_PER_DAY + self.micros;
+            let origin = if period % (7 * USECS_PER_DAY) == 0 { 2 * USECS_PER_DAY } else { 0 };
+            origin + (local - origin).div_euclid(period) * period
+        };
+        self.from_local(local)
+    }
+
+    // The times from `start` up to and including `end`, one interval apart in
+    // the local calendar.
+    fn times(&self, start: pg_sys::TimestampTz, end: pg_sys::TimestampTz) -> Vec<pg_sys::TimestampTz> {
+        let start_local = self.to_local(start);
+        let mut times = vec![];
+        let mut time = start;
+        while time <= end {
+            times.push(time);
+            time = self.from_local(self.step(start_local, times.len() as i32));
         }
+        times
+    }
+}
 
-        Some(TimeSeries::from_internal_time_series(&result))
+// Downsamples a series into a normal series with one value per `interval`,
+// combining the points in each bucket with `agg`. Like time_bucket() the
+// buckets are aligned to multiples of the interval since 2000-01-01 UTC, the
+// Postgres epoch, rather than to the first point. Empty buckets are filled
+// using `gapfill`, except when counting, where they are 0.
+#[pg_extern(schema = "toolkit_experimental", strict, immutable)]
+pub fn resample(
+    series: crate::time_series::toolkit_experimental::TimeSeries<'static>,
+    interval: Interval,
+    agg: default!(String, "average"),
+    gapfill: default!(String, "locf"),
+    percentile: default!(f64, 0.5),
+) -> crate::time_series::toolkit_experimental::TimeSeries<'static> {
+    let interval = interval_to_micros(interval);
+    if interval <= 0 {
+        panic!("resample interval must be positive")
+    }
+    let aggregation = match agg.trim().to_ascii_lowercase().as_str() {
+        "average" | "avg" => AggregationMethod::Average,
+        "min" => AggregationMethod::Min,
+        "max" => AggregationMethod::Max,
+        "sum" => AggregationMethod::Sum,
+        "count" => AggregationMethod::Count,
+        "first" => AggregationMethod::First,
+        "last" => AggregationMethod::Last,
+        "median" => AggregationMethod::Percentile(0.5),
+        "percentile" => {
+            if !(0.0..=1.0).contains(&percentile) {
+                panic!("percentile must be between 0 and 1")
+            }
+            AggregationMethod::Percentile(percentile)
+        },
+        _ => panic!("Unknown aggregation: {} - valid aggregations are average, min, max, sum, count, first, last, median, or percentile", agg)
+    };
+    let gapfill = parse_gapfill_method(&gapfill);
+
+    let series = match series.to_internal_time_series() {
+        InternalTimeSeries::Explicit(mut series) => {
+            series.sort();
+            series
+        },
+        normal @ InternalTimeSeries::Normal(_) => ExplicitTimeSeries {
+            ordered: true,
+            points: normal.iter().collect(),
+        },
+    };
+
+    match series.resample(interval, 0, aggregation, gapfill) {
+        Ok(result) => TimeSeries::from_internal_time_series(&InternalTimeSeries::Normal(result)),
+        Err(_) => unreachable!(),
     }
 }
 
 
+// Element-wise arithmetic, available as operators with the series on the
+// left, eg `series * 2.0`. Like the builtin operators, dividing by zero is an
+// error rather than producing infinities.
+#[pg_extern(schema = "toolkit_experimental", strict, immutable)]
+pub fn timeseries_add(
+    series: crate::time_series::toolkit_experimental::TimeSeries<'static>,
+    value: f64,
+) -> crate::time_series::toolkit_experimental::TimeSeries<'static> {
+    series.map_values(|v| v + value)
+}
+
+#[pg_extern(schema = "toolkit_experimental", strict, immutable)]
+pub fn timeseries_subtract(
+    series: crate::time_series::toolkit_experimental::TimeSeries<'static>,
+    value: f64,
+) -> crate::time_series::toolkit_experimental::TimeSeries<'static> {
+    series.map_values(|v| v - value)
+}
+
+#[pg_extern(schema = "toolkit_experimental", strict, immutable)]
+pub fn timeseries_multiply(
+    series: crate::time_series::toolkit_experimental::TimeSeries<'static>,
+    value: f64,
+) -> crate::time_series::toolkit_experimental::TimeSeries<'static> {
+    series.map_values(|v| v * value)
+}
+
+#[pg_extern(schema = "toolkit_experimental", strict, immutable)]
+pub fn timeseries_divide(
+    series: crate::time_series::toolkit_experimental::TimeSeries<'static>,
+    value: f64,
+) -> crate::time_series::toolkit_experimental::TimeSeries<'static> {
+    if value == 0.0 {
+        panic!("division by zero")
+    }
+    series.map_values(|v| v / value)
+}
+
+extension_sql!(r#"
+CREATE OPERATOR toolkit_experimental.+ (
+    PROCEDURE = toolkit_experimental.timeseries_add,
+    LEFTARG = toolkit_experimental.timeseries,
+    RIGHTARG = DOUBLE PRECISION
+);
+CREATE OPERATOR toolkit_experimental.- (
+    PROCEDURE = toolkit_experimental.timeseries_subtract,
+    LEFTARG = toolkit_experimental.timeseries,
+    RIGHTARG = DOUBLE PRECISION
+);
+CREATE OPERATOR toolkit_experimental.* (
+    PROCEDURE = toolkit_experimental.timeseries_multiply,
+    LEFTARG = toolkit_experimental.timeseries,
+    RIGHTARG = DOUBLE PRECISION
+);
+CREATE OPERATOR toolkit_experimental./ (
+    PROCEDURE = toolkit_experimental.timeseries_divide,
+    LEFTARG = toolkit_experimental.timeseries,
+    RIGHTARG = DOUBLE PRECISION
+);
+"#);
+
+#[pg_extern(schema = "toolkit_experimental", name="abs", strict, immutable)]
+pub fn timeseries_abs(
+    series: crate::time_series::toolkit_experimental::TimeSeries<'static>,
+) -> crate::time_series::toolkit_experimental::TimeSeries<'static> {
+    series.map_values(f64::abs)
+}
+
+// as with the builtin ln(), the logarithm of zero or a negative number is an error
+#[pg_extern(schema = "toolkit_experimental", name="ln", strict, immutable)]
+pub fn timeseries_ln(
+    series: crate::time_series::toolkit_experimental::TimeSeries<'static>,
+) -> crate::time_series::toolkit_experimental::TimeSeries<'static> {
+    series.map_values(|v| {
+        if v == 0.0 {
+            panic!("cannot take logarithm of zero")
+        }
+        if v < 0.0 {
+            panic!("cannot take logarithm of a negative number")
+        }
+        v.ln()
+    })
+}
+
+// a NULL bound leaves that side unbounded
+#[pg_extern(schema = "toolkit_experimental", name="clamp", immutable)]
+pub fn timeseries_clamp(
+    series: Option<crate::time_series::toolkit_experimental::TimeSeries<'static>>,
+    min: Option<f64>,
+    max: Option<f64>,
+) -> Option<crate::time_series::toolkit_experimental::TimeSeries<'static>> {
+    let min = min.unwrap_or(f64::NEG_INFINITY);
+    let max = max.unwrap_or(f64::INFINITY);
+    if min > max {
+        panic!("clamp minimum must not be greater than the maximum")
+    }
+    Some(series?.map_values(|v| if v.is_nan() { v } else { v.max(min).min(max) }))
+}
+
+// the change in value from each point to the next, so there is one point
+// fewer than in `series`
+#[pg_extern(schema = "toolkit_experimental", name="delta", strict, immutable)]
+pub fn timeseries_delta(
+    series: crate::time_series::toolkit_experimental::TimeSeries<'static>,
+) -> crate::time_series::toolkit_experimental::TimeSeries<'static> {
+    series.map_pairs(|earlier, later| later.val - earlier.val)
+}
+
+// the rate of change per `per` between each point and the next
+#[pg_extern(schema = "toolkit_experimental", name="derivative", strict, immutable)]
+pub fn timeseries_derivative(
+    series: crate::time_series::toolkit_experimental::TimeSeries<'static>,
+    per: Interval,
+) -> crate::time_series::toolkit_experimental::TimeSeries<'static> {
+    let per = interval_to_micros(per);
+    if per <= 0 {
+        panic!("derivative interval must be positive")
+    }
+    series.map_pairs(|earlier, later| {
+        if later.ts == earlier.ts {
+            panic!("cannot take the derivative of a series with multiple points at the same time")
+        }
+        (later.val - earlier.val) / (later.ts - earlier.ts) as f64 * per as f64
+    })
+}
+
+
+// the points with times within `range`
+#[pg_extern(schema = "toolkit_experimental", name="slice", strict, immutable)]
+pub fn timeseries_slice(
+    series: crate::time_series::toolkit_experimental::TimeSeries<'static>,
+    range: tstzrange,
+) -> crate::time_series::toolkit_experimental::TimeSeries<'static> {
+    let range = unsafe { get_range(range as *mut pg_sys::varlena) };
+    match range {
+        Some(range) => series.time_slice(range.left, range.right),
+        // the range is empty
+        None => series.time_slice(Some(0), Some(0)),
+    }
+}
+
+// the points with times from `start` to `end` inclusive, like BETWEEN. A
+// NULL bound leaves that side unbounded. BETWEEN is a keyword, so this needs
+// to be called with the schema or as "between".
+#[pg_extern(schema = "toolkit_experimental", name="between", immutable)]
+pub fn timeseries_between(
+    series: Option<crate::time_series::toolkit_experimental::TimeSeries<'static>>,
+    start: Option<pg_sys::TimestampTz>,
+    end: Option<pg_sys::TimestampTz>,
+) -> Option<crate::time_series::toolkit_experimental::TimeSeries<'static>> {
+    let end = end.map(|end| end.saturating_add(1));
+    Some(series?.time_slice(start, end))
+}
+
+// the points with values from `min` to `max` inclusive, a NULL bound leaves
+// that side unbounded
+#[pg_extern(schema = "toolkit_experimental", name="filter", immutable)]
+pub fn timeseries_filter(
+    series: Option<crate::time_series::toolkit_experimental::TimeSeries<'static>>,
+    min: Option<f64>,
+    max: Option<f64>,
+) -> Option<crate::time_series::toolkit_experimental::TimeSeries<'static>> {
+    Some(series?.filter_values(|v| min.map_or(true, |min| v >= min) && max.map_or(true, |max| v <= max)))
+}
+
+// the earliest point, or no row if the series is empty
+#[pg_extern(schema = "toolkit_experimental", name="first", strict, immutable)]
+pub fn timeseries_first(
+    series: crate::time_series::toolkit_experimental::TimeSeries<'static>,
+) -> impl std::iter::Iterator<Item = (name!(time,pg_sys::TimestampTz),name!(value,f64))> {
+    series.first().map(|p| (p.ts, p.val)).into_iter()
+}
+
+// the latest point, or no row if the series is empty
+#[pg_extern(schema = "toolkit_experimental", name="last", strict, immutable)]
+pub fn timeseries_last(
+    series: crate::time_series::toolkit_experimental::TimeSeries<'static>,
+) -> impl std::iter::Iterator<Item = (name!(time,pg_sys::TimestampTz),name!(value,f64))> {
+    series.last().map(|p| (p.ts, p.val)).into_iter()
+}
+
 #[cfg(any(test, feature = "pg_test"))]
 mod tests {
     use pgx::*;
@@ -480,4 +985,227 @@ mod tests {
 
         })
     }
+
+    #[pg_test]
+    fn test_calendar_normalization() {
+        Spi::execute(|client| {
+            client.select("CREATE TABLE test(time TIMESTAMPTZ, value DOUBLE PRECISION);", None, None);
+            // the value is the number of hours since 2020-01-10 UTC, every 6
+            // hours until 2020-04-19, so interpolating it gives the time of each
+            // point back
+            client.select(
+                "INSERT INTO test
+                SELECT '2020-01-10 UTC'::timestamptz + '6 hours'::interval * i, 6.0 * i
+                FROM generate_series(0, 400) as i", None, None);
+            // set search_path after defining our table so we don't pollute the wrong schema
+            let search_path = client.select("SELECT format('toolkit_experimental, %s',current_setting('search_path'))", None, None)
+                .first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", search_path), None, None);
+
+            // the hours since 2020-01-10 UTC of each point of the normalized series
+            let hours = |args: &str| -> Vec<f64> {
+                let stmt = format!(
+                    "SELECT date_part('epoch', time - '2020-01-10 UTC')/3600, value
+                    FROM unnest_series((SELECT normalize(timeseries(time, value), {}) FROM test))
+                    ORDER BY time",
+                    args);
+                client.select(&stmt, None, None)
+                    .map(|r| {
+                        let hours = r.by_ordinal(1).unwrap().value::<f64>().unwrap();
+                        let value = r.by_ordinal(2).unwrap().value::<f64>().unwrap();
+                        assert!((hours - value).abs() < 1e-9, "{} != {}", hours, value);
+                        hours
+                    })
+                    .collect()
+            };
+
+            // local midnight, including on the 29th of March when DST starts
+            let days = hours("'1 day', 'interpolate', true, '2020-03-27 UTC', '2020-03-31 UTC', 'Europe/Berlin'");
+            assert_eq!(days, vec![1847.0, 1871.0, 1895.0, 1918.0, 1942.0]);
+
+            // the first of each month, which was CET (UTC+1) until April
+            let months = hours("'1 month', 'interpolate', true, NULL, NULL, 'Europe/Berlin'");
+            assert_eq!(months, vec![-217.0, 527.0, 1223.0, 1966.0]);
+            let quarters = hours("'3 months', 'interpolate', true, NULL, NULL, 'UTC'");
+            assert_eq!(quarters, vec![-216.0, 1968.0]);
+
+            // weeks start on Monday, the 6th of January
+            let weeks = hours("'1 week', 'interpolate', true, NULL, '2020-01-31 UTC', 'UTC'");
+            assert_eq!(weeks, vec![-96.0, 72.0, 240.0, 408.0]);
+
+            // without truncation the months step from the 31st to the last day of shorter months
+            let stmt = "SELECT to_char(time AT TIME ZONE 'UTC', 'MM-DD')
+                FROM unnest_series((SELECT normalize(timeseries(time, value), '1 month', 'locf', false,
+                    '2020-01-31 UTC', '2020-04-30 UTC', 'UTC') FROM test))
+                ORDER BY time";
+            let dates: Vec<String> = client.select(stmt, None, None)
+                .map(|r| r.by_ordinal(1).unwrap().value::<String>().unwrap())
+                .collect();
+            assert_eq!(dates, vec!["01-31", "02-29", "03-31", "04-30"]);
+
+            // the session time zone is used by default, DST started at 2am on the 8th
+            client.select("SET LOCAL TIME ZONE 'America/New_York'", None, None);
+            let days = hours("'1 day', 'interpolate', true, '2020-03-07 UTC', '2020-03-10 UTC'");
+            assert_eq!(days, vec![1349.0, 1373.0, 1397.0, 1420.0]);
+
+            // when the steps are all the same length the series is still normal
+            let normal = |args: &str| -> bool {
+                let stmt = format!(
+                    "SELECT normalize(timeseries(time, value), {})::text LIKE '%NormalSeries%' FROM test",
+                    args);
+                client.select(&stmt, None, None).first().get_one::<bool>().unwrap()
+            };
+            assert!(normal("'1 day', 'interpolate', true, '2020-03-07 UTC', '2020-03-10 UTC', 'UTC'"));
+            assert!(normal("'1 week', 'interpolate', true, NULL, '2020-01-31 UTC', 'UTC'"));
+            assert!(!normal("'1 day', 'interpolate', true, '2020-03-07 UTC', '2020-03-10 UTC'"));
+            assert!(!normal("'1 month', 'interpolate', true, NULL, NULL, 'UTC'"));
+            let days = hours("'1 day', 'interpolate', true, '2020-03-07 UTC', '2020-03-10 UTC', 'UTC'");
+            assert_eq!(days, vec![1368.0, 1392.0, 1416.0, 1440.0]);
+        })
+    }
+
+    #[pg_test]
+    fn test_arithmetic() {
+        Spi::execute(|client| {
+            client.select("CREATE TABLE test(time TIMESTAMPTZ, value DOUBLE PRECISION);", None, None);
+            client.select(
+                "INSERT INTO test VALUES \
+                ('2020-01-01 0:00 UTC', 1.0), \
+                ('2020-01-01 0:20 UTC', -4.0), \
+                ('2020-01-01 0:10 UTC', 2.0)", None, None);
+            // set search_path after defining our table so we don't pollute the wrong schema
+            let search_path = client.select("SELECT format('toolkit_experimental, %s',current_setting('search_path'))", None, None)
+                .first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", search_path), None, None);
+
+            let values = |stmt: &str| -> Vec<f64> {
+                client.select(&format!("SELECT value FROM unnest_series(({})) ORDER BY time", stmt), None, None)
+                    .map(|r| r.by_ordinal(1).unwrap().value::<f64>().unwrap())
+                    .collect()
+            };
+
+            // the points are out of order, so this is an ExplicitSeries
+            let series = "SELECT timeseries(time, value) FROM test";
+            assert_eq!(values(&format!("SELECT s * 2.0 + 1.0 FROM ({}) t(s)", series)), vec![3.0, 5.0, -7.0]);
+            assert_eq!(values(&format!("SELECT abs(s / 2.0 - 1.0) FROM ({}) t(s)", series)), vec![0.5, 0.0, 3.0]);
+            assert_eq!(values(&format!("SELECT clamp(s, 0, NULL) FROM ({}) t(s)", series)), vec![1.0, 2.0, 0.0]);
+            assert_eq!(values(&format!("SELECT delta(s) FROM ({}) t(s)", series)), vec![1.0, -6.0]);
+            assert_eq!(values(&format!("SELECT derivative(s, '1 minute') FROM ({}) t(s)", series)), vec![0.1, -0.6]);
+
+            // and this a NormalSeries with the values 1, 1.5, 2, -1, -4
+            let series = "SELECT normalize(timeseries(time, value), '5 min', 'interpolate', true) FROM test";
+            assert_eq!(values(&format!("SELECT s - 1.0 FROM ({}) t(s)", series)), vec![0.0, 0.5, 1.0, -2.0, -5.0]);
+            assert_eq!(values(&format!("SELECT delta(s) FROM ({}) t(s)", series)), vec![0.5, 0.5, -3.0, -3.0]);
+            assert_eq!(values(&format!("SELECT derivative(s, '10 min') FROM ({}) t(s)", series)), vec![1.0, 1.0, -6.0, -6.0]);
+            assert_eq!(values(&format!("SELECT ln(clamp(s, 1, NULL)) FROM ({}) t(s)", series)), vec![0.0, 1.5f64.ln(), 2.0f64.ln(), 0.0, 0.0]);
+        })
+    }
+
+    #[pg_test]
+    fn test_slicing() {
+        Spi::execute(|client| {
+            client.select("CREATE TABLE test(time TIMESTAMPTZ, value DOUBLE PRECISION);", None, None);
+            client.select(
+                "INSERT INTO test
+                SELECT '2020-01-01 0:00 UTC'::timestamptz + '10 minutes'::interval * i, i::float
+                FROM generate_series(0,5) as i", None, None);
+            // set search_path after defining our table so we don't pollute the wrong schema
+            let search_path = client.select("SELECT format('toolkit_experimental, %s',current_setting('search_path'))", None, None)
+                .first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", search_path), None, None);
+
+            let values = |stmt: &str| -> Vec<f64> {
+                client.select(&format!("SELECT value FROM unnest_series(({})) ORDER BY time", stmt), None, None)
+                    .map(|r| r.by_ordinal(1).unwrap().value::<f64>().unwrap())
+                    .collect()
+            };
+
+            // the values are 0, 1, 2, 3, 4, 5 at 10 minute intervals, as a
+            // sorted, an unsorted and a normal series
+            for series in &[
+                "SELECT timeseries(time, value ORDER BY time) FROM test",
+                "SELECT timeseries(time, value ORDER BY time DESC) FROM test",
+                "SELECT normalize(timeseries(time, value), '10 min', 'locf', true) FROM test",
+            ] {
+                let stmt = format!("SELECT slice(s, '[2020-01-01 0:10 UTC, 2020-01-01 0:30 UTC)') FROM ({}) t(s)", series);
+                assert_eq!(values(&stmt), vec![1.0, 2.0]);
+                let stmt = format!("SELECT slice(s, '(2020-01-01 0:05 UTC, 2020-01-01 0:30 UTC]') FROM ({}) t(s)", series);
+                assert_eq!(values(&stmt), vec![1.0, 2.0, 3.0]);
+                let stmt = format!("SELECT slice(s, '[2020-01-01 0:35 UTC,)') FROM ({}) t(s)", series);
+                assert_eq!(values(&stmt), vec![4.0, 5.0]);
+                let stmt = format!("SELECT slice(s, 'empty') FROM ({}) t(s)", series);
+                assert_eq!(values(&stmt), Vec::<f64>::new());
+                let stmt = format!("SELECT toolkit_experimental.between(s, '2020-01-01 0:10 UTC', '2020-01-01 0:30 UTC') FROM ({}) t(s)", series);
+                assert_eq!(values(&stmt), vec![1.0, 2.0, 3.0]);
+                let stmt = format!("SELECT toolkit_experimental.between(s, NULL, '2019-01-01 UTC') FROM ({}) t(s)", series);
+                assert_eq!(values(&stmt), Vec::<f64>::new());
+                let stmt = format!("SELECT filter(s, 1.5, 4) FROM ({}) t(s)", series);
+                assert_eq!(values(&stmt), vec![2.0, 3.0, 4.0]);
+                let stmt = format!("SELECT filter(s, NULL, 1) FROM ({}) t(s)", series);
+                assert_eq!(values(&stmt), vec![0.0, 1.0]);
+
+                let stmt = format!("SELECT (first(s)).value FROM ({}) t(s)", series);
+                assert_eq!(client.select(&stmt, None, None).first().get_one::<f64>(), Some(0.0));
+                let stmt = format!("SELECT (last(s)).value FROM ({}) t(s)", series);
+                assert_eq!(client.select(&stmt, None, None).first().get_one::<f64>(), Some(5.0));
+                let stmt = format!("SELECT count(*) FROM ({}) t(s), last(slice(s, 'empty'))", series);
+                assert_eq!(client.select(&stmt, None, None).first().get_one::<i64>(), Some(0));
+            }
+        })
+    }
+
+    #[pg_test]
+    fn test_resample() {
+        Spi::execute(|client| {
+            client.select("CREATE TABLE test(time TIMESTAMPTZ, value DOUBLE PRECISION);", None, None);
+            // 4 points in the first 10 minutes, none in the second and 2 in the third
+            client.select(
+                "INSERT INTO test VALUES
+                    ('2020-01-01 0:00 UTC', 4.0),
+                    ('2020-01-01 0:02 UTC', 1.0),
+                    ('2020-01-01 0:05 UTC', 7.0),
+                    ('2020-01-01 0:09 UTC', 4.0),
+                    ('2020-01-01 0:20 UTC', 10.0),
+                    ('2020-01-01 0:25 UTC', 6.0)", None, None);
+            // set search_path after defining our table so we don't pollute the wrong schema
+            let search_path = client.select("SELECT format('toolkit_experimental, %s',current_setting('search_path'))", None, None)
+                .first().get_one::<String>().unwrap();
+            client.select(&format!("SET LOCAL search_path TO {}", search_path), None, None);
+
+            let values = |args: &str| -> Vec<f64> {
+                let stmt = format!(
+                    "SELECT value FROM unnest_series((SELECT resample(timeseries(time, value), '10 min'{}) FROM test)) ORDER BY time",
+                    args);
+                client.select(&stmt, None, None)
+                    .map(|r| r.by_ordinal(1).unwrap().value::<f64>().unwrap())
+                    .collect()
+            };
+
+            assert_eq!(values(""), vec![4.0, 4.0, 8.0]);
+            assert_eq!(values(", agg => 'max'"), vec![7.0, 7.0, 10.0]);
+            assert_eq!(values(", agg => 'max', gapfill => 'interpolate'"), vec![7.0, 8.5, 10.0]);
+            assert_eq!(values(", 'min', 'nearest'"), vec![1.0, 1.0, 6.0]);
+            assert_eq!(values(", agg => 'sum'"), vec![16.0, 16.0, 16.0]);
+            assert_eq!(values(", agg => 'count'"), vec![4.0, 0.0, 2.0]);
+            assert_eq!(values(", agg => 'first'"), vec![4.0, 4.0, 10.0]);
+            assert_eq!(values(", agg => 'last'"), vec![4.0, 4.0, 6.0]);
+            assert_eq!(values(", agg => 'median'"), vec![4.0, 4.0, 8.0]);
+            assert_eq!(values(", agg => 'percentile', percentile => 0.25"), vec![3.25, 3.25, 7.0]);
+
+            let stmt = "SELECT max(time) = '2020-01-01 0:20 UTC' FROM unnest_series((SELECT resample(timeseries(time, value), '10 min') FROM test))";
+            assert_eq!(client.select(stmt, None, None).first().get_one::<bool>(), Some(true));
+
+            // the buckets don't move with the first point
+            let stmt = "SELECT string_agg((time AT TIME ZONE 'UTC')::text || '=' || value, ',' ORDER BY time) FROM unnest_series( \
+                (SELECT resample(timeseries(time, value), '10 min') FROM test WHERE time > '2020-01-01 0:00 UTC'))";
+            let buckets = client.select(stmt, None, None).first().get_one::<String>().unwrap();
+            assert_eq!(buckets, "2020-01-01 00:00:00=4,2020-01-01 00:10:00=4,2020-01-01 00:20:00=8");
+
+            // series shorter than the interval give a single bucket
+            let stmt = "SELECT string_agg((time AT TIME ZONE 'UTC')::text || '=' || value, ',' ORDER BY time) FROM unnest_series( \
+                (SELECT resample(timeseries(time, value), '1 hour', 'count') FROM test))";
+            let buckets = client.select(stmt, None, None).first().get_one::<String>().unwrap();
+            assert_eq!(buckets, "2020-01-01 00:00:00=6");
+        })
+    }
 }
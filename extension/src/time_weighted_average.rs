@@ -1,12 +1,16 @@
 #![allow(non_camel_case_types)]
 
 use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
 use std::slice;
 
 use crate::{
-    aggregate_utils::in_aggregate_context, flatten, json_inout_funcs, palloc::Internal, pg_type,
+    aggregate_utils::in_aggregate_context,
+    datum_utils::{interval_from_micros, interval_to_micros, Interval},
+    flatten, json_inout_funcs, palloc::Internal, pg_type,
 };
 use flat_serialize::*;
+use flat_serialize_macro::FlatSerializable;
 use pgx::*;
 
 use time_series::TSPoint;
@@ -21,17 +25,95 @@ type bytea = pg_sys::Datum;
 
 pg_type! {
     #[derive(Debug)]
-    struct TimeWeightSummary {
+    struct TimeWeightSummary<'input> {
         first: TSPoint,
         last: TSPoint,
         weighted_sum: f64,
         method: TimeWeightMethod,
+        // the max_gap (0 if there is none) and the duration skipped because
+        // of it, missing from summaries before version 2
+        #[serde(default)]
+        max_gap: [UnalignedI64; if self.version >= 2 { 1 } else { 0 }],
+        #[serde(default)]
+        excluded_duration: [UnalignedI64; if self.version >= 2 { 1 } else { 0 }],
+        // the centred second moment used for the variance, missing from
+        // summaries before version 3
+        #[serde(default)]
+        second_moment: [UnalignedF64; if self.version >= 3 { 1 } else { 0 }],
     }
 }
 json_inout_funcs!(TimeWeightSummary);
 
 varlena_type!(TimeWeightSummary);
 
+// version 2 added the max_gap, version 3 the second moment. Older summaries
+// are still readable, they're treated as having no max_gap, and have no
+// variance.
+const TIME_WEIGHT_SUMMARY_VERSION: u8 = 3;
+
+// The fields added after version 1 come after `method`, which is a single
+// byte, so they can't be stored as aligned i64s and f64s. These wrappers store
+// the native-endian bytes instead, and (de)serialize as the plain number.
+#[derive(Clone, Copy, Debug, PartialEq, FlatSerializable)]
+#[repr(C)]
+pub struct UnalignedI64 {
+    bytes: [u8; 8],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, FlatSerializable)]
+#[repr(C)]
+pub struct UnalignedF64 {
+    bytes: [u8; 8],
+}
+
+impl From<i64> for UnalignedI64 {
+    fn from(val: i64) -> Self {
+        Self { bytes: val.to_ne_bytes() }
+    }
+}
+
+impl From<UnalignedI64> for i64 {
+    fn from(val: UnalignedI64) -> Self {
+        i64::from_ne_bytes(val.bytes)
+    }
+}
+
+impl From<f64> for UnalignedF64 {
+    fn from(val: f64) -> Self {
+        Self { bytes: val.to_ne_bytes() }
+    }
+}
+
+impl From<UnalignedF64> for f64 {
+    fn from(val: UnalignedF64) -> Self {
+        f64::from_ne_bytes(val.bytes)
+    }
+}
+
+impl Serialize for UnalignedI64 {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        i64::from(*self).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for UnalignedI64 {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        i64::deserialize(deserializer).map(Into::into)
+    }
+}
+
+impl Serialize for UnalignedF64 {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        f64::from(*self).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for UnalignedF64 {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        f64::deserialize(deserializer).map(Into::into)
+    }
+}
+
 impl<'input> TimeWeightSummary<'input> {
     fn to_internal(&self) -> TimeWeightSummaryInternal {
         TimeWeightSummaryInternal {
@@ -39,6 +121,34 @@ impl<'input> TimeWeightSummary<'input> {
             first: self.first,
             last: self.last,
             w_sum: self.weighted_sum,
+            m2: self.second_moment.first().copied().map(f64::from),
+            max_gap: match self.max_gap.first().copied().map_or(0, i64::from) {
+                0 => None,
+                max_gap => Some(max_gap),
+            },
+            excluded: self.excluded_duration.first().copied().map_or(0, i64::from),
+        }
+    }
+
+    fn from_internal(tws: &TimeWeightSummaryInternal) -> TimeWeightSummary<'static> {
+        let max_gap = [tws.max_gap.unwrap_or(0).into()];
+        let excluded_duration = [tws.excluded.into()];
+        // a summary without a second moment can only be written as version 2
+        let m2 = tws.m2.map(UnalignedF64::from);
+        let (version, moment) = match &m2 {
+            Some(m2) => (TIME_WEIGHT_SUMMARY_VERSION, slice::from_ref(m2)),
+            None => (2, &[][..]),
+        };
+        unsafe {
+            flatten!(TimeWeightSummary version version {
+                method: tws.method,
+                first: tws.first,
+                last: tws.last,
+                weighted_sum: tws.w_sum,
+                max_gap: &max_gap,
+                excluded_duration: &excluded_duration,
+                second_moment: moment,
+            })
         }
     }
 }
@@ -48,6 +158,7 @@ pub struct TimeWeightTransState {
     #[serde(skip)]
     point_buffer: Vec<TSPoint>,
     method: TimeWeightMethod,
+    max_gap: Option<i64>,
     summary_buffer: Vec<TimeWeightSummaryInternal>,
 }
 
@@ -62,8 +173,12 @@ impl TimeWeightTransState {
         }
         self.point_buffer.sort_unstable_by_key(|p| p.ts);
         self.summary_buffer.push(
-            TimeWeightSummaryInternal::new_from_sorted_iter(&self.point_buffer, self.method)
-                .unwrap(),
+            TimeWeightSummaryInternal::new_from_sorted_iter_with_max_gap(
+                &self.point_buffer,
+                self.method,
+                self.max_gap,
+            )
+            .unwrap(),
         );
         self.point_buffer.clear();
     }
@@ -100,6 +215,17 @@ pub fn time_weight_trans_deserialize(
     crate::do_deserialize!(bytes, TimeWeightTransState)
 }
 
+pub(crate) fn parse_method(method: &str) -> TimeWeightMethod {
+    // TODO technically not portable to ASCII-compatible charsets
+    match method.trim().to_lowercase().as_str() {
+        "linear" => TimeWeightMethod::Linear,
+        "locf" => TimeWeightMethod::LOCF,
+        "nocb" => TimeWeightMethod::NOCB,
+        "nearest" => TimeWeightMethod::Nearest,
+        _ => panic!("unknown method '{}', valid methods are linear, locf, nocb and nearest", method),
+    }
+}
+
 #[pg_extern()]
 pub fn time_weight_trans(
     state: Option<Internal<TimeWeightTransState>>,
@@ -107,6 +233,18 @@ pub fn time_weight_trans(
     ts: Option<pg_sys::TimestampTz>,
     val: Option<f64>,
     fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<TimeWeightTransState>> {
+    time_weight_trans_with_max_gap(state, method, ts, val, None, fcinfo)
+}
+
+#[pg_extern(schema = "toolkit_experimental")]
+pub fn time_weight_trans_with_max_gap(
+    state: Option<Internal<TimeWeightTransState>>,
+    method: String,
+    ts: Option<pg_sys::TimestampTz>,
+    val: Option<f64>,
+    max_gap: Option<Interval>,
+    fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<Internal<TimeWeightTransState>> {
     unsafe {
         in_aggregate_context(fcinfo, || {
@@ -120,14 +258,13 @@ pub fn time_weight_trans(
                 None => {
                     let mut s = TimeWeightTransState {
                         point_buffer: vec![],
-                        // TODO technically not portable to ASCII-compatible charsets
-                        method: match method.trim().to_lowercase().as_str() {
-                            "linear" => TimeWeightMethod::Linear,
-                            "locf" => TimeWeightMethod::LOCF,
-                            _ => panic!("unknown method"),
-                        },
+                        method: parse_method(&method),
+                        max_gap: max_gap.map(interval_to_micros),
                         summary_buffer: vec![],
                     };
+                    if matches!(s.max_gap, Some(max_gap) if max_gap <= 0) {
+                        panic!("max_gap must be positive")
+                    }
                     s.push_point(p);
                     Some(s.into())
                 }
@@ -154,6 +291,7 @@ pub fn time_weight_summary_trans<'b>(
                     summary_buffer: vec![next.to_internal()],
                     point_buffer: vec![],
                     method: next.method.clone(),
+                    max_gap: next.to_internal().max_gap,
                 }
                 .into(),
             ),
@@ -163,6 +301,7 @@ pub fn time_weight_summary_trans<'b>(
                     summary_buffer: vec![next.to_internal()],
                     point_buffer: vec![],
                     method: next.method,
+                    max_gap: next.to_internal().max_gap,
                 };
                 state.push_summary(&next);
                 Some(state.into())
@@ -217,22 +356,314 @@ fn time_weight_final(
             };
             state.combine_summaries();
             debug_assert!(state.summary_buffer.len() <= 1);
-            match state.summary_buffer.pop() {
-                None => None,
-                Some(st) => Some(
-                    flatten!(TimeWeightSummary {
-                        method: st.method,
-                        first: st.first,
-                        last: st.last,
-                        weighted_sum: st.w_sum,
-                    })
-                    .into(),
-                ),
+            state
+                .summary_buffer
+                .pop()
+                .map(|st| TimeWeightSummary::from_internal(&st))
+        })
+    }
+}
+
+// State for time_weight used as a moving aggregate, ie in a window whose frame
+// start moves. Postgres adds rows at the end of the frame and removes them
+// from its start, so as long as the window is ordered by time we can keep the
+// summary up to date by accumulating new points and removing the first one.
+// We keep every point in the frame so we know which point follows the one
+// being removed.
+#[derive(Clone, Debug)]
+pub struct TimeWeightMovingState {
+    points: VecDeque<TSPoint>,
+    method: TimeWeightMethod,
+    max_gap: Option<i64>,
+    // None exactly when there are no points
+    summary: Option<TimeWeightSummaryInternal>,
+}
+
+impl TimeWeightMovingState {
+    fn rebuild_summary(&mut self) {
+        let (front, back) = self.points.as_slices();
+        self.summary = match self.points.is_empty() {
+            true => None,
+            false => Some(
+                TimeWeightSummaryInternal::new_from_sorted_iter_with_max_gap(
+                    front.iter().chain(back),
+                    self.method,
+                    self.max_gap,
+                )
+                .unwrap(),
+            ),
+        };
+    }
+
+    fn add_point(&mut self, p: TSPoint) {
+        match self.points.back() {
+            Some(last) if p.ts >= last.ts => {
+                self.summary.as_mut().unwrap().accum(p).unwrap();
+                self.points.push_back(p);
+            }
+            _ => {
+                // the window isn't ordered by time, keep the points sorted and
+                // start over
+                let idx = self.points.partition_point(|q| q.ts <= p.ts);
+                self.points.insert(idx, p);
+                self.rebuild_summary();
+            }
+        }
+    }
+
+    // returns false if the point isn't in the frame
+    fn remove_point(&mut self, p: TSPoint) -> bool {
+        if self.points.front() == Some(&p) {
+            self.points.pop_front();
+            match self.points.front() {
+                // duplicate timestamps are only counted once so the summary
+                // needs to start from whichever point now comes first
+                Some(&next) if next.ts > p.ts => {
+                    match self.summary.as_mut().unwrap().remove_first(next) {
+                        Ok(()) => {}
+                        // too little is left to subtract from, recompute it
+                        Err(TimeWeightError::PrecisionLoss) => self.rebuild_summary(),
+                        Err(e) => panic!("{:?}", e),
+                    }
+                }
+                _ => self.rebuild_summary(),
+            }
+            return true;
+        }
+        match self.points.iter().position(|q| *q == p) {
+            None => false,
+            Some(idx) => {
+                self.points.remove(idx);
+                self.rebuild_summary();
+                true
+            }
+        }
+    }
+}
+
+#[pg_extern()]
+pub fn time_weight_moving_trans(
+    state: Option<Internal<TimeWeightMovingState>>,
+    method: String,
+    ts: Option<pg_sys::TimestampTz>,
+    val: Option<f64>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<TimeWeightMovingState>> {
+    time_weight_moving_trans_with_max_gap(state, method, ts, val, None, fcinfo)
+}
+
+#[pg_extern(schema = "toolkit_experimental")]
+pub fn time_weight_moving_trans_with_max_gap(
+    state: Option<Internal<TimeWeightMovingState>>,
+    method: String,
+    ts: Option<pg_sys::TimestampTz>,
+    val: Option<f64>,
+    max_gap: Option<Interval>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<TimeWeightMovingState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let p = match (ts, val) {
+                (Some(ts), Some(val)) => TSPoint { ts, val },
+                _ => return state,
+            };
+            let mut state = match state {
+                None => {
+                    let max_gap = max_gap.map(interval_to_micros);
+                    if matches!(max_gap, Some(max_gap) if max_gap <= 0) {
+                        panic!("max_gap must be positive")
+                    }
+                    TimeWeightMovingState {
+                        points: VecDeque::new(),
+                        method: parse_method(&method),
+                        max_gap,
+                        summary: None,
+                    }
+                    .into()
+                }
+                Some(state) => state,
+            };
+            state.add_point(p);
+            Some(state)
+        })
+    }
+}
+
+#[pg_extern()]
+pub fn time_weight_moving_inv(
+    state: Option<Internal<TimeWeightMovingState>>,
+    method: String,
+    ts: Option<pg_sys::TimestampTz>,
+    val: Option<f64>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<TimeWeightMovingState>> {
+    time_weight_moving_inv_with_max_gap(state, method, ts, val, None, fcinfo)
+}
+
+#[pg_extern(schema = "toolkit_experimental")]
+pub fn time_weight_moving_inv_with_max_gap(
+    state: Option<Internal<TimeWeightMovingState>>,
+    _method: String,
+    ts: Option<pg_sys::TimestampTz>,
+    val: Option<f64>,
+    _max_gap: Option<Interval>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<TimeWeightMovingState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let p = match (ts, val) {
+                (Some(ts), Some(val)) => TSPoint { ts, val },
+                _ => return state,
+            };
+            let mut state = state?;
+            // returning NULL makes postgres recompute the frame from scratch
+            match state.remove_point(p) {
+                true => Some(state),
+                false => None,
+            }
+        })
+    }
+}
+
+#[pg_extern()]
+fn time_weight_moving_final(
+    state: Option<Internal<TimeWeightMovingState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<TimeWeightSummary<'static>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            state?.summary.as_ref().map(TimeWeightSummary::from_internal)
+        })
+    }
+}
+
+// State for rolling(TimeWeightSummary) over a window. As with
+// TimeWeightMovingState the summaries in the frame are kept in time order so
+// that when the first one leaves the frame it can be removed from the combined
+// summary using the first point of the summary after it.
+#[derive(Clone, Debug)]
+pub struct TimeWeightRollingState {
+    summaries: VecDeque<TimeWeightSummaryInternal>,
+    // None exactly when there are no summaries
+    combined: Option<TimeWeightSummaryInternal>,
+}
+
+impl TimeWeightRollingState {
+    fn rebuild_combined(&mut self) {
+        let (front, back) = self.summaries.as_slices();
+        self.combined = match self.summaries.is_empty() {
+            true => None,
+            false => Some(
+                TimeWeightSummaryInternal::combine_sorted_iter(front.iter().chain(back)).unwrap()
+            ),
+        };
+    }
+
+    fn add_summary(&mut self, next: TimeWeightSummaryInternal) {
+        match self.summaries.back() {
+            Some(last) if next.first.ts > last.last.ts => {
+                let combined = self.combined.as_ref().unwrap().combine(&next).unwrap();
+                self.combined = Some(combined);
+                self.summaries.push_back(next);
+            }
+            _ => {
+                // the window isn't ordered by time, keep the summaries sorted
+                // and start over, combining will error if they overlap
+                let idx = self.summaries.partition_point(|s| s.first.ts <= next.first.ts);
+                self.summaries.insert(idx, next);
+                self.rebuild_combined();
+            }
+        }
+    }
+
+    // returns false if the summary isn't in the frame
+    fn remove_summary(&mut self, removed: &TimeWeightSummaryInternal) -> bool {
+        if self.summaries.front() == Some(removed) {
+            self.summaries.pop_front();
+            match self.summaries.front() {
+                Some(next) => {
+                    let combined = self.combined.as_mut().unwrap();
+                    match combined.remove_first_summary(removed, next.first) {
+                        Ok(()) => {}
+                        Err(TimeWeightError::PrecisionLoss) => self.rebuild_combined(),
+                        Err(e) => panic!("{:?}", e),
+                    }
+                }
+                None => self.combined = None,
+            }
+            return true;
+        }
+        match self.summaries.iter().position(|s| s == removed) {
+            None => false,
+            Some(idx) => {
+                self.summaries.remove(idx);
+                self.rebuild_combined();
+                true
+            }
+        }
+    }
+}
+
+#[pg_extern(schema = "toolkit_experimental")]
+pub fn time_weight_rolling_trans<'b>(
+    state: Option<Internal<TimeWeightRollingState>>,
+    next: Option<TimeWeightSummary<'b>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<TimeWeightRollingState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let next = match next {
+                None => return state,
+                Some(next) => next.to_internal(),
+            };
+            let mut state = match state {
+                None => TimeWeightRollingState {
+                    summaries: VecDeque::new(),
+                    combined: None,
+                }
+                .into(),
+                Some(state) => state,
+            };
+            state.add_summary(next);
+            Some(state)
+        })
+    }
+}
+
+#[pg_extern(schema = "toolkit_experimental")]
+pub fn time_weight_rolling_inv<'b>(
+    state: Option<Internal<TimeWeightRollingState>>,
+    removed: Option<TimeWeightSummary<'b>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<TimeWeightRollingState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let removed = match removed {
+                None => return state,
+                Some(removed) => removed.to_internal(),
+            };
+            let mut state = state?;
+            // returning NULL makes postgres recompute the frame from scratch
+            match state.remove_summary(&removed) {
+                true => Some(state),
+                false => None,
             }
         })
     }
 }
 
+#[pg_extern(schema = "toolkit_experimental")]
+fn time_weight_rolling_final(
+    state: Option<Internal<TimeWeightRollingState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<TimeWeightSummary<'static>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            state?.combined.as_ref().map(TimeWeightSummary::from_internal)
+        })
+    }
+}
+
 extension_sql!(
     r#"
 CREATE AGGREGATE time_weight(method text, ts timestamptz, value DOUBLE PRECISION)
@@ -243,6 +674,25 @@ CREATE AGGREGATE time_weight(method text, ts timestamptz, value DOUBLE PRECISION
     combinefunc = time_weight_combine,
     serialfunc = time_weight_trans_serialize,
     deserialfunc = time_weight_trans_deserialize,
+    msfunc = time_weight_moving_trans,
+    minvfunc = time_weight_moving_inv,
+    mstype = internal,
+    mfinalfunc = time_weight_moving_final,
+    parallel = restricted
+);
+
+CREATE AGGREGATE toolkit_experimental.time_weight(method text, ts timestamptz, value DOUBLE PRECISION, max_gap interval)
+(
+    sfunc = toolkit_experimental.time_weight_trans_with_max_gap,
+    stype = internal,
+    finalfunc = time_weight_final,
+    combinefunc = time_weight_combine,
+    serialfunc = time_weight_trans_serialize,
+    deserialfunc = time_weight_trans_deserialize,
+    msfunc = toolkit_experimental.time_weight_moving_trans_with_max_gap,
+    minvfunc = toolkit_experimental.time_weight_moving_inv_with_max_gap,
+    mstype = internal,
+    mfinalfunc = time_weight_moving_final,
     parallel = restricted
 );
 
@@ -256,6 +706,21 @@ CREATE AGGREGATE rollup(tws TimeWeightSummary)
     deserialfunc = time_weight_trans_deserialize,
     parallel = restricted
 );
+
+CREATE AGGREGATE toolkit_experimental.rolling(tws TimeWeightSummary)
+(
+    sfunc = time_weight_summary_trans,
+    stype = internal,
+    finalfunc = time_weight_final,
+    combinefunc = time_weight_combine,
+    serialfunc = time_weight_trans_serialize,
+    deserialfunc = time_weight_trans_deserialize,
+    msfunc = toolkit_experimental.time_weight_rolling_trans,
+    minvfunc = toolkit_experimental.time_weight_rolling_inv,
+    mstype = internal,
+    mfinalfunc = toolkit_experimental.time_weight_rolling_final,
+    parallel = restricted
+);
 "#
 );
 
@@ -280,8 +745,114 @@ pub fn time_weighted_average_average(
     }
 }
 
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe, name = "variance")]
+pub fn time_weighted_average_variance(
+    tws: Option<TimeWeightSummary>,
+    _fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<f64> {
+    match tws?.to_internal().time_weighted_variance() {
+        Ok(v) => Some(v),
+        // like average, this is undefined without a duration, summaries from
+        // before version 3 don't have the second moment it needs either
+        Err(TimeWeightError::ZeroDuration) | Err(TimeWeightError::MissingVariance) => None,
+        Err(e) => Err(e).unwrap(),
+    }
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe, name = "stddev")]
+pub fn time_weighted_average_stddev(
+    tws: Option<TimeWeightSummary>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<f64> {
+    time_weighted_average_variance(tws, fcinfo).map(f64::sqrt)
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe, name = "covered_duration")]
+pub fn time_weighted_average_covered_duration(
+    tws: Option<TimeWeightSummary>,
+    _fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Interval> {
+    tws.map(|tws| interval_from_micros(tws.to_internal().covered_duration()))
+}
+
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe, name = "integral")]
+pub fn time_weighted_average_integral(
+    tws: Option<TimeWeightSummary>,
+    unit: default!(String, "second"),
+    _fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<f64> {
+    let unit = time_unit_micros(&unit);
+    tws.map(|tws| tws.to_internal().time_weighted_integral() / unit)
+}
+
+// `start` and `interval` describe the bucket `tws` summarizes, `prev` and
+// `next` are the summaries for the buckets before and after it, usually found
+// with `lag()` and `lead()`, and are used to extend `tws` to the bucket edges.
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe, name = "interpolated_integral")]
+pub fn time_weighted_average_interpolated_integral(
+    tws: Option<TimeWeightSummary>,
+    start: pg_sys::TimestampTz,
+    interval: Interval,
+    prev: Option<TimeWeightSummary>,
+    next: Option<TimeWeightSummary>,
+    unit: default!(String, "second"),
+    _fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<f64> {
+    let unit = time_unit_micros(&unit);
+    let tws = interpolate(tws, start, interval, prev, next)?;
+    Some(tws.time_weighted_integral() / unit)
+}
+
+// see interpolated_integral for a description of the arguments
+#[pg_extern(schema = "toolkit_experimental", immutable, parallel_safe, name = "interpolated_average")]
+pub fn time_weighted_average_interpolated_average(
+    tws: Option<TimeWeightSummary>,
+    start: pg_sys::TimestampTz,
+    interval: Interval,
+    prev: Option<TimeWeightSummary>,
+    next: Option<TimeWeightSummary>,
+    _fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<f64> {
+    let tws = interpolate(tws, start, interval, prev, next)?;
+    match tws.time_weighted_average() {
+        Ok(a) => Some(a),
+        Err(TimeWeightError::ZeroDuration) => None,
+        Err(e) => Err(e).unwrap(),
+    }
+}
+
+fn interpolate(
+    tws: Option<TimeWeightSummary>,
+    start: pg_sys::TimestampTz,
+    interval: Interval,
+    prev: Option<TimeWeightSummary>,
+    next: Option<TimeWeightSummary>,
+) -> Option<TimeWeightSummaryInternal> {
+    let tws = tws?.to_internal();
+    let interval = interval_to_micros(interval);
+    let prev = prev.map(|p| p.to_internal());
+    let next = next.map(|n| n.to_internal());
+    Some(tws.interpolate(start, interval, prev.as_ref(), next.as_ref()).unwrap())
+}
+
+// the length of a unit of time in microseconds, days and weeks are always 24 and 168 hours
+fn time_unit_micros(unit: &str) -> f64 {
+    // TODO technically not portable to ASCII-compatible charsets
+    match unit.trim().to_lowercase().as_str() {
+        "microsecond" | "microseconds" | "us" => 1.0,
+        "millisecond" | "milliseconds" | "ms" => 1_000.0,
+        "second" | "seconds" | "s" | "sec" | "secs" => 1_000_000.0,
+        "minute" | "minutes" | "min" | "mins" => 60.0 * 1_000_000.0,
+        "hour" | "hours" | "h" | "hr" | "hrs" => 60.0 * 60.0 * 1_000_000.0,
+        "day" | "days" | "d" => 24.0 * 60.0 * 60.0 * 1_000_000.0,
+        "week" | "weeks" | "w" => 7.0 * 24.0 * 60.0 * 60.0 * 1_000_000.0,
+        _ => panic!("unknown time unit '{}'", unit),
+    }
+}
+
 #[cfg(any(test, feature = "pg_test"))]
 mod tests {
+    use approx::assert_relative_eq;
     use pgx::*;
     macro_rules! select_one {
         ($client:expr, $stmt:expr, $type:ty) => {
@@ -307,6 +878,10 @@ mod tests {
             assert_eq!(select_one!(client, stmt, f64), 15.0);
             let stmt = "SELECT average(time_weight('LOCF', ts, val)) FROM test";
             assert_eq!(select_one!(client, stmt, f64), 10.0);
+            let stmt = "SELECT average(time_weight('NOCB', ts, val)) FROM test";
+            assert_eq!(select_one!(client, stmt, f64), 20.0);
+            let stmt = "SELECT average(time_weight('Nearest', ts, val)) FROM test";
+            assert_eq!(select_one!(client, stmt, f64), 15.0);
 
             // more values evenly spaced
             let stmt = "INSERT INTO test VALUES('2020-01-01 00:02:00+00', 10.0), ('2020-01-01 00:03:00+00', 20.0), ('2020-01-01 00:04:00+00', 10.0)";
@@ -351,6 +926,13 @@ mod tests {
             let linear_time_weight = "SELECT time_weight('Linear', ts, val)::TEXT FROM test";
             let locf_time_weight =  "SELECT time_weight('LOCF', ts, val)::TEXT FROM test";
             let avg = |text: &str| format!("SELECT average('{}'::TimeWeightSummary)", text);
+            // new summaries are written in the current version, which adds
+            // trailing fields to the version 1 format
+            let current = |v1: &str, trailing: &str| format!(
+                "{},{}}}",
+                v1.replace("\"version\":1", "\"version\":3").trim_end_matches('}'),
+                trailing,
+            );
 
             // add a couple points
             let stmt = "INSERT INTO test VALUES('2020-01-01 00:00:00+00', 10.0), ('2020-01-01 00:01:00+00', 20.0)";
@@ -364,7 +946,8 @@ mod tests {
                 \"weighted_sum\":900000000.0,\
                 \"method\":\"Linear\"\
             }";
-            assert_eq!(select_one!(client, linear_time_weight, String), expected);
+            let trailing = "\"max_gap\":[0],\"excluded_duration\":[0],\"second_moment\":[500000000.0]";
+            assert_eq!(select_one!(client, linear_time_weight, String), current(expected, trailing));
             assert_eq!(select_one!(client, &*avg(expected), f64), 15.0);
 
             let expected = "{\
@@ -374,7 +957,8 @@ mod tests {
                 \"weighted_sum\":600000000.0,\
                 \"method\":\"LOCF\"\
             }";
-            assert_eq!(select_one!(client, locf_time_weight, String), expected);
+            let trailing = "\"max_gap\":[0],\"excluded_duration\":[0],\"second_moment\":[0.0]";
+            assert_eq!(select_one!(client, locf_time_weight, String), current(expected, trailing));
             assert_eq!(select_one!(client, &*avg(expected), f64), 10.0);
 
             // more values evenly spaced
@@ -388,7 +972,8 @@ mod tests {
                 \"weighted_sum\":3600000000.0,\
                 \"method\":\"Linear\"\
             }";
-            assert_eq!(select_one!(client, linear_time_weight, String), expected);
+            let trailing = "\"max_gap\":[0],\"excluded_duration\":[0],\"second_moment\":[2000000000.0]";
+            assert_eq!(select_one!(client, linear_time_weight, String), current(expected, trailing));
             assert_eq!(select_one!(client, &*avg(expected), f64), 15.0);
             let expected = "{\
                 \"version\":1,\
@@ -397,7 +982,8 @@ mod tests {
                 \"weighted_sum\":3600000000.0,\
                 \"method\":\"LOCF\"\
             }";
-            assert_eq!(select_one!(client, locf_time_weight, String), expected);
+            let trailing = "\"max_gap\":[0],\"excluded_duration\":[0],\"second_moment\":[6000000000.0]";
+            assert_eq!(select_one!(client, locf_time_weight, String), current(expected, trailing));
             assert_eq!(select_one!(client, &*avg(expected), f64), 15.0);
 
             //non-evenly spaced values
@@ -410,7 +996,8 @@ mod tests {
                 \"last\":{\"ts\":\"2020-01-01 00:20:00+00\",\"val\":30.0},\
                 \"weighted_sum\":25500000000.0,\"method\":\"Linear\"\
             }";
-            assert_eq!(select_one!(client, linear_time_weight, String), expected);
+            let trailing = "\"max_gap\":[0],\"excluded_duration\":[0],\"second_moment\":[38125000000.0]";
+            assert_eq!(select_one!(client, linear_time_weight, String), current(expected, trailing));
             assert_eq!(select_one!(client, &*avg(expected), f64), 21.25);
             let expected = "{\
                 \"version\":1,\
@@ -418,8 +1005,234 @@ mod tests {
                 \"last\":{\"ts\":\"2020-01-01 00:20:00+00\",\"val\":30.0},\
                 \"weighted_sum\":21300000000.0,\"method\":\"LOCF\"\
             }";
-            assert_eq!(select_one!(client, locf_time_weight, String), expected);
+            let trailing = "\"max_gap\":[0],\"excluded_duration\":[0],\"second_moment\":[44925000000.0]";
+            assert_eq!(select_one!(client, locf_time_weight, String), current(expected, trailing));
             assert_eq!(select_one!(client, &*avg(expected), f64), 17.75);
         });
     }
+
+    #[pg_test]
+    fn test_time_weight_integral() {
+        Spi::execute(|client| {
+            let stmt = "CREATE TABLE test(ts timestamptz, val DOUBLE PRECISION)";
+            client.select(stmt, None, None);
+            let stmt = "INSERT INTO test VALUES('2020-01-01 00:00:00+00', 10.0), ('2020-01-01 00:30:00+00', 20.0), \
+                ('2020-01-01 01:30:00+00', 30.0), ('2020-01-01 02:00:00+00', 10.0)";
+            client.select(stmt, None, None);
+
+            let stmt = "SELECT toolkit_experimental.integral(time_weight('LOCF', ts, val), 'hour') FROM test";
+            assert_eq!(select_one!(client, stmt, f64), 5.0 + 20.0 + 15.0);
+            let stmt = "SELECT toolkit_experimental.integral(time_weight('Linear', ts, val), 'hours') FROM test";
+            assert_eq!(select_one!(client, stmt, f64), 7.5 + 25.0 + 10.0);
+            let stmt = "SELECT toolkit_experimental.integral(time_weight('LOCF', ts, val)) FROM test";
+            assert_eq!(select_one!(client, stmt, f64), 40.0 * 3600.0);
+            let stmt = "SELECT toolkit_experimental.integral(time_weight('LOCF', ts, val), 'minute') FROM test WHERE val = 10";
+            assert_eq!(select_one!(client, stmt, f64), 10.0 * 120.0);
+
+            // with the edges interpolated the hourly integrals add up to the total
+            let stmt = "SELECT sum(integral) FROM ( \
+                SELECT toolkit_experimental.interpolated_integral( \
+                    tws, bucket, '1 hour', lag(tws) OVER (ORDER BY bucket), lead(tws) OVER (ORDER BY bucket), 'hour') AS integral \
+                FROM (SELECT date_trunc('hour', ts) bucket, time_weight('Linear', ts, val) tws FROM test GROUP BY bucket) s \
+            ) s";
+            assert_eq!(select_one!(client, stmt, f64), 7.5 + 25.0 + 10.0);
+            // without interpolation the time between buckets is lost
+            let stmt = "SELECT sum(toolkit_experimental.integral(tws, 'hour')) \
+                FROM (SELECT date_trunc('hour', ts) bucket, time_weight('Linear', ts, val) tws FROM test GROUP BY bucket) s";
+            assert_eq!(select_one!(client, stmt, f64), 7.5);
+        });
+    }
+
+    #[pg_test]
+    fn test_time_weight_variance() {
+        Spi::execute(|client| {
+            let stmt = "CREATE TABLE test(ts timestamptz, val DOUBLE PRECISION)";
+            client.select(stmt, None, None);
+            let stmt = "INSERT INTO test VALUES('2020-01-01 00:00:00+00', 10.0), ('2020-01-01 00:10:00+00', 30.0), \
+                ('2020-01-01 00:30:00+00', 30.0)";
+            client.select(stmt, None, None);
+
+            // 10 for 10 minutes then 30 for 20 minutes
+            let stmt = "SELECT toolkit_experimental.variance(time_weight('LOCF', ts, val)) FROM test";
+            assert_relative_eq!(select_one!(client, stmt, f64), 24000.0 / 270.0);
+            let stmt = "SELECT toolkit_experimental.stddev(time_weight('LOCF', ts, val)) FROM test";
+            assert_relative_eq!(select_one!(client, stmt, f64), (24000.0f64 / 270.0).sqrt());
+            let stmt = "SELECT toolkit_experimental.variance(rollup(tws)) FROM \
+                (SELECT time_weight('LOCF', ts, val) tws FROM test GROUP BY ts) s";
+            assert_relative_eq!(select_one!(client, stmt, f64), 24000.0 / 270.0);
+
+            // a single point has no variance
+            let stmt = "SELECT toolkit_experimental.variance(time_weight('LOCF', ts, val)) FROM test WHERE val = 10";
+            assert!(client.select(stmt, None, None).first().get_one::<f64>().is_none());
+
+            // nor does a summary from before the second moment was stored
+            let stmt = "SELECT toolkit_experimental.variance('{\
+                \"version\":2,\
+                \"first\":{\"ts\":\"2020-01-01 00:00:00+00\",\"val\":10.0},\
+                \"last\":{\"ts\":\"2020-01-01 00:30:00+00\",\"val\":30.0},\
+                \"weighted_sum\":30000000000.0,\"method\":\"LOCF\",\
+                \"max_gap\":[0],\"excluded_duration\":[0]\
+            }'::TimeWeightSummary)";
+            assert!(client.select(stmt, None, None).first().get_one::<f64>().is_none());
+        });
+    }
+
+    #[pg_test]
+    fn test_time_weight_max_gap() {
+        Spi::execute(|client| {
+            let stmt = "CREATE TABLE test(ts timestamptz, val DOUBLE PRECISION)";
+            client.select(stmt, None, None);
+            // the sensor is offline between 00:10 and 06:10
+            let stmt = "INSERT INTO test VALUES('2020-01-01 00:00:00+00', 10.0), ('2020-01-01 00:10:00+00', 10.0), \
+                ('2020-01-01 06:10:00+00', 40.0), ('2020-01-01 06:20:00+00', 20.0)";
+            client.select(stmt, None, None);
+
+            let stmt = "SELECT average(toolkit_experimental.time_weight('LOCF', ts, val, '1 hour')) FROM test";
+            assert_eq!(select_one!(client, stmt, f64), 25.0);
+            let stmt = "SELECT average(toolkit_experimental.time_weight('Linear', ts, val, '1 hour')) FROM test";
+            assert_eq!(select_one!(client, stmt, f64), 20.0);
+            let stmt = "SELECT toolkit_experimental.covered_duration(toolkit_experimental.time_weight('LOCF', ts, val, '1 hour'))::text FROM test";
+            assert_eq!(select_one!(client, stmt, String), "00:20:00");
+            let stmt = "SELECT toolkit_experimental.covered_duration(time_weight('LOCF', ts, val))::text FROM test";
+            assert_eq!(select_one!(client, stmt, String), "06:20:00");
+
+            // the gap is excluded when it falls between summaries as well
+            let stmt = "SELECT average(rollup(tws)) FROM \
+                (SELECT toolkit_experimental.time_weight('LOCF', ts, val, '1 hour') tws FROM test GROUP BY date_trunc('hour', ts)) s";
+            assert_eq!(select_one!(client, stmt, f64), 25.0);
+
+            let stmt = "SELECT toolkit_experimental.time_weight('LOCF', ts, val, '1 hour')::text FROM test";
+            let text = select_one!(client, stmt, String);
+            assert!(text.contains("\"max_gap\":[3600000000],\"excluded_duration\":[21600000000]"), "{}", text);
+        });
+    }
+
+    #[pg_test]
+    fn test_time_weight_moving() {
+        Spi::execute(|client| {
+            let stmt = "CREATE TABLE test(ts timestamptz, val DOUBLE PRECISION)";
+            client.select(stmt, None, None);
+            let stmt = "INSERT INTO test VALUES('2020-01-01 00:00:00+00', 10.0), ('2020-01-01 00:10:00+00', 20.0), \
+                ('2020-01-01 00:20:00+00', 20.0), ('2020-01-01 00:30:00+00', 50.0), ('2020-01-01 00:40:00+00', 10.0)";
+            client.select(stmt, None, None);
+            let windows = |order: &str| format!(
+                "SELECT string_agg(coalesce(average::text, 'null'), ',' ORDER BY ts) FROM ( \
+                    SELECT ts, average(time_weight('Linear', ts, val) OVER (ORDER BY ts {} ROWS 2 PRECEDING)) \
+                    FROM test \
+                ) s",
+                order,
+            );
+
+            // each frame drops the earliest point of the previous one
+            let stmt = windows("ASC");
+            assert_eq!(select_one!(client, &stmt, String), "null,15,17.5,27.5,32.5");
+
+            // frames that aren't in time order still work
+            let stmt = windows("DESC");
+            assert_eq!(select_one!(client, &stmt, String), "17.5,27.5,32.5,30,null");
+        });
+    }
+
+    #[pg_test]
+    fn test_time_weight_moving_long_frame() {
+        Spi::execute(|client| {
+            let stmt = "CREATE TABLE test(ts timestamptz, val DOUBLE PRECISION)";
+            client.select(stmt, None, None);
+            // the spikes dwarf the rest of the frame, removing them has to
+            // recompute the summary rather than subtract
+            let stmt = "INSERT INTO test \
+                SELECT '2020-01-01'::timestamptz + i * '1 minute'::interval, \
+                    CASE WHEN i % 300 = 100 THEN 1e12 ELSE (i % 7) * 0.1 END \
+                FROM generate_series(0, 999) i";
+            client.select(stmt, None, None);
+            for method in &["LOCF", "Linear"] {
+                let stmt = format!(
+                    "SELECT count(*) FROM ( \
+                        SELECT \
+                            average(moving) AS moving_average, \
+                            average(frame) AS average, \
+                            toolkit_experimental.variance(moving) AS moving_variance, \
+                            toolkit_experimental.variance(frame) AS variance \
+                        FROM ( \
+                            SELECT ts, time_weight('{0}', ts, val) OVER (ORDER BY ts ROWS 49 PRECEDING) AS moving \
+                            FROM test \
+                        ) m, LATERAL ( \
+                            SELECT time_weight('{0}', t.ts, t.val) AS frame \
+                            FROM test t WHERE t.ts BETWEEN m.ts - '49 minutes'::interval AND m.ts \
+                        ) f \
+                    ) s \
+                    WHERE abs(moving_average - average) > 1e-9 * greatest(1, abs(average)) \
+                        OR abs(moving_variance - variance) > 1e-9 * greatest(1, variance)",
+                    method,
+                );
+                assert_eq!(select_one!(client, &stmt, i64), 0);
+            }
+        });
+    }
+
+    #[pg_test]
+    fn test_time_weight_rolling() {
+        Spi::execute(|client| {
+            let stmt = "CREATE TABLE test(ts timestamptz, val DOUBLE PRECISION)";
+            client.select(stmt, None, None);
+            let stmt = "INSERT INTO test VALUES('2020-01-01 00:00:00+00', 10.0), ('2020-01-01 00:05:00+00', 10.0), \
+                ('2020-01-01 00:10:00+00', 40.0), ('2020-01-01 00:15:00+00', 40.0), \
+                ('2020-01-01 00:20:00+00', 10.0), ('2020-01-01 00:25:00+00', 10.0)";
+            client.select(stmt, None, None);
+            let windows = |order: &str| format!(
+                "SELECT string_agg(average::text, ',' ORDER BY bucket) FROM ( \
+                    SELECT bucket, average(toolkit_experimental.rolling(tws) OVER (ORDER BY bucket {} ROWS 1 PRECEDING)) \
+                    FROM ( \
+                        SELECT date_trunc('hour', ts) + (date_part('minute', ts)::int / 10) * '10 min'::interval AS bucket, \
+                            time_weight('LOCF', ts, val) AS tws \
+                        FROM test GROUP BY bucket \
+                    ) summaries \
+                ) s",
+                order,
+            );
+
+            // each frame drops the earliest summary of the previous one
+            let stmt = windows("ASC");
+            assert_eq!(select_one!(client, &stmt, String), "10,20,30");
+
+            // frames that aren't in time order still work
+            let stmt = windows("DESC");
+            assert_eq!(select_one!(client, &stmt, String), "20,30,10");
+        });
+    }
+
+    #[pg_test]
+    fn test_time_weight_interpolated_average() {
+        Spi::execute(|client| {
+            let stmt = "CREATE TABLE test(ts timestamptz, val DOUBLE PRECISION)";
+            client.select(stmt, None, None);
+            let stmt = "INSERT INTO test VALUES('2020-01-01 00:00:00+00', 10.0), ('2020-01-01 00:30:00+00', 20.0), \
+                ('2020-01-01 01:30:00+00', 30.0), ('2020-01-01 02:00:00+00', 10.0)";
+            client.select(stmt, None, None);
+            let buckets = |method: &str| format!(
+                "SELECT bucket, toolkit_experimental.interpolated_average( \
+                    tws, bucket, '1 hour', lag(tws) OVER (ORDER BY bucket), lead(tws) OVER (ORDER BY bucket)) AS average \
+                FROM (SELECT date_trunc('hour', ts) bucket, time_weight('{}', ts, val) tws FROM test GROUP BY bucket) s",
+                method,
+            );
+
+            // the first bucket is extended to 01:00 where the value is 25, the
+            // second starts at 25 and ends at 10 at 02:00, and the last bucket
+            // only has a single point
+            let stmt = format!("SELECT average FROM ({}) s ORDER BY bucket LIMIT 1", buckets("Linear"));
+            assert_eq!(select_one!(client, &stmt, f64), 18.75);
+            let stmt = format!("SELECT average FROM ({}) s ORDER BY bucket OFFSET 1 LIMIT 1", buckets("Linear"));
+            assert_eq!(select_one!(client, &stmt, f64), 23.75);
+            let stmt = format!("SELECT average FROM ({}) s ORDER BY bucket OFFSET 2 LIMIT 1", buckets("Linear"));
+            assert!(client.select(&stmt, None, None).first().get_one::<f64>().is_none());
+
+            // LOCF always carries the last value to the end of the bucket
+            let stmt = format!("SELECT average FROM ({}) s ORDER BY bucket LIMIT 1", buckets("LOCF"));
+            assert_eq!(select_one!(client, &stmt, f64), 15.0);
+            let stmt = format!("SELECT average FROM ({}) s ORDER BY bucket OFFSET 1 LIMIT 1", buckets("LOCF"));
+            assert_eq!(select_one!(client, &stmt, f64), 25.0);
+            let stmt = format!("SELECT average FROM ({}) s ORDER BY bucket OFFSET 2 LIMIT 1", buckets("LOCF"));
+            assert_eq!(select_one!(client, &stmt, f64), 10.0);
+        });
+    }
 }
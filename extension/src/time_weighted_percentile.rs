@@ -0,0 +1,635 @@
+use serde::{Deserialize, Serialize};
+use std::slice;
+
+use pgx::*;
+
+use flat_serialize::*;
+
+use time_series::TSPoint;
+use time_weighted_average::TimeWeightMethod;
+use uddsketch::{SketchHashKey, UDDSketch as UddSketchInternal};
+
+use crate::{
+    aggregate_utils::in_aggregate_context,
+    flatten,
+    palloc::Internal,
+    pg_type,
+    time_weighted_average::parse_method,
+    uddsketch::{compress_buckets, decompress_counts, decompress_keys, CompressedBuckets, UddSketch},
+};
+
+#[allow(non_camel_case_types)]
+type bytea = pg_sys::Datum;
+
+// use the same parameters as percentile_agg so the results can be rolled up
+// with sketches it created
+const DEFAULT_SIZE: u64 = 200;
+const DEFAULT_MAX_ERROR: f64 = 0.001;
+
+// the number of pieces a linearly interpolated segment is split into, each
+// piece is added to the sketch at its midpoint
+const LINEAR_PIECES: u64 = 100;
+
+// Adds the values taken over the segment between two points to the sketch,
+// weighted by how long they were taken for in microseconds.
+fn add_segment(
+    sketch: &mut UddSketchInternal,
+    method: TimeWeightMethod,
+    first: TSPoint,
+    second: TSPoint,
+) {
+    let duration = second.ts - first.ts;
+    if duration <= 0 {
+        return;
+    }
+    let duration = duration as u64;
+    match method {
+        TimeWeightMethod::LOCF => sketch.add_value_with_count(first.val, duration),
+        TimeWeightMethod::NOCB => sketch.add_value_with_count(second.val, duration),
+        TimeWeightMethod::Nearest => {
+            // ties go to the earlier point, as in TimeWeightSummary
+            let half = duration - duration / 2;
+            sketch.add_value_with_count(first.val, half);
+            sketch.add_value_with_count(second.val, duration - half);
+        }
+        TimeWeightMethod::Linear => {
+            if first.val == second.val {
+                sketch.add_value_with_count(first.val, duration);
+                return;
+            }
+            let pieces = LINEAR_PIECES.min(duration);
+            let piece_end = |i: u64| (duration as u128 * i as u128 / pieces as u128) as u64;
+            for i in 0..pieces {
+                let fraction = (i as f64 + 0.5) / pieces as f64;
+                let value = first.val + (second.val - first.val) * fraction;
+                sketch.add_value_with_count(value, piece_end(i + 1) - piece_end(i));
+            }
+        }
+    }
+}
+
+// The sketch of a sorted run of points, along with the points at either end so
+// that the segment between it and the next run can be added when combining.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct SketchRun {
+    first: TSPoint,
+    last: TSPoint,
+    sketch: UddSketchInternal,
+}
+
+impl SketchRun {
+    fn new_from_sorted(points: &[TSPoint], method: TimeWeightMethod) -> SketchRun {
+        let mut sketch = UddSketchInternal::new(DEFAULT_SIZE, DEFAULT_MAX_ERROR);
+        for segment in points.windows(2) {
+            add_segment(&mut sketch, method, segment[0], segment[1]);
+        }
+        SketchRun {
+            first: points[0],
+            last: *points.last().unwrap(),
+            sketch,
+        }
+    }
+
+    fn sketch(&self) -> Option<&UddSketchInternal> {
+        // a single point covers no time, so there is nothing in the sketch
+        (self.sketch.count() > 0).then(|| &self.sketch)
+    }
+
+    // `next` must start no earlier than `self` ends
+    fn combine(&mut self, next: &SketchRun, method: TimeWeightMethod) {
+        if next.first.ts < self.last.ts {
+            panic!("cannot combine time weighted percentiles over overlapping periods")
+        }
+        add_segment(&mut self.sketch, method, self.last, next.first);
+        self.sketch.merge_sketch(&next.sketch);
+        self.last = next.last;
+    }
+}
+
+// The sketch along with the first and last points and the method, so that
+// rollup() can add the segments between summaries, as for TimeWeightSummary.
+// The sketch is stored the same way as in UddSketch.
+pg_type! {
+    #[derive(Debug)]
+    struct TimeWeightedPercentileSummary<'input> {
+        first: TSPoint,
+        last: TSPoint,
+        alpha: f64,
+        max_buckets: u32,
+        num_buckets: u32,
+        compactions: u64,
+        count: u64,
+        sum: f64,
+        zero_bucket_count: u64,
+        neg_indexes_bytes: u32,
+        neg_buckets_bytes: u32,
+        pos_indexes_bytes: u32,
+        pos_buckets_bytes: u32,
+        method: TimeWeightMethod,
+        negative_indexes: [u8; self.neg_indexes_bytes],
+        negative_counts: [u8; self.neg_buckets_bytes],
+        positive_indexes: [u8; self.pos_indexes_bytes],
+        positive_counts: [u8; self.pos_buckets_bytes],
+    }
+}
+
+// hack to allow us to qualify names with "toolkit_experimental"
+// so that pgx generates the correct SQL
+mod toolkit_experimental {
+    pub(crate) use super::*;
+
+    varlena_type!(TimeWeightedPercentileSummary);
+}
+
+#[derive(Serialize, Deserialize)]
+struct ReadableTimeWeightedPercentileSummary {
+    version: u8,
+    method: TimeWeightMethod,
+    first: TSPoint,
+    last: TSPoint,
+    alpha: f64,
+    max_buckets: u32,
+    compactions: u64,
+    count: u64,
+    sum: f64,
+    buckets: Vec<(SketchHashKey, u64)>,
+}
+
+impl<'input> InOutFuncs for TimeWeightedPercentileSummary<'input> {
+    fn output(&self, buffer: &mut StringInfo) {
+        use crate::serialization::{EncodedStr::*, str_to_db_encoding};
+
+        let readable = ReadableTimeWeightedPercentileSummary {
+            version: self.version,
+            method: self.method,
+            first: self.first,
+            last: self.last,
+            alpha: self.alpha,
+            max_buckets: self.max_buckets,
+            compactions: self.compactions,
+            count: self.count,
+            sum: self.sum,
+            buckets: self.keys().zip(self.counts()).collect(),
+        };
+        let stringified = serde_json::to_string(&readable).unwrap();
+        match str_to_db_encoding(&stringified) {
+            Utf8(s) => buffer.push_str(s),
+            Other(s) => buffer.push_bytes(s.to_bytes()),
+        }
+    }
+
+    fn input(input: &std::ffi::CStr) -> Self
+    where
+        Self: Sized,
+    {
+        use crate::serialization::str_from_db_encoding;
+
+        let utf8_str = str_from_db_encoding(input);
+        let val: ReadableTimeWeightedPercentileSummary = serde_json::from_str(utf8_str).unwrap();
+        assert_eq!(val.version, 1);
+        let run = SketchRun {
+            first: val.first,
+            last: val.last,
+            sketch: sketch_from_data(
+                val.max_buckets,
+                val.alpha,
+                val.compactions,
+                val.count,
+                val.sum,
+                val.buckets.iter().map(|b| b.0),
+                val.buckets.iter().map(|b| b.1),
+            ),
+        };
+        TimeWeightedPercentileSummary::from_internal(&run, val.method)
+    }
+}
+
+fn sketch_from_data(
+    max_buckets: u32,
+    alpha: f64,
+    compactions: u64,
+    count: u64,
+    sum: f64,
+    keys: impl Iterator<Item = SketchHashKey>,
+    counts: impl Iterator<Item = u64>,
+) -> UddSketchInternal {
+    // new_from_data() needs at least one bucket, a sketch of a single point
+    // has none
+    if count == 0 {
+        return UddSketchInternal::new(max_buckets as u64, alpha);
+    }
+    UddSketchInternal::new_from_data(max_buckets as u64, alpha, compactions, count, sum, keys, counts)
+}
+
+impl<'input> TimeWeightedPercentileSummary<'input> {
+    fn keys(&self) -> impl Iterator<Item = SketchHashKey> + '_ {
+        decompress_keys(self.negative_indexes, self.zero_bucket_count != 0, self.positive_indexes)
+    }
+
+    fn counts(&self) -> impl Iterator<Item = u64> + '_ {
+        decompress_counts(self.negative_counts, self.zero_bucket_count, self.positive_counts)
+    }
+
+    fn to_internal(&self) -> SketchRun {
+        SketchRun {
+            first: self.first,
+            last: self.last,
+            sketch: sketch_from_data(
+                self.max_buckets,
+                self.alpha,
+                self.compactions,
+                self.count,
+                self.sum,
+                self.keys(),
+                self.counts(),
+            ),
+        }
+    }
+
+    fn from_internal(
+        run: &SketchRun,
+        method: TimeWeightMethod,
+    ) -> TimeWeightedPercentileSummary<'static> {
+        let CompressedBuckets {
+            negative_indexes,
+            negative_counts,
+            zero_bucket_count,
+            positive_indexes,
+            positive_counts,
+        } = compress_buckets(run.sketch.bucket_iter());
+
+        unsafe {
+            flatten!(TimeWeightedPercentileSummary {
+                first: run.first,
+                last: run.last,
+                alpha: run.sketch.max_error(),
+                max_buckets: run.sketch.max_allowed_buckets() as u32,
+                num_buckets: run.sketch.current_buckets_count() as u32,
+                compactions: run.sketch.times_compacted() as u64,
+                count: run.sketch.count(),
+                sum: run.sketch.sum(),
+                zero_bucket_count: zero_bucket_count,
+                neg_indexes_bytes: negative_indexes.len() as u32,
+                neg_buckets_bytes: negative_counts.len() as u32,
+                pos_indexes_bytes: positive_indexes.len() as u32,
+                pos_buckets_bytes: positive_counts.len() as u32,
+                method: method,
+                negative_indexes: &negative_indexes,
+                negative_counts: &negative_counts,
+                positive_indexes: &positive_indexes,
+                positive_counts: &positive_counts,
+            })
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TimeWeightedPercentileTransState {
+    #[serde(skip)]
+    point_buffer: Vec<TSPoint>,
+    method: TimeWeightMethod,
+    run_buffer: Vec<SketchRun>,
+}
+
+impl TimeWeightedPercentileTransState {
+    fn combine_points(&mut self) {
+        if self.point_buffer.is_empty() {
+            return;
+        }
+        // if two points have the same time we only use the first we see, as
+        // TimeWeightSummary does, so the sort must be stable
+        self.point_buffer.sort_by_key(|p| p.ts);
+        self.point_buffer.dedup_by_key(|p| p.ts);
+        self.run_buffer.push(SketchRun::new_from_sorted(&self.point_buffer, self.method));
+        self.point_buffer.clear();
+    }
+
+    fn push_runs(&mut self, other: TimeWeightedPercentileTransState) {
+        if self.method != other.method {
+            panic!("cannot combine time weighted percentiles with different methods")
+        }
+        self.run_buffer.extend(other.run_buffer);
+    }
+
+    fn combine_runs(&mut self) {
+        self.combine_points();
+        if self.run_buffer.len() <= 1 {
+            return;
+        }
+        self.run_buffer.sort_unstable_by_key(|r| r.first.ts);
+        let mut runs = std::mem::take(&mut self.run_buffer).into_iter();
+        let mut combined = runs.next().unwrap();
+        for run in runs {
+            combined.combine(&run, self.method);
+        }
+        self.run_buffer = vec![combined];
+    }
+}
+
+#[pg_extern(schema = "toolkit_experimental")]
+pub fn time_weighted_percentile_trans_serialize(
+    mut state: Internal<TimeWeightedPercentileTransState>,
+) -> bytea {
+    state.combine_runs();
+    crate::do_serialize!(state)
+}
+
+#[pg_extern(schema = "toolkit_experimental", strict)]
+pub fn time_weighted_percentile_trans_deserialize(
+    bytes: bytea,
+    _internal: Option<Internal<()>>,
+) -> Internal<TimeWeightedPercentileTransState> {
+    crate::do_deserialize!(bytes, TimeWeightedPercentileTransState)
+}
+
+#[pg_extern(schema = "toolkit_experimental")]
+pub fn time_weighted_percentile_trans(
+    state: Option<Internal<TimeWeightedPercentileTransState>>,
+    method: String,
+    ts: Option<pg_sys::TimestampTz>,
+    val: Option<f64>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<TimeWeightedPercentileTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let p = match (ts, val) {
+                (Some(ts), Some(val)) => TSPoint { ts, val },
+                _ => return state,
+            };
+            let mut state = match state {
+                None => TimeWeightedPercentileTransState {
+                    point_buffer: vec![],
+                    method: parse_method(&method),
+                    run_buffer: vec![],
+                }
+                .into(),
+                Some(state) => state,
+            };
+            state.point_buffer.push(p);
+            Some(state)
+        })
+    }
+}
+
+#[pg_extern(schema = "toolkit_experimental")]
+pub fn time_weighted_percentile_combine(
+    state1: Option<Internal<TimeWeightedPercentileTransState>>,
+    state2: Option<Internal<TimeWeightedPercentileTransState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<TimeWeightedPercentileTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            match (state1, state2) {
+                (None, None) => None,
+                (None, Some(state2)) => {let mut s = state2.clone(); s.combine_points(); Some(s.into())},
+                (Some(state1), None) => {let mut s = state1.clone(); s.combine_points(); Some(s.into())},
+                (Some(state1), Some(state2)) => {
+                    let mut s1 = state1.clone();
+                    s1.combine_points();
+                    let mut s2 = state2.clone();
+                    s2.combine_points();
+                    s2.push_runs(s1);
+                    Some(s2.into())
+                }
+            }
+        })
+    }
+}
+
+#[pg_extern(schema = "toolkit_experimental")]
+pub fn time_weighted_percentile_summary_trans(
+    state: Option<Internal<TimeWeightedPercentileTransState>>,
+    value: Option<toolkit_experimental::TimeWeightedPercentileSummary>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<TimeWeightedPercentileTransState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let value = match value {
+                None => return state,
+                Some(value) => value,
+            };
+            let mut state = match state {
+                None => TimeWeightedPercentileTransState {
+                    point_buffer: vec![],
+                    method: value.method,
+                    run_buffer: vec![],
+                }
+                .into(),
+                Some(state) => state,
+            };
+            if state.method != value.method {
+                panic!("cannot combine time weighted percentiles with different methods")
+            }
+            state.run_buffer.push(value.to_internal());
+            Some(state)
+        })
+    }
+}
+
+#[pg_extern(schema = "toolkit_experimental")]
+fn time_weighted_percentile_final(
+    state: Option<Internal<TimeWeightedPercentileTransState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<toolkit_experimental::TimeWeightedPercentileSummary<'static>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let mut state = match state {
+                None => return None,
+                Some(state) => state.clone(),
+            };
+            state.combine_runs();
+            debug_assert!(state.run_buffer.len() <= 1);
+            state.run_buffer.pop()
+                .map(|run| TimeWeightedPercentileSummary::from_internal(&run, state.method))
+        })
+    }
+}
+
+extension_sql!(r#"
+CREATE AGGREGATE toolkit_experimental.time_weighted_percentile_agg(method text, ts timestamptz, value DOUBLE PRECISION)
+(
+    sfunc = toolkit_experimental.time_weighted_percentile_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.time_weighted_percentile_final,
+    combinefunc = toolkit_experimental.time_weighted_percentile_combine,
+    serialfunc = toolkit_experimental.time_weighted_percentile_trans_serialize,
+    deserialfunc = toolkit_experimental.time_weighted_percentile_trans_deserialize,
+    parallel = restricted
+);
+"#);
+
+extension_sql!(r#"
+CREATE AGGREGATE toolkit_experimental.rollup(summary toolkit_experimental.TimeWeightedPercentileSummary)
+(
+    sfunc = toolkit_experimental.time_weighted_percentile_summary_trans,
+    stype = internal,
+    finalfunc = toolkit_experimental.time_weighted_percentile_final,
+    combinefunc = toolkit_experimental.time_weighted_percentile_combine,
+    serialfunc = toolkit_experimental.time_weighted_percentile_trans_serialize,
+    deserialfunc = toolkit_experimental.time_weighted_percentile_trans_deserialize,
+    parallel = restricted
+);
+"#);
+
+// The accessors are NULL for a summary of a single point, which covers no time
+
+#[pg_extern(name="approx_percentile", schema = "toolkit_experimental", strict, immutable, parallel_safe)]
+fn time_weighted_percentile_approx_percentile(
+    percentile: f64,
+    summary: toolkit_experimental::TimeWeightedPercentileSummary,
+    _fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<f64> {
+    summary.to_internal().sketch().map(|sketch| sketch.estimate_quantile(percentile))
+}
+
+#[pg_extern(name="approx_percentile_rank", schema = "toolkit_experimental", strict, immutable, parallel_safe)]
+fn time_weighted_percentile_approx_percentile_rank(
+    value: f64,
+    summary: toolkit_experimental::TimeWeightedPercentileSummary,
+    _fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<f64> {
+    summary.to_internal().sketch().map(|sketch| sketch.estimate_quantile_at_value(value))
+}
+
+// The duration covered by the summary, in microseconds.
+#[pg_extern(name="num_vals", schema = "toolkit_experimental", strict, immutable, parallel_safe)]
+fn time_weighted_percentile_num_vals(
+    summary: toolkit_experimental::TimeWeightedPercentileSummary,
+    _fcinfo: pg_sys::FunctionCallInfo,
+) -> f64 {
+    summary.count as f64
+}
+
+// The time weighted average.
+#[pg_extern(name="mean", schema = "toolkit_experimental", strict, immutable, parallel_safe)]
+fn time_weighted_percentile_mean(
+    summary: toolkit_experimental::TimeWeightedPercentileSummary,
+    _fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<f64> {
+    summary.to_internal().sketch().map(UddSketchInternal::mean)
+}
+
+// The sketch without the points needed for rollup(), for use with the other
+// UddSketch functions, or to combine with percentile_agg() sketches.
+#[pg_extern(name="to_uddsketch", schema = "toolkit_experimental", strict, immutable, parallel_safe)]
+fn time_weighted_percentile_to_uddsketch(
+    summary: toolkit_experimental::TimeWeightedPercentileSummary,
+    _fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<UddSketch<'static>> {
+    summary.to_internal().sketch().map(UddSketch::from_internal)
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+mod tests {
+
+    use approx::assert_relative_eq;
+    use pgx::*;
+
+    macro_rules! select_one {
+        ($client:expr, $stmt:expr, $type:ty) => {
+            $client
+                .select($stmt, None, None)
+                .first()
+                .get_one::<$type>()
+                .unwrap()
+        };
+    }
+
+    #[pg_test]
+    fn test_time_weighted_percentile_agg() {
+        Spi::execute(|client| {
+            client.select("CREATE TABLE test(ts timestamptz, val DOUBLE PRECISION)", None, None);
+            // set search_path after defining our table so we don't pollute the wrong schema
+            let stmt = "SELECT format('toolkit_experimental, %s',current_setting('search_path'))";
+            let search_path = select_one!(client, stmt, String);
+            client.select(&format!("SET LOCAL search_path TO {}", search_path), None, None);
+            // 10 for 1 minute then 20 for 3 minutes under LOCF
+            let stmt = "INSERT INTO test VALUES \
+                ('2020-01-01 00:00:00+00', 10.0), ('2020-01-01 00:01:00+00', 20.0), \
+                ('2020-01-01 00:04:00+00', 30.0)";
+            client.select(stmt, None, None);
+
+            let stmt = "SELECT approx_percentile(0.2, time_weighted_percentile_agg('LOCF', ts, val)) FROM test";
+            assert_relative_eq!(select_one!(client, stmt, f64), 10.0, max_relative = 0.001);
+            let stmt = "SELECT approx_percentile(0.3, time_weighted_percentile_agg('LOCF', ts, val)) FROM test";
+            assert_relative_eq!(select_one!(client, stmt, f64), 20.0, max_relative = 0.001);
+            // the values are weighted by microseconds
+            let stmt = "SELECT num_vals(time_weighted_percentile_agg('LOCF', ts, val)) FROM test";
+            assert_relative_eq!(select_one!(client, stmt, f64), 240_000_000.0);
+            let stmt = "SELECT mean(time_weighted_percentile_agg('LOCF', ts, val)) FROM test";
+            assert_relative_eq!(select_one!(client, stmt, f64), 17.5, max_relative = 0.001);
+
+            // values rise from 10 to 20 over the first minute and 20 to 30 over the next 3
+            let stmt = "SELECT approx_percentile(0.125, time_weighted_percentile_agg('Linear', ts, val)) FROM test";
+            assert_relative_eq!(select_one!(client, stmt, f64), 15.0, max_relative = 0.01);
+            let stmt = "SELECT approx_percentile(0.625, time_weighted_percentile_agg('Linear', ts, val)) FROM test";
+            assert_relative_eq!(select_one!(client, stmt, f64), 25.0, max_relative = 0.01);
+
+            // rollup adds the segments between the summaries, so it gives the
+            // same result as aggregating all the points at once, even when
+            // each summary only has a single point
+            for method in &["LOCF", "Linear"] {
+                for grouping in &["ts < '2020-01-01 00:01:00+00'", "ts"] {
+                    let compare = |accessor: &str| {
+                        let whole = format!(
+                            "SELECT {} FROM (SELECT time_weighted_percentile_agg('{}', ts, val) agg FROM test) s",
+                            accessor, method,
+                        );
+                        let rolled_up = format!(
+                            "SELECT {} FROM (SELECT rollup(agg) agg FROM \
+                                (SELECT time_weighted_percentile_agg('{}', ts, val) agg FROM test GROUP BY {}) b) s",
+                            accessor, method, grouping,
+                        );
+                        let expected = select_one!(client, &*whole, f64);
+                        let actual = select_one!(client, &*rolled_up, f64);
+                        assert_relative_eq!(actual, expected, max_relative = 1e-12);
+                    };
+                    compare("num_vals(agg)");
+                    compare("mean(agg)");
+                    compare("approx_percentile(0.1, agg)");
+                    compare("approx_percentile(0.5, agg)");
+                    compare("approx_percentile(0.9, agg)");
+                    compare("approx_percentile_rank(22.0, agg)");
+                }
+            }
+            let stmt = "SELECT num_vals(rollup(agg)) FROM ( \
+                    SELECT time_weighted_percentile_agg('LOCF', ts, val) AS agg FROM test \
+                    GROUP BY ts < '2020-01-01 00:01:00+00' \
+                ) s";
+            assert_relative_eq!(select_one!(client, stmt, f64), 240_000_000.0);
+
+            // the sketch can be used with the UddSketch functions
+            let stmt = "SELECT num_vals(to_uddsketch(time_weighted_percentile_agg('LOCF', ts, val))) FROM test";
+            assert_relative_eq!(select_one!(client, stmt, f64), 240_000_000.0);
+
+            // a single point covers no time
+            let stmt = "SELECT approx_percentile(0.5, time_weighted_percentile_agg('LOCF', ts, val)) IS NULL FROM test WHERE val = 10.0";
+            assert!(select_one!(client, stmt, bool));
+            let stmt = "SELECT num_vals(time_weighted_percentile_agg('LOCF', ts, val)) FROM test WHERE val = 10.0";
+            assert_eq!(select_one!(client, stmt, f64), 0.0);
+        });
+    }
+
+    #[pg_test]
+    fn test_time_weighted_percentile_duplicate_time() {
+        Spi::execute(|client| {
+            client.select("CREATE TABLE test(ts timestamptz, val DOUBLE PRECISION)", None, None);
+            // set search_path after defining our table so we don't pollute the wrong schema
+            let stmt = "SELECT format('toolkit_experimental, %s',current_setting('search_path'))";
+            let search_path = select_one!(client, stmt, String);
+            client.select(&format!("SET LOCAL search_path TO {}", search_path), None, None);
+            // the second point at 00:01 is ignored
+            let stmt = "INSERT INTO test VALUES \
+                ('2020-01-01 00:00:00+00', 10.0), ('2020-01-01 00:01:00+00', 20.0), \
+                ('2020-01-01 00:01:00+00', 50.0), ('2020-01-01 00:04:00+00', 30.0)";
+            client.select(stmt, None, None);
+
+            let stmt = "SELECT approx_percentile(0.9, time_weighted_percentile_agg('LOCF', ts, val)) FROM test";
+            assert_relative_eq!(select_one!(client, stmt, f64), 20.0, max_relative = 0.001);
+            let stmt = "SELECT mean(time_weighted_percentile_agg('LOCF', ts, val)) FROM test";
+            assert_relative_eq!(select_one!(client, stmt, f64), 17.5, max_relative = 0.001);
+            let stmt = "SELECT average(time_weight('LOCF', ts, val)) FROM test";
+            assert_relative_eq!(select_one!(client, stmt, f64), 17.5);
+        });
+    }
+}
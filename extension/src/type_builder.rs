@@ -233,11 +233,16 @@ macro_rules! json_inout_funcs {
 #[macro_export]
 macro_rules! flatten {
     ($typ:ident { $($field:ident: $value:expr),* $(,)? }) => {
+        $crate::flatten!($typ version 1 { $($field: $value),* })
+    };
+    // types whose layout has changed since they were first released record
+    // which version of the layout they use
+    ($typ:ident version $version:tt { $($field:ident: $value:expr),* $(,)? }) => {
         {
             let data = ::paste::paste! {
                 [<$typ Data>] {
                     header: 0,
-                    version: 1,
+                    version: $version,
                     padding: [0; 3],
                     $(
                         $field: $value
@@ -1,4 +1,5 @@
 
+use std::collections::VecDeque;
 use std::slice;
 
 use pgx::*;
@@ -266,32 +267,19 @@ impl<'input> UddSketch<'input> {
     fn to_uddsketch(&self) -> UddSketchInternal {
         UddSketchInternal::new_from_data(self.max_buckets as u64, self.alpha, self.compactions, self.count, self.sum, self.keys(), self.counts())
     }
-}
-
-// PG function to generate a user-facing UddSketch object from a UddSketchInternal.
-#[pg_extern()]
-fn uddsketch_final(
-    state: Option<Internal<UddSketchInternal>>,
-    fcinfo: pg_sys::FunctionCallInfo,
-) -> Option<UddSketch<'static>> {
-    unsafe {
-        in_aggregate_context(fcinfo, || {
-            let state = match state {
-                None => return None,
-                Some(state) => state,
-            };
-
-            let CompressedBuckets {
-                negative_indexes,
-                negative_counts,
-                zero_bucket_count,
-                positive_indexes,
-                positive_counts,
-            } = compress_buckets(state.bucket_iter());
 
+    pub(crate) fn from_internal(state: &UddSketchInternal) -> UddSketch<'static> {
+        let CompressedBuckets {
+            negative_indexes,
+            negative_counts,
+            zero_bucket_count,
+            positive_indexes,
+            positive_counts,
+        } = compress_buckets(state.bucket_iter());
 
-            // we need to flatten the vector to a single buffer that contains
-            // both the size, the data, and the varlen header
+        // we need to flatten the vector to a single buffer that contains
+        // both the size, the data, and the varlen header
+        unsafe {
             flatten!(
                 UddSketch {
                     alpha: state.max_error(),
@@ -310,21 +298,39 @@ fn uddsketch_final(
                     positive_indexes: &positive_indexes,
                     positive_counts: &positive_counts,
                 }
-            ).into()
+            )
+        }
+    }
+}
+
+// PG function to generate a user-facing UddSketch object from a UddSketchInternal.
+#[pg_extern()]
+fn uddsketch_final(
+    state: Option<Internal<UddSketchInternal>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<UddSketch<'static>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let state = match state {
+                None => return None,
+                Some(state) => state,
+            };
+
+            UddSketch::from_internal(&state).into()
         })
     }
 }
 
 #[derive(serde::Serialize, serde::Deserialize)]
-struct CompressedBuckets {
-    negative_indexes: Vec<u8>,
-    negative_counts: Vec<u8>,
-    zero_bucket_count: u64,
-    positive_indexes: Vec<u8>,
-    positive_counts: Vec<u8>,
+pub(crate) struct CompressedBuckets {
+    pub(crate) negative_indexes: Vec<u8>,
+    pub(crate) negative_counts: Vec<u8>,
+    pub(crate) zero_bucket_count: u64,
+    pub(crate) positive_indexes: Vec<u8>,
+    pub(crate) positive_counts: Vec<u8>,
 }
 
-fn compress_buckets(buckets: impl Iterator<Item=(SketchHashKey, u64)>) -> CompressedBuckets {
+pub(crate) fn compress_buckets(buckets: impl Iterator<Item=(SketchHashKey, u64)>) -> CompressedBuckets {
     let mut negative_indexes = prefix_varint::I64Compressor::with(delta::i64_encoder());
     let mut negative_counts = prefix_varint::U64Compressor::with(delta::u64_encoder());
     let mut zero_bucket_count = 0;
@@ -361,7 +367,7 @@ fn compress_buckets(buckets: impl Iterator<Item=(SketchHashKey, u64)>) -> Compre
 }
 
 
-fn decompress_keys<'i>(
+pub(crate) fn decompress_keys<'i>(
     negative_indexes: &'i [u8],
     zero_bucket: bool,
     positive_indexes: &'i [u8]
@@ -379,7 +385,7 @@ fn decompress_keys<'i>(
     negatives.chain(zero).chain(positives)
 }
 
-fn decompress_counts<'b>(
+pub(crate) fn decompress_counts<'b>(
     negative_buckets: &'b [u8],
     zero_bucket: u64,
     positive_buckets: &'b [u8],
@@ -454,6 +460,102 @@ CREATE AGGREGATE rollup(
 );
 "#);
 
+// State for rolling(uddsketch) over a window. Postgres always removes the
+// earliest rows of the frame first, so alongside the merged sketch we keep
+// how many times each sketch in the frame had been compacted, in order.
+// Removing a sketch is only exact if the merged sketch ends up no more
+// compacted than the most compacted sketch still in the frame, as merging
+// them from scratch would otherwise give a finer sketch.
+pub struct UddSketchRollingState {
+    sketch: UddSketchInternal,
+    compactions: VecDeque<u32>,
+}
+
+#[pg_extern(schema = "toolkit_experimental")]
+pub fn uddsketch_rolling_trans(
+    state: Option<Internal<UddSketchRollingState>>,
+    value: Option<UddSketch>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<UddSketchRollingState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let value = match value {
+                None => return state,
+                Some(value) => value.to_uddsketch(),
+            };
+            let mut state = match state {
+                None => UddSketchRollingState {
+                    sketch: value.clone(),
+                    compactions: VecDeque::new(),
+                }.into(),
+                Some(mut state) => {
+                    state.sketch.merge_sketch(&value);
+                    state
+                },
+            };
+            state.compactions.push_back(value.times_compacted());
+            Some(state)
+        })
+    }
+}
+
+#[pg_extern(schema = "toolkit_experimental")]
+pub fn uddsketch_rolling_inv(
+    state: Option<Internal<UddSketchRollingState>>,
+    value: Option<UddSketch>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<UddSketchRollingState>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let value = match value {
+                None => return state,
+                Some(value) => value.to_uddsketch(),
+            };
+            // returning NULL makes postgres recompute the frame from scratch
+            let mut state = state?;
+            state.compactions.pop_front();
+            let finest_possible = state.compactions.iter().max()?;
+            if *finest_possible != state.sketch.times_compacted() {
+                return None;
+            }
+            match state.sketch.subtract_sketch(&value) {
+                true => Some(state),
+                false => None,
+            }
+        })
+    }
+}
+
+#[pg_extern(schema = "toolkit_experimental")]
+fn uddsketch_rolling_final(
+    state: Option<Internal<UddSketchRollingState>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<UddSketch<'static>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            state.map(|state| UddSketch::from_internal(&state.sketch))
+        })
+    }
+}
+
+extension_sql!(r#"
+CREATE AGGREGATE toolkit_experimental.rolling(
+    sketch uddsketch
+) (
+    sfunc = uddsketch_compound_trans,
+    stype = internal,
+    finalfunc = uddsketch_final,
+    combinefunc = uddsketch_combine,
+    serialfunc = uddsketch_serialize,
+    deserialfunc = uddsketch_deserialize,
+    msfunc = toolkit_experimental.uddsketch_rolling_trans,
+    minvfunc = toolkit_experimental.uddsketch_rolling_inv,
+    mstype = internal,
+    mfinalfunc = toolkit_experimental.uddsketch_rolling_final,
+    parallel = safe
+);
+"#);
+
 //---- Available PG operations on the sketch
 
 // Approximate the value at the given approx_percentile (0.0-1.0)
@@ -686,6 +788,45 @@ mod tests {
         });
     }
 
+    #[pg_test]
+    fn test_rolling() {
+        Spi::execute(|client| {
+            client.select("CREATE TABLE rolling_test (device INTEGER, value DOUBLE PRECISION)", None, None);
+            client.select("INSERT INTO rolling_test SELECT dev, dev - v FROM generate_series(1,10) dev, generate_series(0, 1.0, 0.01) v", None, None);
+
+            client.select("CREATE VIEW sketches AS \
+                SELECT device, uddsketch(200, 0.001, value) \
+                FROM rolling_test \
+                GROUP BY device", None, None);
+
+            // the frames of the moving aggregate should match rolling up each
+            // frame on its own
+            let rolling = client
+                .select("SELECT string_agg(approx_percentile(0.5, rolled)::text, ',' ORDER BY device) FROM ( \
+                    SELECT device, toolkit_experimental.rolling(uddsketch) OVER (ORDER BY device ROWS 2 PRECEDING) AS rolled \
+                    FROM sketches \
+                ) s", None, None)
+                .first()
+                .get_one::<String>();
+            let rollup = client
+                .select("SELECT string_agg(approx_percentile(0.5, rolled)::text, ',' ORDER BY device) FROM ( \
+                    SELECT s1.device, rollup(s2.uddsketch) AS rolled \
+                    FROM sketches s1 JOIN sketches s2 ON s2.device BETWEEN s1.device - 2 AND s1.device \
+                    GROUP BY s1.device \
+                ) s", None, None)
+                .first()
+                .get_one::<String>();
+            assert_eq!(rolling, rollup);
+
+            let count = client
+                .select("SELECT num_vals(toolkit_experimental.rolling(uddsketch) OVER (ORDER BY device ROWS 2 PRECEDING)) \
+                    FROM sketches ORDER BY device DESC LIMIT 1", None, None)
+                .first()
+                .get_one::<f64>();
+            assert_eq!(count, Some(303.0));
+        });
+    }
+
     #[pg_test]
     fn uddsketch_io_test() {
         Spi::execute(|client| {